@@ -1,7 +1,9 @@
-use actix_web::HttpMessage;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use mongodb::bson::doc;
+use actix_web::{http::Method, web::Data, HttpMessage};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
     collections::HashSet,
     future::{ready, Ready},
@@ -12,9 +14,18 @@ use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
 use futures_util::future::LocalBoxFuture;
 
 use crate::{
-    environment::JWT_SECRET,
+    constants::LAST_USED_PRECISION,
+    database::{
+        personal_token::{
+            self, SCOPE_PROFILE_READ, SCOPE_PROFILE_WRITE, SCOPE_SECURITY_EVENTS_READ,
+            SCOPE_SESSIONS_READ,
+        },
+        service_account,
+    },
+    environment::{JWT_PREVIOUS_SECRETS, JWT_SECRET},
     errors::{Error, Result},
-    utilities::get_time_millis,
+    store::{PersonalTokenStore, ServiceAccountStore, SessionStore},
+    utilities::{get_time_millis, get_time_secs, hash_api_key},
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -28,8 +39,62 @@ pub struct UserJwt {
 pub struct Authenticate {
     pub jwt: String,
     pub jwt_content: UserJwt,
+    // the id of the personal access token when signed in with one
+    pub session_id: String,
+}
+
+// The only routes personal access tokens are accepted on, with the scope each needs. Anything
+// else, such as escalating or managing tokens, needs a real session.
+const PERSONAL_TOKEN_ROUTES: &[(Method, &str, &str)] = &[
+    (Method::GET, "/api/user", SCOPE_PROFILE_READ),
+    (Method::GET, "/api/user/{id}", SCOPE_PROFILE_READ),
+    (
+        Method::GET,
+        "/api/user/by-username/{username}",
+        SCOPE_PROFILE_READ,
+    ),
+    (Method::PATCH, "/api/user/profile", SCOPE_PROFILE_WRITE),
+    (Method::GET, "/api/session", SCOPE_SESSIONS_READ),
+    (
+        Method::GET,
+        "/api/user/security-events",
+        SCOPE_SECURITY_EVENTS_READ,
+    ),
+];
+
+// A service account calling with its API key, never a user
+#[derive(Clone, Debug)]
+pub struct ServiceAuthenticate {
+    pub service_account_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl ServiceAuthenticate {
+    pub fn require(&self, scope: &str) -> Result<()> {
+        if self.scopes.iter().any(|s| s == scope) {
+            Ok(())
+        } else {
+            Err(Error::MissingPermission)
+        }
+    }
 }
 
+// Either a user or a service account with `scope` has to be signed in
+pub fn require_user_or_scope(
+    user: Result<Authenticate>,
+    service: Result<ServiceAuthenticate>,
+    scope: &str,
+) -> Result<()> {
+    match (user, service) {
+        (Ok(_), _) => Ok(()),
+        (_, Ok(service)) => service.require(scope),
+        (Err(e), Err(_)) => Err(e),
+    }
+}
+
+// Handlers can take both `Result<Authenticate>` and `Result<ServiceAuthenticate>`, at most one
+// of them is `Ok`
 pub struct JwtAuthentication;
 impl<S, B> Transform<S, ServiceRequest> for JwtAuthentication
 where
@@ -54,13 +119,43 @@ pub struct JwtMiddleware<S> {
     service: Rc<S>,
 }
 
-pub async fn validate_token(jwt: &String) -> Result<Authenticate> {
+// Sent as `kid`, so tokens signed before `rotate-keys` are checked against the right secret
+pub fn key_id(secret: &str) -> String {
+    hex::encode(&Sha256::digest(secret.as_bytes())[..8])
+}
+
+pub fn sign_token(claims: &UserJwt) -> String {
+    let header = Header {
+        kid: Some(key_id(&JWT_SECRET)),
+        ..Header::default()
+    };
+    encode(
+        &header,
+        claims,
+        &EncodingKey::from_secret(JWT_SECRET.as_ref()),
+    )
+    .expect("Unexpected error: failed to encode token")
+}
+
+// Tokens without a key id were signed before there were previous secrets
+fn signing_secret(jwt: &str) -> Result<&'static str> {
+    let Some(kid) = decode_header(jwt)?.kid else {
+        return Ok(JWT_SECRET.as_str());
+    };
+    std::iter::once(&*JWT_SECRET)
+        .chain(JWT_PREVIOUS_SECRETS.iter())
+        .find(|secret| key_id(secret) == kid)
+        .map(String::as_str)
+        .ok_or(Error::InvalidToken)
+}
+
+pub async fn validate_token(sessions: &dyn SessionStore, jwt: &String) -> Result<Authenticate> {
     let mut validation = Validation::new(Algorithm::HS256);
     validation.required_spec_claims = HashSet::new();
     validation.validate_exp = false;
     let token_data = decode::<UserJwt>(
         jwt,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
+        &DecodingKey::from_secret(signing_secret(jwt)?.as_ref()),
         &validation,
     )?;
 
@@ -68,28 +163,96 @@ pub async fn validate_token(jwt: &String) -> Result<Authenticate> {
     if millis > token_data.claims.expires_at {
         return Err(Error::InvalidToken);
     }
-    let collection = crate::database::session::get_collection();
-    let query = collection
-        .find_one(doc! {
-            "token": jwt
-        })
-        .await?;
-    if query.is_some() {
+    if let Some(session) = sessions.find_by_token(jwt).await? {
         return Ok(Authenticate {
             jwt: jwt.to_string(),
             jwt_content: token_data.claims,
+            session_id: session.id,
         });
     }
     Err(Error::InvalidToken)
 }
 
-pub async fn get_token(req: &ServiceRequest) -> Result<Authenticate> {
+fn get_bearer(req: &ServiceRequest) -> Result<String> {
     let authorization = req
         .headers()
         .get("Authorization")
         .ok_or(Error::MissingToken)?;
-    let jwt = &authorization.to_str().map_err(|_| Error::InvalidToken)?[7..];
-    validate_token(&jwt.to_string()).await
+    let token = authorization
+        .to_str()
+        .ok()
+        .and_then(|authorization| authorization.get(7..))
+        .ok_or(Error::InvalidToken)?;
+    Ok(token.to_string())
+}
+
+pub async fn get_token(req: &ServiceRequest) -> Result<Authenticate> {
+    let jwt = get_bearer(req)?;
+    let sessions = req
+        .app_data::<Data<dyn SessionStore>>()
+        .expect("Unexpected error: session store not configured");
+    validate_token(sessions.get_ref(), &jwt).await
+}
+
+// Finds the unexpired token and records that it was used
+pub async fn get_personal_token(req: &ServiceRequest, token: &str) -> Result<Authenticate> {
+    let tokens = req
+        .app_data::<Data<dyn PersonalTokenStore>>()
+        .expect("Unexpected error: personal token store not configured");
+    let now = get_time_secs();
+    let personal_token = tokens
+        .find_by_token_hash(&hash_api_key(token), now)
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    let pattern = req.match_pattern();
+    let allowed = PERSONAL_TOKEN_ROUTES.iter().any(|(method, route, scope)| {
+        method == req.method()
+            && pattern.as_deref() == Some(*route)
+            && personal_token.scopes.iter().any(|s| s == scope)
+    });
+    if !allowed {
+        return Err(Error::MissingPermission);
+    }
+    // only requests the token was allowed to make count as a use
+    if personal_token
+        .last_used_at
+        .is_none_or(|used| now.saturating_sub(used) >= LAST_USED_PRECISION)
+    {
+        tokens.set_last_used(&personal_token.id, now).await?;
+    }
+    Ok(Authenticate {
+        jwt: token.to_string(),
+        jwt_content: UserJwt {
+            id: personal_token.user_id,
+            issued_at: personal_token.created_at as u128 * 1000,
+            expires_at: personal_token.expires_at as u128 * 1000,
+        },
+        session_id: personal_token.id,
+    })
+}
+
+// Finds the account the key belongs to and records that it was used
+pub async fn get_service(req: &ServiceRequest, key: &str) -> Result<ServiceAuthenticate> {
+    let accounts = req
+        .app_data::<Data<dyn ServiceAccountStore>>()
+        .expect("Unexpected error: service account store not configured");
+    let account = accounts
+        .find_by_key_hash(&hash_api_key(key))
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    // written at most once a minute per account rather than on every request
+    let now = get_time_secs();
+    if account
+        .last_used_at
+        .is_none_or(|used| now.saturating_sub(used) >= LAST_USED_PRECISION)
+    {
+        accounts.set_last_used(&account.id, now).await?;
+    }
+    Ok(ServiceAuthenticate {
+        service_account_id: account.id,
+        name: account.name,
+        scopes: account.scopes,
+    })
 }
 
 impl<S, B> Service<ServiceRequest> for JwtMiddleware<S>
@@ -113,8 +276,24 @@ where
     fn call(self: &JwtMiddleware<S>, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
         Box::pin(async move {
-            let token = get_token(&req).await;
+            let bearer = get_bearer(&req);
+            let (token, service) = match &bearer {
+                Ok(key) if key.starts_with(service_account::KEY_PREFIX) => {
+                    let service = get_service(&req, key).await;
+                    let token = match &service {
+                        Ok(_) => Err(Error::MissingPermission),
+                        Err(e) => Err(e.clone()),
+                    };
+                    (token, service)
+                }
+                Ok(token) if token.starts_with(personal_token::TOKEN_PREFIX) => (
+                    get_personal_token(&req, token).await,
+                    Err(Error::MissingToken),
+                ),
+                _ => (get_token(&req).await, Err(Error::MissingToken)),
+            };
             req.extensions_mut().insert(token);
+            req.extensions_mut().insert(service);
             svc.call(req).await
         })
     }
@@ -0,0 +1,79 @@
+pub mod noop;
+pub mod pow;
+pub mod siteverify;
+
+use actix_web::{web::Data, HttpRequest};
+use async_trait::async_trait;
+use log::info;
+use once_cell::sync::OnceCell;
+
+use crate::{
+    environment::{CAPTCHA_PROVIDER, CAPTCHA_SECRET, CAPTCHA_VERIFY_URL},
+    errors::{Error, Result},
+    store::ChallengeStore,
+};
+
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    // `challenges` remembers used tokens for verifiers that issue their own
+    async fn verify(
+        &self,
+        token: &str,
+        remote_ip: Option<&str>,
+        challenges: &dyn ChallengeStore,
+    ) -> Result<()>;
+
+    // whether clients have to send a token at all
+    fn is_required(&self) -> bool {
+        true
+    }
+}
+
+static VERIFIER: OnceCell<Box<dyn CaptchaVerifier>> = OnceCell::new();
+
+fn create_verifier() -> Box<dyn CaptchaVerifier> {
+    let provider = CAPTCHA_PROVIDER.as_str();
+    let secret = || CAPTCHA_SECRET.clone().expect("CAPTCHA_SECRET must be set");
+    let url = CAPTCHA_VERIFY_URL.clone();
+    match provider {
+        "hcaptcha" => Box::new(siteverify::SiteVerify::hcaptcha(secret(), url)),
+        "turnstile" => Box::new(siteverify::SiteVerify::turnstile(secret(), url)),
+        "recaptcha" => Box::new(siteverify::SiteVerify::recaptcha(secret(), url)),
+        "pow" => Box::new(pow::PowVerifier),
+        "test" => Box::new(noop::TestVerifier),
+        "none" => Box::new(noop::NoopVerifier),
+        other => panic!("Unknown CAPTCHA_PROVIDER: {}", other),
+    }
+}
+
+pub fn init() {
+    if get_verifier().is_required() {
+        info!("Verifying captchas with {}", CAPTCHA_PROVIDER.as_str());
+    } else {
+        info!("Captcha is disabled");
+    }
+}
+
+pub fn get_verifier() -> &'static dyn CaptchaVerifier {
+    VERIFIER.get_or_init(create_verifier).as_ref()
+}
+
+pub async fn validate(req: &HttpRequest, token: Option<String>) -> Result<()> {
+    let verifier = get_verifier();
+    let Some(token) = token.filter(|t| !t.is_empty()) else {
+        if verifier.is_required() {
+            return Err(Error::CaptchaRequired);
+        }
+        return Ok(());
+    };
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.to_string());
+    let challenges = req
+        .app_data::<Data<dyn ChallengeStore>>()
+        .expect("Unexpected error: challenge store not configured");
+    verifier
+        .verify(&token, ip.as_deref(), challenges.as_ref())
+        .await
+}
@@ -0,0 +1,35 @@
+use async_trait::async_trait;
+
+use crate::{
+    errors::{Error, Result},
+    store::ChallengeStore,
+};
+
+use super::CaptchaVerifier;
+
+// Accepts everything, for development and deployments without a captcha
+pub struct NoopVerifier;
+
+#[async_trait]
+impl CaptchaVerifier for NoopVerifier {
+    async fn verify(&self, _: &str, _: Option<&str>, _: &dyn ChallengeStore) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_required(&self) -> bool {
+        false
+    }
+}
+
+// Requires a token, but only accepts the literal "pass", for tests
+pub struct TestVerifier;
+
+#[async_trait]
+impl CaptchaVerifier for TestVerifier {
+    async fn verify(&self, token: &str, _: Option<&str>, _: &dyn ChallengeStore) -> Result<()> {
+        if token != "pass" {
+            return Err(Error::InvalidCaptcha);
+        }
+        Ok(())
+    }
+}
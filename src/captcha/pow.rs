@@ -0,0 +1,153 @@
+// Self-hosted proof of work, for deployments that can't reach a captcha provider
+//
+// A challenge is `<id>.<difficulty>.<expires_at>.<signature>`. Clients search for
+// a solution such that SHA-256 of `<challenge>.<solution>` starts with at least
+// `difficulty` zero bits, and submit `<challenge>.<solution>` as the captcha token.
+
+use std::time::Duration;
+
+use actix_extensible_rate_limit::backend::{memory::InMemoryBackend, Backend, SimpleInput};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+
+use crate::{
+    constants::{POW_CHALLENGE_TIMEOUT, POW_FREE_REQUESTS, POW_WINDOW},
+    environment::{CAPTCHA_PROVIDER, JWT_SECRET, POW_DIFFICULTY, POW_MAX_DIFFICULTY},
+    errors::{Error, Result},
+    store::ChallengeStore,
+    utilities::get_time_secs,
+};
+
+use super::CaptchaVerifier;
+
+// Challenges issued per ip, counted by the backend the rate limiters use, which also forgets
+// windows once they end
+pub struct IssuedChallenges(InMemoryBackend);
+
+impl Default for IssuedChallenges {
+    fn default() -> Self {
+        IssuedChallenges(InMemoryBackend::builder().build())
+    }
+}
+
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expires_at: u64,
+}
+
+pub fn is_enabled() -> bool {
+    *CAPTCHA_PROVIDER == "pow"
+}
+
+fn sign(payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(JWT_SECRET.as_bytes())
+        .expect("Unexpected error: HMAC accepts any key length");
+    mac.update(b"pow:");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn verify_signature(payload: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(JWT_SECRET.as_bytes())
+        .expect("Unexpected error: HMAC accepts any key length");
+    mac.update(b"pow:");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+// Adds a bit of difficulty for every doubling of requests beyond the free allowance
+async fn difficulty_for(issued: &IssuedChallenges, ip: Option<&str>) -> u32 {
+    let Some(ip) = ip else {
+        return *POW_MAX_DIFFICULTY;
+    };
+    // never denied, the count only raises the difficulty
+    let input = SimpleInput {
+        interval: Duration::from_secs(POW_WINDOW),
+        max_requests: u64::MAX,
+        key: ip.to_string(),
+    };
+    let count = issued
+        .0
+        .request(input)
+        .await
+        .map(|(_, output, _)| output.limit - output.remaining)
+        .unwrap_or(u64::MAX);
+    let extra = if count <= POW_FREE_REQUESTS {
+        0
+    } else {
+        64 - ((count - 1) / POW_FREE_REQUESTS).leading_zeros()
+    };
+    (*POW_DIFFICULTY + extra).min(*POW_MAX_DIFFICULTY)
+}
+
+pub async fn issue(issued: &IssuedChallenges, ip: Option<&str>) -> Challenge {
+    let difficulty = difficulty_for(issued, ip).await;
+    let expires_at = get_time_secs() + POW_CHALLENGE_TIMEOUT;
+    let payload = format!("{}.{}.{}", Ulid::new(), difficulty, expires_at);
+    let signature = sign(&payload);
+    Challenge {
+        challenge: format!("{}.{}", payload, signature),
+        difficulty,
+        expires_at,
+    }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+pub struct PowVerifier;
+
+#[async_trait]
+impl CaptchaVerifier for PowVerifier {
+    async fn verify(
+        &self,
+        token: &str,
+        _: Option<&str>,
+        challenges: &dyn ChallengeStore,
+    ) -> Result<()> {
+        let parts = token.split('.').collect::<Vec<_>>();
+        let [id, difficulty, expires_at, signature, solution] = parts[..] else {
+            return Err(Error::InvalidCaptcha);
+        };
+        if solution.is_empty() || solution.len() > 64 {
+            return Err(Error::InvalidCaptcha);
+        }
+        let payload = format!("{}.{}.{}", id, difficulty, expires_at);
+        if !verify_signature(&payload, signature) {
+            return Err(Error::InvalidCaptcha);
+        }
+        let (Ok(difficulty), Ok(expires_at)) =
+            (difficulty.parse::<u32>(), expires_at.parse::<u64>())
+        else {
+            return Err(Error::InvalidCaptcha);
+        };
+        if get_time_secs() > expires_at {
+            return Err(Error::InvalidCaptcha);
+        }
+        let hash = Sha256::digest(token.as_bytes());
+        if leading_zero_bits(&hash) < difficulty {
+            return Err(Error::InvalidCaptcha);
+        }
+        // checked last, so an invalid solution doesn't use up the challenge
+        if !challenges.use_once(id, expires_at).await? {
+            return Err(Error::InvalidCaptcha);
+        }
+        Ok(())
+    }
+}
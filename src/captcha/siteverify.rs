@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use log::error;
+use serde::Deserialize;
+
+use crate::{
+    environment::CAPTCHA_MIN_SCORE,
+    errors::{Error, Result},
+    store::ChallengeStore,
+};
+
+use super::CaptchaVerifier;
+
+// hCaptcha, Turnstile and reCAPTCHA share the same siteverify protocol
+pub struct SiteVerify {
+    url: String,
+    secret: String,
+    // reCAPTCHA v3 scores each token instead of passing or failing it
+    check_score: bool,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    score: Option<f64>,
+    #[serde(rename = "error-codes")]
+    error_codes: Option<Vec<String>>,
+}
+
+impl SiteVerify {
+    fn new(url: String, secret: String, check_score: bool) -> Self {
+        Self {
+            url,
+            secret,
+            check_score,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn hcaptcha(secret: String, url: Option<String>) -> Self {
+        Self::new(
+            url.unwrap_or("https://hcaptcha.com/siteverify".to_string()),
+            secret,
+            false,
+        )
+    }
+
+    pub fn turnstile(secret: String, url: Option<String>) -> Self {
+        Self::new(
+            url.unwrap_or("https://challenges.cloudflare.com/turnstile/v0/siteverify".to_string()),
+            secret,
+            false,
+        )
+    }
+
+    pub fn recaptcha(secret: String, url: Option<String>) -> Self {
+        Self::new(
+            url.unwrap_or("https://www.google.com/recaptcha/api/siteverify".to_string()),
+            secret,
+            true,
+        )
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for SiteVerify {
+    async fn verify(
+        &self,
+        token: &str,
+        remote_ip: Option<&str>,
+        _: &dyn ChallengeStore,
+    ) -> Result<()> {
+        let mut params = vec![("secret", self.secret.as_str()), ("response", token)];
+        if let Some(ip) = remote_ip {
+            params.push(("remoteip", ip));
+        }
+        let result = self
+            .client
+            .post(&self.url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to reach captcha provider: {}", e);
+                Error::InternalCaptchaError
+            })?;
+        if result.status() != reqwest::StatusCode::OK {
+            error!("Captcha provider returned {}", result.status());
+            return Err(Error::InternalCaptchaError);
+        }
+        let text = result.text().await.map_err(|e| {
+            error!("Failed to read captcha response: {}", e);
+            Error::InternalCaptchaError
+        })?;
+        let response: SiteVerifyResponse = serde_json::from_str(&text).map_err(|e| {
+            error!("Failed to parse captcha response: {}", e);
+            Error::InternalCaptchaError
+        })?;
+        if !response.success {
+            if let Some(codes) = response.error_codes {
+                // bad secrets are our fault, not the user's
+                if codes
+                    .iter()
+                    .any(|c| c == "invalid-input-secret" || c == "missing-input-secret")
+                {
+                    error!("Captcha provider rejected the secret: {:?}", codes);
+                    return Err(Error::InternalCaptchaError);
+                }
+            }
+            return Err(Error::InvalidCaptcha);
+        }
+        if self.check_score && response.score.unwrap_or(0.0) < *CAPTCHA_MIN_SCORE {
+            return Err(Error::InvalidCaptcha);
+        }
+        Ok(())
+    }
+}
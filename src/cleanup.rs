@@ -1,6 +1,12 @@
+use serde::Serialize;
+
 use crate::{
-    constants::CONTINUE_TIMEOUT,
-    routes::{forgot, login, mfa, register, update_password},
+    constants::{CONTINUE_TIMEOUT, RECOVERY_TIMEOUT, VERIFY_TIMEOUT},
+    environment::SECURITY_EVENT_RETENTION_DAYS,
+    errors::Result,
+    mail::outbox,
+    routes::{forgot, login, mfa, register, update_password, verify_email},
+    store::Stores,
     utilities::get_time_secs,
 };
 
@@ -16,6 +22,7 @@ pub fn run() {
             login::PENDING_MFAS.remove(pending.key());
         }
     }
+    login::PENDING_UPGRADES.retain(|_, pending| now - pending.time <= CONTINUE_TIMEOUT);
     for pending in register::PENDING_REGISTERS1.iter() {
         if now - pending.value().time > CONTINUE_TIMEOUT {
             register::PENDING_REGISTERS1.remove(pending.key());
@@ -41,9 +48,49 @@ pub fn run() {
             forgot::PENDING_FORGOTS2.remove(pending.key());
         }
     }
+    login::FAILED_LOGINS.retain(|_, failed| now.saturating_sub(failed.time) <= CONTINUE_TIMEOUT);
+    verify_email::PENDING_VERIFICATIONS
+        .retain(|_, pending| now.saturating_sub(pending.time) <= VERIFY_TIMEOUT);
     for pending in update_password::PENDING_UPDATES.iter() {
         if now - pending.value().time > CONTINUE_TIMEOUT {
             update_password::PENDING_UPDATES.remove(pending.key());
         }
     }
 }
+
+// Rows deleted by `purge`, reported by the `purge` command
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeReport {
+    pub security_events: u64,
+    pub recovery_tokens: u64,
+    pub reset_requests: u64,
+    pub emails: u64,
+    pub personal_tokens: u64,
+}
+
+pub async fn purge(stores: &Stores) -> Result<PurgeReport> {
+    // every purge runs even if an earlier one failed, errors are retried on the next run
+    let now = get_time_secs();
+    let security_events = stores
+        .security_events
+        .purge_before(now.saturating_sub(*SECURITY_EVENT_RETENTION_DAYS * 86400))
+        .await;
+    let recovery_tokens = stores
+        .recovery_tokens
+        .purge_before(now.saturating_sub(RECOVERY_TIMEOUT))
+        .await;
+    let reset_requests = stores
+        .reset_requests
+        .purge_before(now.saturating_sub(RECOVERY_TIMEOUT))
+        .await;
+    let emails = outbox::purge_finished(stores.outbox.as_ref()).await;
+    let personal_tokens = stores.personal_tokens.purge_expired(now).await;
+    Ok(PurgeReport {
+        security_events: security_events?,
+        recovery_tokens: recovery_tokens?,
+        reset_requests: reset_requests?,
+        emails: emails?,
+        personal_tokens: personal_tokens?,
+    })
+}
@@ -0,0 +1,508 @@
+// Operator commands, run as `account-services <command>`. Every command prints one JSON value on
+// stdout so it can be scripted, logs keep going to stderr.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    authenticate::key_id,
+    cleanup,
+    database::{
+        indexes::{self, Conflict},
+        invite::Invite,
+        security_event::{self, SecurityEventKind},
+        settings::Settings,
+        user::User,
+    },
+    environment::{JWT_PREVIOUS_SECRETS, JWT_SECRET},
+    errors::Error,
+    migrations, opaque,
+    store::{self, Stores},
+    username,
+    utilities::{generate_invite_code, get_time_secs, random_number},
+};
+
+pub const USAGE: &str = "Usage: account-services <command>
+
+  migrate [--dry-run]
+  server-setup export <file> | import <file> [--replace] | rotate
+  rotate-keys [--write <file>] [--write-previous <file>]
+  users list [--after <id>] [--limit <n>]
+  users conflicts
+  users invite [--max-uses <n>] [--expires-in-hours <h>]
+  users promote | demote | reset-mfa | revoke-sessions <id, email or username>
+  purge";
+
+type Result<T> = std::result::Result<T, String>;
+
+// `Error` only has a Debug representation
+fn failed(e: Error) -> String {
+    format!("{:?}", e)
+}
+
+fn flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+fn option(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+fn parsed<T: std::str::FromStr>(args: &[String], name: &str) -> Result<Option<T>> {
+    option(args, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{} expects a number, got {}", name, value))
+        })
+        .transpose()
+}
+
+fn json(value: impl Serialize) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+#[derive(Serialize)]
+struct Failure {
+    error: String,
+}
+
+// What a failed command prints instead of its result
+pub fn failure(error: String) -> Value {
+    json(Failure { error }).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSummary {
+    id: String,
+    email: String,
+    username: String,
+    platform_administrator: bool,
+    mfa_enabled: bool,
+    email_verified: bool,
+    pending_approval: bool,
+}
+
+impl From<User> for UserSummary {
+    fn from(user: User) -> Self {
+        UserSummary {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            platform_administrator: user.platform_administrator,
+            mfa_enabled: user.mfa_enabled,
+            email_verified: user.email_verified,
+            pending_approval: user.pending_approval,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MigrateReport {
+    from: u32,
+    to: u32,
+    dry_run: bool,
+    // accounts that keep the unique indexes from being built
+    conflicts: Vec<Conflict>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerSetupReport {
+    server_setups: usize,
+    current_server_setup: u32,
+    file: Option<String>,
+}
+
+impl ServerSetupReport {
+    fn new(settings: &Settings, file: Option<&String>) -> Self {
+        ServerSetupReport {
+            server_setups: settings.server_setups.len(),
+            current_server_setup: settings.current_server_setup,
+            file: file.cloned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RotatedKeys {
+    key_id: String,
+    // the secrets are only printed when they weren't written to a file
+    jwt_secret: Option<String>,
+    file: Option<String>,
+    jwt_previous_secrets: Option<String>,
+    previous_file: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InviteReport {
+    code: String,
+    max_uses: Option<u32>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserUpdate {
+    user: UserSummary,
+    changed: bool,
+}
+
+pub enum ServerSetupCommand {
+    Export(String),
+    Import { path: String, replace: bool },
+    Rotate,
+}
+
+pub enum UserCommand {
+    Promote,
+    Demote,
+    ResetMfa,
+    RevokeSessions,
+}
+
+// Commands are parsed before anything connects, so usage errors don't need a database
+pub enum Command {
+    Migrate {
+        dry_run: bool,
+    },
+    ServerSetup(ServerSetupCommand),
+    RotateKeys {
+        file: Option<String>,
+        previous_file: Option<String>,
+    },
+    ListUsers {
+        after: Option<String>,
+        limit: usize,
+    },
+    Conflicts,
+    Invite {
+        max_uses: Option<u32>,
+        expires_in_hours: Option<u64>,
+    },
+    User {
+        command: UserCommand,
+        query: String,
+    },
+    Purge,
+}
+
+impl Command {
+    pub fn uses_database(&self) -> bool {
+        !matches!(self, Command::RotateKeys { .. })
+    }
+
+    // these read MongoDB directly rather than going through the stores
+    fn needs_mongo(&self) -> bool {
+        matches!(self, Command::Migrate { .. } | Command::Conflicts)
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Command> {
+    let rest = args.get(1..).unwrap_or_default();
+    let command = match args.first().map(String::as_str) {
+        Some("migrate") => Command::Migrate {
+            dry_run: flag(rest, "--dry-run"),
+        },
+        Some("server-setup") => Command::ServerSetup(parse_server_setup(rest)?),
+        Some("rotate-keys") => Command::RotateKeys {
+            file: option(rest, "--write"),
+            previous_file: option(rest, "--write-previous"),
+        },
+        Some("users") => parse_users(rest)?,
+        Some("purge") => Command::Purge,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(command)
+}
+
+fn parse_server_setup(args: &[String]) -> Result<ServerSetupCommand> {
+    match (args.first().map(String::as_str), args.get(1)) {
+        (Some("export"), Some(path)) => Ok(ServerSetupCommand::Export(path.clone())),
+        (Some("import"), Some(path)) => Ok(ServerSetupCommand::Import {
+            path: path.clone(),
+            replace: flag(args, "--replace"),
+        }),
+        (Some("rotate"), _) => Ok(ServerSetupCommand::Rotate),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn parse_users(args: &[String]) -> Result<Command> {
+    let command = match args.first().map(String::as_str) {
+        Some("list") => {
+            return Ok(Command::ListUsers {
+                after: option(args, "--after"),
+                limit: parsed(args, "--limit")?.unwrap_or(100),
+            })
+        }
+        Some("conflicts") => return Ok(Command::Conflicts),
+        Some("invite") => {
+            return Ok(Command::Invite {
+                max_uses: parsed(args, "--max-uses")?,
+                expires_in_hours: parsed(args, "--expires-in-hours")?,
+            })
+        }
+        Some("promote") => UserCommand::Promote,
+        Some("demote") => UserCommand::Demote,
+        Some("reset-mfa") => UserCommand::ResetMfa,
+        Some("revoke-sessions") => UserCommand::RevokeSessions,
+        _ => return Err(USAGE.to_string()),
+    };
+    let query = args.get(1).ok_or(USAGE.to_string())?.clone();
+    Ok(Command::User { command, query })
+}
+
+pub async fn run(stores: &Stores, args: &[String]) -> Result<Value> {
+    execute(stores, parse(args)?).await
+}
+
+pub async fn execute(stores: &Stores, command: Command) -> Result<Value> {
+    if command.needs_mongo() && !store::uses_mongo() {
+        return Err("This command only applies to the mongodb storage backend".to_string());
+    }
+    match command {
+        Command::Migrate { dry_run } => migrate(dry_run).await,
+        Command::ServerSetup(command) => server_setup(stores, command).await,
+        Command::RotateKeys {
+            file,
+            previous_file,
+        } => rotate_keys(file, previous_file),
+        Command::ListUsers { after, limit } => {
+            let users = stores
+                .users
+                .list(after.as_deref(), limit)
+                .await
+                .map_err(failed)?;
+            json(users.into_iter().map(UserSummary::from).collect::<Vec<_>>())
+        }
+        Command::Conflicts => json(indexes::find_conflicts().await.map_err(failed)?),
+        Command::Invite {
+            max_uses,
+            expires_in_hours,
+        } => invite(stores, max_uses, expires_in_hours).await,
+        Command::User { command, query } => update_user(stores, command, &query).await,
+        Command::Purge => json(cleanup::purge(stores).await.map_err(failed)?),
+    }
+}
+
+async fn migrate(dry_run: bool) -> Result<Value> {
+    let from = migrations::current_version().await.map_err(failed)?;
+    migrations::run(dry_run).await.map_err(failed)?;
+    let conflicts = indexes::find_conflicts().await.map_err(failed)?;
+    if !dry_run {
+        if !conflicts.is_empty() {
+            return Err(conflicts_message(&conflicts));
+        }
+        indexes::ensure().await;
+    }
+    json(MigrateReport {
+        from,
+        to: migrations::current_version().await.map_err(failed)?,
+        dry_run,
+        conflicts,
+    })
+}
+
+fn conflicts_message(conflicts: &[Conflict]) -> String {
+    let listed = conflicts
+        .iter()
+        .map(|c| format!("{} {} ({})", c.field, c.value, c.user_ids.join(", ")))
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!(
+        "Several accounts share an email or username apart from case, change or delete all but \
+         one of each before migrating: {}",
+        listed
+    )
+}
+
+// Backups keep the setups encrypted, so they only restore with the same OPAQUE_MASTER_KEY
+async fn server_setup(stores: &Stores, command: ServerSetupCommand) -> Result<Value> {
+    let settings = &stores.settings;
+    match command {
+        ServerSetupCommand::Export(path) => {
+            let current = settings.get().await.map_err(failed)?;
+            let backup = serde_json::to_string_pretty(&current).map_err(|e| e.to_string())?;
+            std::fs::write(&path, backup)
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            json(ServerSetupReport::new(&current, Some(&path)))
+        }
+        ServerSetupCommand::Import { path, replace } => {
+            let backup = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let imported: Settings = serde_json::from_str(&backup)
+                .map_err(|e| format!("Not a server setup backup: {}", e))?;
+            opaque::verify_settings(&imported)
+                .map_err(|_| "The backup can't be used with this OPAQUE_MASTER_KEY".to_string())?;
+            let existing = settings.find().await.map_err(failed)?;
+            if existing.is_some() && !replace {
+                return Err(
+                    "A server setup already exists, pass --replace to overwrite it".to_string(),
+                );
+            }
+            let report = ServerSetupReport::new(&imported, Some(&path));
+            settings.set(imported).await.map_err(failed)?;
+            json(report)
+        }
+        // new passwords use the new setup, existing users move to it on their next login
+        ServerSetupCommand::Rotate => {
+            let mut current = settings.get().await.map_err(failed)?;
+            opaque::verify_settings(&current).map_err(|_| {
+                "The server setup can't be used with this OPAQUE_MASTER_KEY".to_string()
+            })?;
+            opaque::rotate_server_setup(&mut current);
+            let report = ServerSetupReport::new(&current, None);
+            settings.set(current).await.map_err(failed)?;
+            json(report)
+        }
+    }
+}
+
+// Session tokens are signed with JWT_SECRET, which lives in the configuration rather than the
+// database. This generates its replacement and moves the current secret to JWT_PREVIOUS_SECRETS,
+// whose tokens stay valid until they expire. Previous secrets can be dropped once
+// PERSISTENT_SESSION_LIFETIME has passed since the rotation.
+fn rotate_keys(file: Option<String>, previous_file: Option<String>) -> Result<Value> {
+    let secret = hex::encode(random_number(48));
+    let previous = std::iter::once(&*JWT_SECRET)
+        .chain(JWT_PREVIOUS_SECRETS.iter())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(",");
+    let write = |path: &String, value: &str| {
+        std::fs::write(path, value).map_err(|e| format!("Failed to write {}: {}", path, e))
+    };
+    if let Some(path) = &file {
+        write(path, &secret)?;
+    }
+    if let Some(path) = &previous_file {
+        write(path, &previous)?;
+    }
+    json(RotatedKeys {
+        key_id: key_id(&secret),
+        jwt_secret: file.is_none().then_some(secret),
+        file,
+        jwt_previous_secrets: previous_file.is_none().then_some(previous),
+        previous_file,
+    })
+}
+
+// Looks users up by email, id or username, in that order
+async fn find_user(stores: &Stores, query: &str) -> Result<User> {
+    let user = if query.contains('@') {
+        stores.users.find_by_email(query).await
+    } else {
+        match stores.users.find(query).await {
+            Ok(None) => {
+                stores
+                    .users
+                    .find_by_username(query, &username::normalize_key(query))
+                    .await
+            }
+            result => result,
+        }
+    };
+    user.map_err(failed)?
+        .ok_or_else(|| format!("No user matches {}", query))
+}
+
+async fn invite(
+    stores: &Stores,
+    max_uses: Option<u32>,
+    expires_in_hours: Option<u64>,
+) -> Result<Value> {
+    let now = get_time_secs();
+    let expires_at = expires_in_hours.map(|h| now + h * 3600);
+    let code = generate_invite_code();
+    stores
+        .invites
+        .create(Invite {
+            code: code.clone(),
+            created_by: "cli".to_string(),
+            max_uses,
+            uses: 0,
+            expires_at,
+            created_at: now,
+        })
+        .await
+        .map_err(failed)?;
+    json(InviteReport {
+        code,
+        max_uses,
+        expires_at,
+    })
+}
+
+async fn update_user(stores: &Stores, command: UserCommand, query: &str) -> Result<Value> {
+    let mut user = find_user(stores, query).await?;
+    let events = stores.security_events.as_ref();
+    let changed = match command {
+        UserCommand::Promote | UserCommand::Demote => {
+            let administrator = matches!(command, UserCommand::Promote);
+            let changed = user.platform_administrator != administrator;
+            stores
+                .users
+                .set_administrator(&user.id, administrator)
+                .await
+                .map_err(failed)?;
+            user.platform_administrator = administrator;
+            changed
+        }
+        UserCommand::ResetMfa => {
+            let changed = user.mfa_enabled;
+            stores.users.disable_mfa(&user.id).await.map_err(failed)?;
+            user.mfa_enabled = false;
+            if changed {
+                security_event::record_for_operator(
+                    events,
+                    SecurityEventKind::AdminMfaReset,
+                    &user.id,
+                )
+                .await;
+            }
+            changed
+        }
+        UserCommand::RevokeSessions => {
+            let had_sessions = !stores
+                .sessions
+                .list(&user.id)
+                .await
+                .map_err(failed)?
+                .is_empty();
+            let revoked_tokens = stores
+                .users
+                .revoke_sessions(&user.id, None)
+                .await
+                .map_err(failed)?;
+            if had_sessions {
+                security_event::record_for_operator(
+                    events,
+                    SecurityEventKind::AdminSessionsRevoked,
+                    &user.id,
+                )
+                .await;
+            }
+            if revoked_tokens > 0 {
+                security_event::record_for_operator(
+                    events,
+                    SecurityEventKind::PersonalTokensRevoked,
+                    &user.id,
+                )
+                .await;
+            }
+            had_sessions || revoked_tokens > 0
+        }
+    };
+    json(UserUpdate {
+        user: user.into(),
+        changed,
+    })
+}
@@ -0,0 +1,712 @@
+// Typed server configuration. Values are read from the TOML file named by CONFIG_FILE
+// (`config.toml` if it exists), overridden by environment variables. Every variable can also be
+// read from the file named by the same variable with a `_FILE` suffix, as Docker mounts secrets.
+// See `config.example.toml` for every option and its default.
+
+use std::{env, fs, path::Path, str::FromStr, time::Duration};
+
+use once_cell::sync::OnceCell;
+use toml::{Table, Value};
+use tracing_subscriber::EnvFilter;
+use webauthn_rs::prelude::Url;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+pub struct Config {
+    pub server: ServerConfig,
+    pub logging: LoggingConfig,
+    pub database: DatabaseConfig,
+    pub security: SecurityConfig,
+    pub opaque: OpaqueConfig,
+    pub sessions: SessionConfig,
+    pub rate_limits: RateLimitConfig,
+    pub captcha: CaptchaConfig,
+    pub mail: MailConfig,
+    // email is disabled without an SMTP server, unless another transport is chosen
+    pub smtp: Option<SmtpConfig>,
+    pub dkim: Option<DkimConfig>,
+    pub registration: RegistrationConfig,
+    pub usernames: UsernameConfig,
+    pub accounts: AccountConfig,
+    pub introspection: IntrospectionConfig,
+}
+
+pub struct ServerConfig {
+    pub host: String,
+    pub cors_origins: Vec<String>,
+    pub public_root: String,
+    pub service_name: String,
+    pub rp_id: String,
+}
+
+pub struct LoggingConfig {
+    // `text` or `json`
+    pub format: String,
+    // `RUST_LOG` style directives, such as `info,account_services=debug`
+    pub level: String,
+}
+
+pub struct DatabaseConfig {
+    pub uri: String,
+    pub name: String,
+    pub cdn_name: String,
+    pub storage_backend: String,
+    pub migrate_on_startup: bool,
+}
+
+pub struct SecurityConfig {
+    pub jwt_secret: String,
+    // secrets replaced by `rotate-keys`, whose sessions stay valid until they expire
+    pub jwt_previous_secrets: Vec<String>,
+    pub opaque_master_key: Vec<u8>,
+    // bearer token Prometheus scrapes /metrics with, which is off without one
+    pub metrics_token: Option<String>,
+}
+
+pub struct OpaqueConfig {
+    pub argon2_memory: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+}
+
+// in seconds
+pub struct SessionConfig {
+    pub lifetime: u64,
+    pub persistent_lifetime: u64,
+    pub escalation_lifetime: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct RateLimit {
+    pub requests: u64,
+    // in seconds
+    pub window: u64,
+}
+
+impl RateLimit {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.window)
+    }
+}
+
+// Only successful requests count, except for `api` which covers every request
+pub struct RateLimitConfig {
+    pub api: RateLimit,
+    pub login: RateLimit,
+    pub registration: RateLimit,
+    pub forgot: RateLimit,
+    pub verify_email: RateLimit,
+    pub validate: RateLimit,
+}
+
+pub struct CaptchaConfig {
+    pub provider: String,
+    pub secret: Option<String>,
+    pub verify_url: Option<String>,
+    pub min_score: f64,
+    pub pow_difficulty: u32,
+    pub pow_max_difficulty: u32,
+    pub on_forgot: bool,
+    pub login_threshold: u64,
+}
+
+pub struct MailConfig {
+    pub transport: Option<String>,
+    pub file_directory: String,
+    pub templates_dir: Option<String>,
+}
+
+pub struct SmtpConfig {
+    pub server: String,
+    pub from: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: Option<String>,
+}
+
+pub struct DkimConfig {
+    pub selector: String,
+    pub domain: String,
+    pub private_key_file: String,
+    pub algorithm: Option<String>,
+}
+
+pub struct RegistrationConfig {
+    pub mode: Option<String>,
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+    pub disposable_domains_file: Option<String>,
+    pub approval: bool,
+    pub user_invites: bool,
+}
+
+pub struct UsernameConfig {
+    pub reserved: Vec<String>,
+    pub change_cooldown_days: u64,
+    pub hold_days: u64,
+}
+
+pub struct AccountConfig {
+    // services, as passed to /validate, that reject users with an unverified email
+    pub unverified_blocked_services: Vec<String>,
+    pub reset_delay_hours: u64,
+    pub security_event_retention_days: u64,
+}
+
+// Resource servers allowed to call `POST /introspect`
+pub struct IntrospectionConfig {
+    pub clients: Vec<IntrospectionClient>,
+    // upper bound for how long an active result may be cached
+    pub cache_seconds: u64,
+}
+
+pub struct IntrospectionClient {
+    pub id: String,
+    pub secret: String,
+}
+
+// Collects every problem instead of stopping at the first one
+struct Loader<'a> {
+    file: Table,
+    env: &'a dyn Fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn describe(name: &str, path: &str) -> String {
+        format!("{} (`{}`)", name, path)
+    }
+
+    fn lookup(&mut self, name: &str, path: &str) -> Option<Value> {
+        if let Some(value) = (self.env)(name) {
+            return Some(Value::String(value));
+        }
+        if let Some(file) = (self.env)(&format!("{}_FILE", name)) {
+            return match fs::read_to_string(&file) {
+                Ok(value) => Some(Value::String(value.trim().to_string())),
+                Err(e) => {
+                    self.errors
+                        .push(format!("{}_FILE: failed to read {}: {}", name, file, e));
+                    None
+                }
+            };
+        }
+        let mut table = Some(&self.file);
+        let mut value = None;
+        for key in path.split('.') {
+            value = table.and_then(|t| t.get(key));
+            table = value.and_then(Value::as_table);
+        }
+        value.cloned()
+    }
+
+    fn string(&mut self, name: &str, path: &str) -> Option<String> {
+        match self.lookup(name, path)? {
+            Value::String(value) if value.is_empty() => None,
+            Value::String(value) => Some(value),
+            _ => {
+                let key = Self::describe(name, path);
+                self.errors.push(format!("{} must be a string", key));
+                None
+            }
+        }
+    }
+
+    fn required(&mut self, name: &str, path: &str) -> String {
+        let errors = self.errors.len();
+        self.string(name, path).unwrap_or_else(|| {
+            if self.errors.len() == errors {
+                let key = Self::describe(name, path);
+                self.errors.push(format!("{} must be set", key));
+            }
+            String::new()
+        })
+    }
+
+    fn required_if(&mut self, required: bool, name: &str, path: &str) -> String {
+        match required {
+            true => self.required(name, path),
+            false => self.string(name, path).unwrap_or_default(),
+        }
+    }
+
+    fn choice(&mut self, name: &str, path: &str, choices: &[&str]) -> Option<String> {
+        let value = self.string(name, path)?;
+        if !choices.contains(&value.as_str()) {
+            let key = Self::describe(name, path);
+            self.errors.push(format!(
+                "{} must be one of {}, not {}",
+                key,
+                choices.join(", "),
+                value
+            ));
+        }
+        Some(value)
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str, path: &str) -> Option<T> {
+        let value = match self.lookup(name, path)? {
+            Value::String(value) => value,
+            value => value.to_string(),
+        };
+        let parsed = value.trim().parse().ok();
+        if parsed.is_none() {
+            let key = Self::describe(name, path);
+            self.errors
+                .push(format!("{} has an invalid value: {}", key, value));
+        }
+        parsed
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, path: &str, default: T) -> T {
+        self.optional(name, path).unwrap_or(default)
+    }
+
+    // a comma-separated string, or an array of strings in the file
+    fn list(&mut self, name: &str, path: &str) -> Vec<String> {
+        let items: Vec<String> = match self.lookup(name, path) {
+            None => return Vec::new(),
+            Some(Value::String(value)) => value.split(',').map(str::to_string).collect(),
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect(),
+            Some(_) => {
+                let key = Self::describe(name, path);
+                self.errors.push(format!("{} must be a list", key));
+                return Vec::new();
+            }
+        };
+        items
+            .iter()
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    fn rate_limit(&mut self, name: &str, path: &str, requests: u64, window: u64) -> RateLimit {
+        let limit = RateLimit {
+            requests: self.parse(
+                &format!("RATE_LIMIT_{}_REQUESTS", name),
+                &format!("rate_limits.{}.requests", path),
+                requests,
+            ),
+            window: self.parse(
+                &format!("RATE_LIMIT_{}_WINDOW", name),
+                &format!("rate_limits.{}.window", path),
+                window,
+            ),
+        };
+        if limit.requests == 0 || limit.window == 0 {
+            self.errors.push(format!(
+                "rate_limits.{} needs at least one request per window",
+                path
+            ));
+        }
+        limit
+    }
+}
+
+impl Config {
+    // `env` looks up environment variables, so sources can be swapped out in tests
+    pub fn parse(file: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<Config, Vec<String>> {
+        let file = file
+            .parse::<Table>()
+            .map_err(|e| vec![format!("Invalid config file: {}", e)])?;
+        let mut l = Loader {
+            file,
+            env,
+            errors: Vec::new(),
+        };
+
+        let server = ServerConfig {
+            host: l.required("HOST", "server.host"),
+            cors_origins: l.list("CORS_ORIGINS", "server.cors_origins"),
+            public_root: l.required("PUBLIC_ROOT", "server.public_root"),
+            service_name: l.required("SERVICE_NAME", "server.service_name"),
+            rp_id: l.required("RP_ID", "server.rp_id"),
+        };
+        let logging = LoggingConfig {
+            format: l
+                .choice("LOG_FORMAT", "logging.format", &["text", "json"])
+                .unwrap_or("text".to_string()),
+            level: l
+                .string("LOG_LEVEL", "logging.level")
+                .unwrap_or("info".to_string()),
+        };
+        let storage_backend = l
+            .choice(
+                "STORAGE_BACKEND",
+                "database.storage_backend",
+                &["mongodb", "memory"],
+            )
+            .unwrap_or("mongodb".to_string());
+        // the memory backend never connects, so it needs no database settings
+        let mongodb = storage_backend == "mongodb";
+        let database = DatabaseConfig {
+            uri: l.required_if(mongodb, "MONGODB_URI", "database.uri"),
+            name: l.required_if(mongodb, "MONGODB_DATABASE", "database.name"),
+            cdn_name: l.required_if(mongodb, "CDN_MONGODB_DATABASE", "database.cdn_name"),
+            storage_backend,
+            migrate_on_startup: l.parse("MIGRATE_ON_STARTUP", "database.migrate_on_startup", true),
+        };
+        let security = SecurityConfig {
+            jwt_secret: l.required("JWT_SECRET", "security.jwt_secret"),
+            jwt_previous_secrets: l.list("JWT_PREVIOUS_SECRETS", "security.jwt_previous_secrets"),
+            opaque_master_key: {
+                let key = l.required("OPAQUE_MASTER_KEY", "security.opaque_master_key");
+                hex::decode(&key).unwrap_or_default()
+            },
+            metrics_token: l.string("METRICS_TOKEN", "security.metrics_token"),
+        };
+        let opaque = OpaqueConfig {
+            argon2_memory: l.parse("OPAQUE_ARGON2_MEMORY", "opaque.argon2_memory", 19456),
+            argon2_iterations: l.parse("OPAQUE_ARGON2_ITERATIONS", "opaque.argon2_iterations", 2),
+            argon2_parallelism: l.parse(
+                "OPAQUE_ARGON2_PARALLELISM",
+                "opaque.argon2_parallelism",
+                1,
+            ),
+        };
+        let sessions = SessionConfig {
+            lifetime: l.parse("SESSION_LIFETIME", "sessions.lifetime", 604800),
+            persistent_lifetime: l.parse(
+                "PERSISTENT_SESSION_LIFETIME",
+                "sessions.persistent_lifetime",
+                2592000,
+            ),
+            escalation_lifetime: l.parse(
+                "ESCALATION_LIFETIME",
+                "sessions.escalation_lifetime",
+                3600,
+            ),
+        };
+        let rate_limits = RateLimitConfig {
+            api: l.rate_limit("API", "api", 20, 5),
+            login: l.rate_limit("LOGIN", "login", 5, 20),
+            registration: l.rate_limit("REGISTRATION", "registration", 5, 21600),
+            forgot: l.rate_limit("FORGOT", "forgot", 10, 21600),
+            verify_email: l.rate_limit("VERIFY_EMAIL", "verify_email", 5, 3600),
+            validate: l.rate_limit("VALIDATE", "validate", 10, 5),
+        };
+        let captcha = CaptchaConfig {
+            provider: l
+                .choice(
+                    "CAPTCHA_PROVIDER",
+                    "captcha.provider",
+                    &["hcaptcha", "turnstile", "recaptcha", "pow", "test", "none"],
+                )
+                .unwrap_or("hcaptcha".to_string()),
+            // HCAPTCHA_SECRET is still accepted from before other providers were supported
+            secret: l
+                .string("CAPTCHA_SECRET", "captcha.secret")
+                .or_else(|| l.string("HCAPTCHA_SECRET", "captcha.hcaptcha_secret")),
+            verify_url: l.string("CAPTCHA_VERIFY_URL", "captcha.verify_url"),
+            min_score: l.parse("CAPTCHA_MIN_SCORE", "captcha.min_score", 0.5),
+            pow_difficulty: l.parse("POW_DIFFICULTY", "captcha.pow_difficulty", 18),
+            pow_max_difficulty: l.parse("POW_MAX_DIFFICULTY", "captcha.pow_max_difficulty", 24),
+            on_forgot: l.parse("CAPTCHA_ON_FORGOT", "captcha.on_forgot", false),
+            login_threshold: l.parse("CAPTCHA_LOGIN_THRESHOLD", "captcha.login_threshold", 5),
+        };
+        let mail = MailConfig {
+            transport: l.choice(
+                "MAIL_TRANSPORT",
+                "mail.transport",
+                &["smtp", "file", "log", "none"],
+            ),
+            file_directory: l
+                .string("MAIL_FILE_DIRECTORY", "mail.file_directory")
+                .unwrap_or("mail".to_string()),
+            templates_dir: l.string("EMAIL_TEMPLATES_DIR", "mail.templates_dir"),
+        };
+        let smtp = l
+            .string("SMTP_SERVER", "smtp.server")
+            .map(|server| SmtpConfig {
+                server,
+                from: l.string("SMTP_FROM", "smtp.from"),
+                port: l.optional("SMTP_PORT", "smtp.port"),
+                username: l.string("SMTP_USERNAME", "smtp.username"),
+                password: l.string("SMTP_PASSWORD", "smtp.password"),
+                tls: l.choice(
+                    "SMTP_TLS",
+                    "smtp.tls",
+                    &["implicit", "starttls", "opportunistic", "none"],
+                ),
+            });
+        let dkim = match (
+            l.string("DKIM_SELECTOR", "dkim.selector"),
+            l.string("DKIM_DOMAIN", "dkim.domain"),
+            l.string("DKIM_PRIVATE_KEY_FILE", "dkim.private_key_file"),
+        ) {
+            (Some(selector), Some(domain), Some(private_key_file)) => Some(DkimConfig {
+                selector,
+                domain,
+                private_key_file,
+                algorithm: l.choice("DKIM_ALGORITHM", "dkim.algorithm", &["rsa", "ed25519"]),
+            }),
+            (None, None, None) => None,
+            _ => {
+                l.errors.push(
+                    "DKIM signing needs a selector, a domain and a private key file".to_string(),
+                );
+                None
+            }
+        };
+        let registration = RegistrationConfig {
+            mode: l.choice(
+                "REGISTRATION_MODE",
+                "registration.mode",
+                &["open", "invite", "closed"],
+            ),
+            allowed_domains: l.list(
+                "REGISTRATION_ALLOWED_DOMAINS",
+                "registration.allowed_domains",
+            ),
+            denied_domains: l.list("REGISTRATION_DENIED_DOMAINS", "registration.denied_domains"),
+            disposable_domains_file: l.string(
+                "DISPOSABLE_DOMAINS_FILE",
+                "registration.disposable_domains_file",
+            ),
+            approval: l.parse("REGISTRATION_APPROVAL", "registration.approval", false),
+            user_invites: l.parse("USER_INVITES", "registration.user_invites", false),
+        };
+        let usernames = UsernameConfig {
+            reserved: l.list("RESERVED_USERNAMES", "usernames.reserved"),
+            change_cooldown_days: l.parse(
+                "USERNAME_CHANGE_COOLDOWN_DAYS",
+                "usernames.change_cooldown_days",
+                30,
+            ),
+            hold_days: l.parse("USERNAME_HOLD_DAYS", "usernames.hold_days", 90),
+        };
+        let accounts = AccountConfig {
+            unverified_blocked_services: l.list(
+                "UNVERIFIED_BLOCKED_SERVICES",
+                "accounts.unverified_blocked_services",
+            ),
+            reset_delay_hours: l.parse("RESET_DELAY_HOURS", "accounts.reset_delay_hours", 72),
+            security_event_retention_days: l.parse(
+                "SECURITY_EVENT_RETENTION_DAYS",
+                "accounts.security_event_retention_days",
+                90,
+            ),
+        };
+        let introspection = IntrospectionConfig {
+            // `id:secret` pairs, the ids are the service names checked by UNVERIFIED_BLOCKED_SERVICES
+            clients: l
+                .list("INTROSPECTION_CLIENTS", "introspection.clients")
+                .into_iter()
+                .filter_map(|client| match client.split_once(':') {
+                    Some((id, secret)) => Some(IntrospectionClient {
+                        id: id.to_string(),
+                        secret: secret.to_string(),
+                    }),
+                    None => {
+                        l.errors.push(format!(
+                            "INTROSPECTION_CLIENTS entries must look like id:secret, got {}",
+                            client
+                        ));
+                        None
+                    }
+                })
+                .collect(),
+            cache_seconds: l.parse(
+                "INTROSPECTION_CACHE_SECONDS",
+                "introspection.cache_seconds",
+                30,
+            ),
+        };
+
+        let config = Config {
+            server,
+            logging,
+            database,
+            security,
+            opaque,
+            sessions,
+            rate_limits,
+            captcha,
+            mail,
+            smtp,
+            dkim,
+            registration,
+            usernames,
+            accounts,
+            introspection,
+        };
+        let mut errors = l.errors;
+        config.validate(&mut errors);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Checks that need more than one value, or more than the value's type
+    fn validate(&self, errors: &mut Vec<String>) {
+        if EnvFilter::try_new(&self.logging.level).is_err() {
+            errors.push(format!(
+                "LOG_LEVEL ({}) is not a valid log filter",
+                self.logging.level
+            ));
+        }
+        if self.server.cors_origins.is_empty() {
+            errors.push("CORS_ORIGINS (`server.cors_origins`) must be set".to_string());
+        }
+        if !self.server.public_root.is_empty() && !self.server.rp_id.is_empty() {
+            match Url::parse(&self.server.public_root) {
+                Ok(url) => {
+                    let host = url.host_str().unwrap_or_default();
+                    let rp_id = &self.server.rp_id;
+                    if host != rp_id && !host.ends_with(&format!(".{}", rp_id)) {
+                        errors.push(format!(
+                            "RP_ID ({}) must be the host of PUBLIC_ROOT ({}) or a parent domain of it",
+                            rp_id, host
+                        ));
+                    }
+                    if url.scheme() != "https" && host != "localhost" {
+                        errors.push("PUBLIC_ROOT must use https outside of localhost".to_string());
+                    }
+                }
+                Err(e) => errors.push(format!("PUBLIC_ROOT is not a valid URL: {}", e)),
+            }
+        }
+        if !self.security.jwt_secret.is_empty() && self.security.jwt_secret.len() < 32 {
+            errors.push("JWT_SECRET must be at least 32 bytes".to_string());
+        }
+        if self
+            .security
+            .jwt_previous_secrets
+            .iter()
+            .any(|secret| secret.len() < 32)
+        {
+            errors.push("JWT_PREVIOUS_SECRETS must each be at least 32 bytes".to_string());
+        }
+        if let Some(token) = &self.security.metrics_token {
+            if token.len() < 32 {
+                errors.push("METRICS_TOKEN must be at least 32 bytes".to_string());
+            }
+        }
+        if self.security.opaque_master_key.len() != 32 {
+            errors.push("OPAQUE_MASTER_KEY must be 32 bytes of hex".to_string());
+        }
+        if argon2::Params::new(
+            self.opaque.argon2_memory,
+            self.opaque.argon2_iterations,
+            self.opaque.argon2_parallelism,
+            None,
+        )
+        .is_err()
+        {
+            errors.push("The OPAQUE_ARGON2_* parameters are out of range".to_string());
+        }
+        let sessions = &self.sessions;
+        if sessions.lifetime == 0 || sessions.persistent_lifetime == 0 {
+            errors.push("Session lifetimes must be longer than 0 seconds".to_string());
+        }
+        if matches!(
+            self.captcha.provider.as_str(),
+            "hcaptcha" | "turnstile" | "recaptcha"
+        ) && self.captcha.secret.is_none()
+        {
+            errors.push(format!(
+                "CAPTCHA_SECRET must be set for {}",
+                self.captcha.provider
+            ));
+        }
+        // accepts a well-known token, so only for throwaway instances
+        if self.captcha.provider == "test" && self.database.storage_backend != "memory" {
+            errors.push("CAPTCHA_PROVIDER=test requires STORAGE_BACKEND=memory".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.captcha.min_score) {
+            errors.push("CAPTCHA_MIN_SCORE must be between 0 and 1".to_string());
+        }
+        if self.captcha.pow_difficulty > self.captcha.pow_max_difficulty {
+            errors.push("POW_DIFFICULTY can't be above POW_MAX_DIFFICULTY".to_string());
+        }
+        if let Some(smtp) = &self.smtp {
+            if smtp.from.is_none() {
+                errors.push("SMTP_FROM must be set to send email over SMTP".to_string());
+            }
+            if smtp.username.is_some() != smtp.password.is_some() {
+                errors.push("SMTP_USERNAME and SMTP_PASSWORD must be set together".to_string());
+            }
+        } else if self.mail.transport.as_deref() == Some("smtp") {
+            errors.push("SMTP_SERVER must be set for the smtp transport".to_string());
+        }
+        let files = [
+            (
+                "DKIM_PRIVATE_KEY_FILE",
+                self.dkim.as_ref().map(|d| &d.private_key_file),
+            ),
+            (
+                "DISPOSABLE_DOMAINS_FILE",
+                self.registration.disposable_domains_file.as_ref(),
+            ),
+        ];
+        for (name, file) in files {
+            if let Some(file) = file {
+                if !Path::new(file).is_file() {
+                    errors.push(format!("{} ({}) does not exist", name, file));
+                }
+            }
+        }
+        let clients = &self.introspection.clients;
+        for (i, client) in clients.iter().enumerate() {
+            if client.secret.len() < 32 {
+                errors.push(format!(
+                    "The introspection secret of {} must be at least 32 bytes",
+                    client.id
+                ));
+            }
+            if clients[..i].iter().any(|other| other.id == client.id) {
+                errors.push(format!(
+                    "Introspection client {} is listed twice",
+                    client.id
+                ));
+            }
+        }
+        if let Some(dir) = &self.mail.templates_dir {
+            if !Path::new(dir).is_dir() {
+                errors.push(format!("EMAIL_TEMPLATES_DIR ({}) does not exist", dir));
+            }
+        }
+    }
+}
+
+fn read_file() -> Result<String, Vec<String>> {
+    let path = env::var("CONFIG_FILE").ok();
+    let required = path.is_some();
+    let path = path.unwrap_or("config.toml".to_string());
+    match fs::read_to_string(&path) {
+        Ok(file) => Ok(file),
+        Err(_) if !required => Ok(String::new()),
+        Err(e) => Err(vec![format!("Failed to read CONFIG_FILE {}: {}", path, e)]),
+    }
+}
+
+pub fn load() -> Result<Config, Vec<String>> {
+    Config::parse(&read_file()?, &|name| env::var(name).ok())
+}
+
+// Loads the configuration, exiting with every problem at once if it's invalid. Logging depends on
+// the configuration, so problems go straight to stderr.
+pub fn init() {
+    CONFIG.get_or_init(|| {
+        load().unwrap_or_else(|errors| {
+            for e in &errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("Invalid configuration, {} problem(s) found", errors.len());
+            std::process::exit(1);
+        })
+    });
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        load().unwrap_or_else(|errors| panic!("Invalid configuration: {}", errors.join("; ")))
+    })
+}
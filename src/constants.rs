@@ -1,8 +1,25 @@
 pub const SERVICE: &str = "account";
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub const SHORT_SESSION: u128 = 604800000; // 7 days
-pub const LONG_SESSION: u128 = 2592000000; // 30 days
-pub const ELEVATED_SESSION: u128 = 300000; // 5 minutes
-
 pub const CONTINUE_TIMEOUT: u64 = 3600; // 1 hour
+pub const VERIFY_TIMEOUT: u64 = 600; // 10 minutes
+pub const RECOVERY_TIMEOUT: u64 = 604800; // 7 days
+
+pub const POW_CHALLENGE_TIMEOUT: u64 = 300; // 5 minutes
+pub const POW_WINDOW: u64 = 600; // 10 minutes
+pub const POW_FREE_REQUESTS: u64 = 5; // per window before difficulty increases
+
+pub const OUTBOX_MAX_ATTEMPTS: u32 = 8;
+pub const OUTBOX_CLAIM_TIMEOUT: u64 = 300; // 5 minutes
+pub const OUTBOX_RETENTION: u64 = 604800; // 7 days
+
+pub const MIGRATION_LOCK_TIMEOUT: u64 = 600; // 10 minutes, renewed after every migration
+
+pub const LAST_USED_PRECISION: u64 = 60; // API keys record their last use at most once a minute
+pub const PERSONAL_TOKEN_MAX_DAYS: u64 = 365;
+
+// Unique indexes whose violations are reported as conflicts, see `errors::duplicate_key_error`
+pub const EMAIL_INDEX: &str = "email_unique";
+pub const USERNAME_INDEX: &str = "username_unique";
+pub const USERNAME_KEY_INDEX: &str = "username_key_unique";
+pub const SERVICE_ACCOUNT_NAME_INDEX: &str = "name_unique";
@@ -1,15 +1,12 @@
-use mongodb::{bson::doc, Collection};
+use mongodb::Collection;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    environment::CDN_MONGODB_DATABASE,
-    errors::{Error, Result},
-};
+use crate::environment::CDN_MONGODB_DATABASE;
 
 use super::get_connection;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum FileMetadata {
     File,
@@ -19,7 +16,7 @@ pub enum FileMetadata {
     Audio,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct File {
     pub id: String,
@@ -49,52 +46,3 @@ pub fn get_collection() -> Collection<File> {
         c
     }
 }
-
-impl File {
-    pub async fn get(id: &String) -> Result<File> {
-        get_collection()
-            .find_one(doc! {
-                "id": id,
-                "deleted": false,
-                "flagged": false
-            })
-            .await?
-            .ok_or(Error::DatabaseError)
-    }
-
-    pub async fn attach(&self) -> Result<()> {
-        get_collection()
-            .update_one(
-                doc! {
-                    "id": &self.id,
-                    "deleted": false,
-                    "flagged": false,
-                },
-                doc! {
-                    "$set": {
-                        "attached": true,
-                    },
-                },
-            )
-            .await?;
-        Ok(())
-    }
-
-    pub async fn detach(&self) -> Result<()> {
-        get_collection()
-            .update_one(
-                doc! {
-                    "id": &self.id,
-                    "deleted": false,
-                    "flagged": false,
-                },
-                doc! {
-                    "$set": {
-                        "attached": false,
-                    },
-                },
-            )
-            .await?;
-        Ok(())
-    }
-}
@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use log::{error, info};
+use mongodb::{
+    bson::{doc, from_document, Document},
+    options::{Collation, CollationStrength, IndexOptions},
+    Collection, IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    code, invite, outbox, passkey, personal_token, profile, recovery, reset_request,
+    security_event, service_account, session, used_challenge, user, username_history,
+};
+use crate::{
+    constants::{EMAIL_INDEX, SERVICE_ACCOUNT_NAME_INDEX, USERNAME_INDEX, USERNAME_KEY_INDEX},
+    errors::{Error, Result},
+};
+
+// Case-insensitive and Unicode-normalized, queries on emails have to use it to hit the index
+pub fn email_collation() -> Collation {
+    Collation::builder()
+        .locale("en".to_string())
+        .strength(CollationStrength::Secondary)
+        .normalization(true)
+        .build()
+}
+
+fn username_collation() -> Collation {
+    Collation::builder()
+        .locale("en".to_string())
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+// Users whose emails or usernames only differ in case, which exact lookups used to allow
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conflict {
+    pub field: String,
+    pub value: String,
+    pub user_ids: Vec<String>,
+}
+
+async fn find_conflicts_on(field: &str, collation: Collation) -> Result<Vec<Conflict>> {
+    let pipeline = vec![
+        doc! { "$group": {
+            "_id": format!("${}", field),
+            "value": { "$first": format!("${}", field) },
+            "user_ids": { "$push": "$id" },
+        } },
+        doc! { "$match": { "user_ids.1": { "$exists": true } } },
+        doc! { "$project": { "_id": 0, "field": field, "value": 1, "user_ids": 1 } },
+    ];
+    let mut cursor = user::get_collection()
+        .aggregate(pipeline)
+        .collation(collation)
+        .await?;
+    let mut conflicts = Vec::new();
+    while let Some(document) = cursor.next().await {
+        let conflict: Conflict = from_document(document?).map_err(|e| {
+            error!("Unexpected conflict document: {}", e);
+            Error::DatabaseError
+        })?;
+        conflicts.push(conflict);
+    }
+    Ok(conflicts)
+}
+
+// Grouped with the collations of the unique indexes, so these are exactly what stops them
+pub async fn find_conflicts() -> Result<Vec<Conflict>> {
+    let mut conflicts = find_conflicts_on("email", email_collation()).await?;
+    conflicts.extend(find_conflicts_on("username", username_collation()).await?);
+    Ok(conflicts)
+}
+
+fn unique(keys: Document, name: &str) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(
+            IndexOptions::builder()
+                .unique(true)
+                .name(name.to_string())
+                .build(),
+        )
+        .build()
+}
+
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+async fn create<T: Send + Sync>(collection: Collection<T>, indexes: Vec<IndexModel>) {
+    let name = collection.name().to_string();
+    collection
+        .create_indexes(indexes)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to create indexes on {}: {}", name, e));
+}
+
+// The unique indexes on users can't be built while accounts collide, so those are listed
+async fn ensure_users() {
+    let result = user::get_collection()
+        .create_indexes(vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            IndexModel::builder()
+                .keys(doc! { "email": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(EMAIL_INDEX.to_string())
+                        .collation(email_collation())
+                        .build(),
+                )
+                .build(),
+            IndexModel::builder()
+                .keys(doc! { "username": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(USERNAME_INDEX.to_string())
+                        .collation(username_collation())
+                        .build(),
+                )
+                .build(),
+            // accounts created before username keys existed have none
+            IndexModel::builder()
+                .keys(doc! { "username_key": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(USERNAME_KEY_INDEX.to_string())
+                        .partial_filter_expression(doc! { "username_key": { "$gt": "" } })
+                        .build(),
+                )
+                .build(),
+            index(doc! { "pending_approval": 1 }),
+        ])
+        .await;
+    let Err(e) = result else {
+        return;
+    };
+    let conflicts = find_conflicts().await.unwrap_or_default();
+    if conflicts.is_empty() {
+        panic!("Failed to create indexes on users: {}", e);
+    }
+    for conflict in &conflicts {
+        error!(
+            "Users {} share the {} {} apart from case",
+            conflict.user_ids.join(", "),
+            conflict.field,
+            conflict.value
+        );
+    }
+    panic!(
+        "{} emails or usernames are used by several accounts that only differ in case. Change or \
+         delete all but one account of each (`users conflicts` lists them), then start again",
+        conflicts.len()
+    );
+}
+
+// Creating an index that already exists is a no-op, so this runs on every startup
+pub async fn ensure() {
+    ensure_users().await;
+    create(
+        profile::get_collection(),
+        vec![unique(doc! { "id": 1 }, "id_unique")],
+    )
+    .await;
+    create(
+        session::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            unique(doc! { "token": 1 }, "token_unique"),
+            index(doc! { "user_id": 1 }),
+        ],
+    )
+    .await;
+    create(
+        passkey::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            unique(doc! { "credential_id": 1 }, "credential_id_unique"),
+            index(doc! { "user_id": 1 }),
+        ],
+    )
+    .await;
+    create(
+        code::get_collection(),
+        vec![index(doc! { "user_id": 1, "code": 1 })],
+    )
+    .await;
+    create(
+        security_event::get_collection(),
+        vec![
+            index(doc! { "user_id": 1, "id": -1 }),
+            index(doc! { "created_at": 1 }),
+        ],
+    )
+    .await;
+    create(
+        recovery::get_collection(),
+        vec![
+            unique(doc! { "token": 1 }, "token_unique"),
+            index(doc! { "created_at": 1 }),
+        ],
+    )
+    .await;
+    create(
+        reset_request::get_collection(),
+        vec![
+            unique(doc! { "token": 1 }, "token_unique"),
+            index(doc! { "user_id": 1 }),
+        ],
+    )
+    .await;
+    create(
+        outbox::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            index(doc! { "status": 1, "next_attempt_at": 1 }),
+        ],
+    )
+    .await;
+    create(
+        invite::get_collection(),
+        vec![
+            unique(doc! { "code": 1 }, "code_unique"),
+            index(doc! { "created_by": 1 }),
+        ],
+    )
+    .await;
+    create(
+        username_history::get_collection(),
+        vec![
+            index(doc! { "username_key": 1, "changed_at": -1 }),
+            index(doc! { "user_id": 1 }),
+        ],
+    )
+    .await;
+    create(
+        service_account::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            unique(doc! { "key_hash": 1 }, "key_hash_unique"),
+            unique(doc! { "name": 1 }, SERVICE_ACCOUNT_NAME_INDEX),
+        ],
+    )
+    .await;
+    create(
+        personal_token::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            unique(doc! { "token_hash": 1 }, "token_hash_unique"),
+            index(doc! { "user_id": 1, "id": -1 }),
+            index(doc! { "expires_at": 1 }),
+        ],
+    )
+    .await;
+    create(
+        used_challenge::get_collection(),
+        vec![
+            unique(doc! { "id": 1 }, "id_unique"),
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                .build(),
+        ],
+    )
+    .await;
+    info!("Database indexes are up to date");
+}
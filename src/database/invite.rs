@@ -0,0 +1,29 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<Invite>> = OnceCell::new();
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: String,
+    // unlimited if not set
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+}
+
+pub fn get_collection() -> Collection<Invite> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<Invite>("invites");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
@@ -1,16 +1,29 @@
 pub mod code;
 pub mod files;
+pub mod indexes;
+pub mod invite;
+pub mod outbox;
 pub mod passkey;
+pub mod personal_token;
 pub mod profile;
+pub mod recovery;
+pub mod reset_request;
+pub mod security_event;
+pub mod service_account;
 pub mod session;
 pub mod settings;
+pub mod used_challenge;
 pub mod user;
+pub mod username_history;
 
 use log::info;
-use mongodb::{Client, Database};
+use mongodb::{Client, ClientSession, Database};
 use once_cell::sync::OnceCell;
 
-use crate::environment::{MONGODB_DATABASE, MONGODB_URI};
+use crate::{
+    environment::{MONGODB_DATABASE, MONGODB_URI},
+    errors::Result,
+};
 
 static DATABASE: OnceCell<Client> = OnceCell::new();
 
@@ -29,3 +42,11 @@ pub fn get_connection() -> &'static Client {
 pub fn get_database() -> Database {
     get_connection().database(&MONGODB_DATABASE)
 }
+
+// Writes spanning several documents go through a transaction, which requires a replica set.
+// Dropping the session without committing aborts the transaction.
+pub async fn start_transaction() -> Result<ClientSession> {
+    let mut session = get_connection().start_session().await?;
+    session.start_transaction().await?;
+    Ok(session)
+}
@@ -0,0 +1,55 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<OutboxEmail>> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OutboxStatus {
+    Pending,
+    Sending,
+    Sent,
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "PENDING",
+            OutboxStatus::Sending => "SENDING",
+            OutboxStatus::Sent => "SENT",
+            OutboxStatus::Failed => "FAILED",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OutboxEmail {
+    pub id: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    // set while a worker is delivering the email, so stale claims can be retried
+    pub claimed_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub sent_at: Option<u64>,
+}
+
+pub fn get_collection() -> Collection<OutboxEmail> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<OutboxEmail>("outbox");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
@@ -0,0 +1,56 @@
+// Tokens users create to script against their own account, see `authenticate::PERSONAL_TOKEN_ROUTES`
+
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Error, Result};
+
+static COLLECTION: OnceCell<Collection<PersonalToken>> = OnceCell::new();
+
+pub const TOKEN_PREFIX: &str = "nxp_";
+
+pub const SCOPE_PROFILE_READ: &str = "profile:read";
+pub const SCOPE_PROFILE_WRITE: &str = "profile:write";
+pub const SCOPE_SESSIONS_READ: &str = "sessions:read";
+pub const SCOPE_SECURITY_EVENTS_READ: &str = "security_events:read";
+pub const SCOPES: &[&str] = &[
+    SCOPE_PROFILE_READ,
+    SCOPE_PROFILE_WRITE,
+    SCOPE_SESSIONS_READ,
+    SCOPE_SECURITY_EVENTS_READ,
+];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PersonalToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    // SHA-256 of the token, which is only shown when it's created
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub last_used_at: Option<u64>,
+}
+
+pub fn get_collection() -> Collection<PersonalToken> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<PersonalToken>("personal_tokens");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub fn validate_scopes(scopes: &[String]) -> Result<()> {
+    if !scopes.is_empty() && scopes.iter().all(|scope| SCOPES.contains(&scope.as_str())) {
+        Ok(())
+    } else {
+        Err(Error::InvalidScope)
+    }
+}
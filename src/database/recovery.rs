@@ -0,0 +1,26 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<RecoveryToken>> = OnceCell::new();
+
+// Issued with security notifications so the account owner can lock out an attacker
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecoveryToken {
+    pub token: String,
+    pub user_id: String,
+    pub created_at: u64,
+}
+
+pub fn get_collection() -> Collection<RecoveryToken> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<RecoveryToken>("recovery_tokens");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
@@ -0,0 +1,28 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<ResetRequest>> = OnceCell::new();
+
+// A password reset that skipped the second factor, which only becomes usable after a delay
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResetRequest {
+    pub token: String,
+    pub user_id: String,
+    pub email: String,
+    pub created_at: u64,
+    pub available_at: u64,
+}
+
+pub fn get_collection() -> Collection<ResetRequest> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<ResetRequest>("reset_requests");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
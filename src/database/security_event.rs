@@ -0,0 +1,125 @@
+use actix_web::{web::Data, HttpRequest};
+use log::error;
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{store::SecurityEventStore, utilities::get_time_secs};
+
+static COLLECTION: OnceCell<Collection<SecurityEvent>> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SecurityEventKind {
+    Login,
+    LoginFailed,
+    MfaEnabled,
+    MfaDisabled,
+    PasswordChanged,
+    PasswordReset,
+    PasswordResetRequested,
+    // re-registered with the current suite after a login, see `Login::FinishUpgrade`
+    PasswordUpgraded,
+    PasskeyAdded,
+    PasskeyRemoved,
+    SessionRevoked,
+    OtherSessionsRevoked,
+    AccountSettingsChanged,
+    AccountRecovery,
+    PersonalTokenCreated,
+    PersonalTokenRevoked,
+    // every token at once, when the user was signed out everywhere
+    PersonalTokensRevoked,
+    // by an operator with the `users` commands
+    AdminMfaReset,
+    AdminSessionsRevoked,
+}
+
+// Append-only: events are only ever inserted, and removed by the retention cleanup
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SecurityEvent {
+    pub id: String,
+    pub user_id: String,
+    pub kind: SecurityEventKind,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub session_id: Option<String>,
+    pub created_at: u64,
+}
+
+pub fn get_collection() -> Collection<SecurityEvent> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<SecurityEvent>("security_events");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+// Audit writes happen after the change they describe is committed, so failing here must not
+// fail the request
+pub async fn record(
+    req: &HttpRequest,
+    kind: SecurityEventKind,
+    user_id: &str,
+    session_id: Option<&str>,
+) {
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.to_string());
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.to_string());
+    let events = req
+        .app_data::<Data<dyn SecurityEventStore>>()
+        .expect("Unexpected error: security event store not configured");
+    insert(
+        events.as_ref(),
+        SecurityEvent {
+            id: Ulid::new().to_string(),
+            user_id: user_id.to_string(),
+            kind,
+            ip,
+            user_agent,
+            session_id: session_id.map(|s| s.to_string()),
+            created_at: get_time_secs(),
+        },
+    )
+    .await;
+}
+
+// For changes made from the command line, which have no request to take an ip from
+pub async fn record_for_operator(
+    events: &dyn SecurityEventStore,
+    kind: SecurityEventKind,
+    user_id: &str,
+) {
+    insert(
+        events,
+        SecurityEvent {
+            id: Ulid::new().to_string(),
+            user_id: user_id.to_string(),
+            kind,
+            ip: None,
+            user_agent: None,
+            session_id: None,
+            created_at: get_time_secs(),
+        },
+    )
+    .await;
+}
+
+async fn insert(events: &dyn SecurityEventStore, event: SecurityEvent) {
+    let (kind, user_id) = (event.kind, event.user_id.clone());
+    if let Err(e) = events.record(event).await {
+        error!("Failed to record {:?} for user {}: {:?}", kind, user_id, e);
+    }
+}
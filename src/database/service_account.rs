@@ -0,0 +1,50 @@
+// Non-human callers, authenticated by an API key sent as a bearer token
+
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Error, Result};
+
+static COLLECTION: OnceCell<Collection<ServiceAccount>> = OnceCell::new();
+
+// every API key starts with this, so the middleware can tell them apart from session tokens
+pub const KEY_PREFIX: &str = "nxs_";
+
+pub const SCOPE_USERS_READ: &str = "users:read";
+pub const SCOPE_INTROSPECT: &str = "introspect";
+pub const SCOPES: &[&str] = &[SCOPE_USERS_READ, SCOPE_INTROSPECT];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ServiceAccount {
+    pub id: String,
+    // checked against UNVERIFIED_BLOCKED_SERVICES, like an introspection client id
+    pub name: String,
+    // SHA-256 of the API key, which is only shown when the account is created
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub created_at: u64,
+    pub last_used_at: Option<u64>,
+}
+
+pub fn get_collection() -> Collection<ServiceAccount> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<ServiceAccount>("service_accounts");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
+
+pub fn validate_scopes(scopes: &[String]) -> Result<()> {
+    if scopes.iter().all(|scope| SCOPES.contains(&scope.as_str())) {
+        Ok(())
+    } else {
+        Err(Error::InvalidScope)
+    }
+}
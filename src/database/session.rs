@@ -10,6 +10,9 @@ pub struct Session {
     pub token: String,
     pub friendly_name: String,
     pub user_id: String,
+    // RFC 8176 method references, empty for sessions from before they were recorded
+    #[serde(default)]
+    pub auth_methods: Vec<String>,
 }
 
 pub fn get_collection() -> Collection<Session> {
@@ -1,16 +1,40 @@
 // Server configuration
 
-use mongodb::{bson::doc, Collection};
+use mongodb::Collection;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
-use crate::opaque::create_server_setup;
-
 static COLLECTION: OnceCell<Collection<Settings>> = OnceCell::new();
+static SCHEMA_COLLECTION: OnceCell<Collection<SchemaState>> = OnceCell::new();
+
+pub const SCHEMA_ID: &str = "schema";
+// new deployments create the server settings under a fixed id, so racing replicas agree
+pub const SETTINGS_ID: &str = "server";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
-    pub opaque_server_setup: Vec<u8>,
+    // older setups stay around for the users that haven't logged in since a rotation
+    pub server_setups: Vec<SealedServerSetup>,
+    pub current_server_setup: u32,
+}
+
+// An OPAQUE server setup encrypted with OPAQUE_MASTER_KEY, see `opaque::seal_server_setup`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedServerSetup {
+    pub id: u32,
+    pub data: Vec<u8>,
+    pub created_at: u64,
+}
+
+// Lives in the settings collection next to the server settings, under a fixed id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaState {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub version: u32,
+    // held by the replica running migrations
+    pub locked_by: Option<String>,
+    pub locked_until: Option<u64>,
 }
 
 pub fn get_collection() -> Collection<Settings> {
@@ -26,15 +50,15 @@ pub fn get_collection() -> Collection<Settings> {
     }
 }
 
-pub async fn get_settings() -> Settings {
-    let collection = get_collection();
-    if let Some(settings) = collection.find_one(doc! {}).await.unwrap() {
-        settings
+pub fn get_schema_collection() -> Collection<SchemaState> {
+    let collection = SCHEMA_COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
     } else {
-        let settings = Settings {
-            opaque_server_setup: create_server_setup().serialize().as_slice().to_vec(),
-        };
-        collection.insert_one(&settings).await.unwrap();
-        settings
+        let c = super::get_database().collection::<SchemaState>("settings");
+        SCHEMA_COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
     }
 }
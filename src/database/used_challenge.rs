@@ -0,0 +1,26 @@
+use mongodb::{bson::DateTime, Collection};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<UsedChallenge>> = OnceCell::new();
+
+// A solved proof-of-work challenge, kept until it expires so it can't be submitted twice
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UsedChallenge {
+    pub id: String,
+    // a date rather than seconds, the TTL index only removes documents by dates
+    pub expires_at: DateTime,
+}
+
+pub fn get_collection() -> Collection<UsedChallenge> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<UsedChallenge>("used_challenges");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
@@ -2,6 +2,8 @@ use mongodb::Collection;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
+use crate::opaque::PasswordSuite;
+
 static COLLECTION: OnceCell<Collection<User>> = OnceCell::new();
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -9,13 +11,39 @@ pub struct User {
     pub id: String,
     pub email: String,
     pub password_data: Vec<u8>,
+    // accounts from before suites were versioned have no key stretching
+    #[serde(default)]
+    pub password_suite: PasswordSuite,
     pub username: String,
+    // see `username::normalize_key`, unique across users
+    #[serde(default)]
+    pub username_key: String,
+    #[serde(default)]
+    pub username_changed_at: Option<u64>,
     pub mfa_enabled: bool,
     pub mfa_secret: Option<String>,
     pub platform_administrator: bool,
+    // non-critical security notifications can be turned off
+    #[serde(default = "default_true")]
+    pub security_notifications: bool,
+    // preferred language for emails, falls back to Accept-Language
+    #[serde(default)]
+    pub locale: Option<String>,
+    // accounts created without email delivery never proved they own the address
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub email_verified_at: Option<u64>,
+    // set when REGISTRATION_APPROVAL is on, until an administrator approves the account
+    #[serde(default)]
+    pub pending_approval: bool,
     // Recovery email, client-encrypted keys?
 }
 
+fn default_true() -> bool {
+    true
+}
+
 pub fn get_collection() -> Collection<User> {
     let collection = COLLECTION.get();
     if let Some(c) = collection {
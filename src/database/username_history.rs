@@ -0,0 +1,28 @@
+use mongodb::Collection;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+static COLLECTION: OnceCell<Collection<UsernameHistory>> = OnceCell::new();
+
+// Old usernames are held until `released_at`, and kept afterwards so lookups can redirect
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UsernameHistory {
+    pub username: String,
+    pub username_key: String,
+    pub user_id: String,
+    pub changed_at: u64,
+    pub released_at: u64,
+}
+
+pub fn get_collection() -> Collection<UsernameHistory> {
+    let collection = COLLECTION.get();
+    if let Some(c) = collection {
+        c.clone()
+    } else {
+        let c = super::get_database().collection::<UsernameHistory>("username_history");
+        COLLECTION
+            .set(c.clone())
+            .expect("Unexpected error: failed to set collection");
+        c
+    }
+}
@@ -1,32 +1,89 @@
-use std::env;
+// Shorthands for values in the validated configuration, see `config.rs`
 
 use lazy_static::lazy_static;
 
+use crate::config;
+
 lazy_static! {
-    pub static ref MONGODB_URI: String = env::var("MONGODB_URI").expect("MONGODB_URI must be set");
-    pub static ref MONGODB_DATABASE: String =
-        env::var("MONGODB_DATABASE").expect("MONGODB_DATABASE must be set");
-    pub static ref CDN_MONGODB_DATABASE: String =
-        env::var("CDN_MONGODB_DATABASE").expect("CDN_MONGODB_DATABASE must be set");
-    pub static ref JWT_SECRET: String = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    pub static ref HCAPTCHA_SECRET: String =
-        env::var("HCAPTCHA_SECRET").expect("HCAPTCHA_SECRET must be set");
-    pub static ref CORS_ORIGINS: Vec<String> = env::var("CORS_ORIGINS")
-        .expect("CORS_ORIGINS must be set")
-        .split(',')
-        .map(|s| s.to_string())
+    pub static ref MONGODB_URI: String = config::get().database.uri.clone();
+    pub static ref MONGODB_DATABASE: String = config::get().database.name.clone();
+    pub static ref CDN_MONGODB_DATABASE: String = config::get().database.cdn_name.clone();
+    // `mongodb`, or `memory` for tests and throwaway instances
+    pub static ref STORAGE_BACKEND: String = config::get().database.storage_backend.clone();
+    // replicas started with this off refuse to run against an outdated schema instead
+    pub static ref MIGRATE_ON_STARTUP: bool = config::get().database.migrate_on_startup;
+    // 32 bytes that the OPAQUE server setups are encrypted with
+    pub static ref OPAQUE_MASTER_KEY: Vec<u8> = config::get().security.opaque_master_key.clone();
+    // Argon2id parameters for new passwords, memory in KiB; existing passwords keep theirs until
+    // the next login upgrades them
+    pub static ref OPAQUE_ARGON2_MEMORY: u32 = config::get().opaque.argon2_memory;
+    pub static ref OPAQUE_ARGON2_ITERATIONS: u32 = config::get().opaque.argon2_iterations;
+    pub static ref OPAQUE_ARGON2_PARALLELISM: u32 = config::get().opaque.argon2_parallelism;
+    pub static ref JWT_SECRET: String = config::get().security.jwt_secret.clone();
+    pub static ref JWT_PREVIOUS_SECRETS: Vec<String> =
+        config::get().security.jwt_previous_secrets.clone();
+    pub static ref METRICS_TOKEN: Option<String> = config::get().security.metrics_token.clone();
+    // session lifetimes in milliseconds, like session expiry times
+    pub static ref SHORT_SESSION: u128 = config::get().sessions.lifetime as u128 * 1000;
+    pub static ref LONG_SESSION: u128 = config::get().sessions.persistent_lifetime as u128 * 1000;
+    // seconds an escalation token stays valid for
+    pub static ref ESCALATION_TIMEOUT: u64 = config::get().sessions.escalation_lifetime;
+    pub static ref CAPTCHA_PROVIDER: String = config::get().captcha.provider.clone();
+    pub static ref CAPTCHA_SECRET: Option<String> = config::get().captcha.secret.clone();
+    pub static ref CAPTCHA_VERIFY_URL: Option<String> = config::get().captcha.verify_url.clone();
+    pub static ref CAPTCHA_MIN_SCORE: f64 = config::get().captcha.min_score;
+    pub static ref POW_DIFFICULTY: u32 = config::get().captcha.pow_difficulty;
+    pub static ref POW_MAX_DIFFICULTY: u32 = config::get().captcha.pow_max_difficulty;
+    pub static ref CAPTCHA_ON_FORGOT: bool = config::get().captcha.on_forgot;
+    pub static ref CAPTCHA_LOGIN_THRESHOLD: u64 = config::get().captcha.login_threshold;
+    pub static ref CORS_ORIGINS: Vec<String> = config::get().server.cors_origins.clone();
+    pub static ref HOST: String = config::get().server.host.clone();
+    pub static ref SMTP_USERNAME: Option<String> =
+        config::get().smtp.as_ref().and_then(|s| s.username.clone());
+    pub static ref SMTP_PASSWORD: Option<String> =
+        config::get().smtp.as_ref().and_then(|s| s.password.clone());
+    pub static ref SMTP_SERVER: Option<String> = config::get().smtp.as_ref().map(|s| s.server.clone());
+    pub static ref SMTP_FROM: Option<String> = config::get().smtp.as_ref().and_then(|s| s.from.clone());
+    pub static ref SMTP_PORT: Option<u16> = config::get().smtp.as_ref().and_then(|s| s.port);
+    pub static ref SMTP_TLS: Option<String> = config::get().smtp.as_ref().and_then(|s| s.tls.clone());
+    pub static ref DKIM_SELECTOR: Option<String> =
+        config::get().dkim.as_ref().map(|d| d.selector.clone());
+    pub static ref DKIM_DOMAIN: Option<String> = config::get().dkim.as_ref().map(|d| d.domain.clone());
+    pub static ref DKIM_PRIVATE_KEY_FILE: Option<String> =
+        config::get().dkim.as_ref().map(|d| d.private_key_file.clone());
+    pub static ref DKIM_ALGORITHM: Option<String> =
+        config::get().dkim.as_ref().and_then(|d| d.algorithm.clone());
+    pub static ref MAIL_TRANSPORT: Option<String> = config::get().mail.transport.clone();
+    pub static ref MAIL_FILE_DIRECTORY: String = config::get().mail.file_directory.clone();
+    pub static ref EMAIL_TEMPLATES_DIR: Option<String> = config::get().mail.templates_dir.clone();
+    pub static ref PUBLIC_ROOT: String = config::get().server.public_root.clone();
+    pub static ref SERVICE_NAME: String = config::get().server.service_name.clone();
+    pub static ref RP_ID: String = config::get().server.rp_id.clone();
+    pub static ref REGISTRATION_MODE: Option<String> = config::get().registration.mode.clone();
+    pub static ref REGISTRATION_ALLOWED_DOMAINS: Vec<String> =
+        config::get().registration.allowed_domains.clone();
+    pub static ref REGISTRATION_DENIED_DOMAINS: Vec<String> =
+        config::get().registration.denied_domains.clone();
+    pub static ref DISPOSABLE_DOMAINS_FILE: Option<String> =
+        config::get().registration.disposable_domains_file.clone();
+    pub static ref REGISTRATION_APPROVAL: bool = config::get().registration.approval;
+    // lets every user create single-use invites, not just administrators
+    pub static ref USER_INVITES: bool = config::get().registration.user_invites;
+    pub static ref RESERVED_USERNAMES: Vec<String> = config::get().usernames.reserved.clone();
+    pub static ref USERNAME_CHANGE_COOLDOWN_DAYS: u64 = config::get().usernames.change_cooldown_days;
+    pub static ref USERNAME_HOLD_DAYS: u64 = config::get().usernames.hold_days;
+    // services, as passed to /validate, that reject users with an unverified email
+    pub static ref UNVERIFIED_BLOCKED_SERVICES: Vec<String> =
+        config::get().accounts.unverified_blocked_services.clone();
+    pub static ref RESET_DELAY_HOURS: u64 = config::get().accounts.reset_delay_hours;
+    pub static ref SECURITY_EVENT_RETENTION_DAYS: u64 =
+        config::get().accounts.security_event_retention_days;
+    // `id` and `secret` of every resource server that may introspect tokens
+    pub static ref INTROSPECTION_CLIENTS: Vec<(String, String)> = config::get()
+        .introspection
+        .clients
+        .iter()
+        .map(|client| (client.id.clone(), client.secret.clone()))
         .collect();
-    pub static ref HOST: String = env::var("HOST").expect("HOST must be set");
-    pub static ref SMTP_USERNAME: Option<String> = env::var("SMTP_USERNAME").ok();
-    pub static ref SMTP_PASSWORD: Option<String> = env::var("SMTP_PASSWORD").ok();
-    pub static ref SMTP_SERVER: Option<String> = env::var("SMTP_SERVER").ok();
-    pub static ref SMTP_FROM: Option<String> = env::var("SMTP_FROM").ok();
-    pub static ref SMTP_ENABLED: bool = SMTP_USERNAME.is_some()
-        && SMTP_PASSWORD.is_some()
-        && SMTP_SERVER.is_some()
-        && SMTP_FROM.is_some();
-    pub static ref PUBLIC_ROOT: String = env::var("PUBLIC_ROOT").expect("PUBLIC_ROOT must be set");
-    pub static ref SERVICE_NAME: String =
-        env::var("SERVICE_NAME").expect("SERVICE_NAME must be set");
-    pub static ref RP_ID: String = env::var("RP_ID").expect("RP_ID must be set");
+    pub static ref INTROSPECTION_CACHE_SECONDS: u64 = config::get().introspection.cache_seconds;
 }
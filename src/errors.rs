@@ -3,37 +3,74 @@ use std::fmt::Display;
 use actix_web::ResponseError;
 use base64::DecodeError;
 use log::error;
+use mongodb::error::{ErrorKind, WriteFailure};
 use opaque_ke::errors::ProtocolError;
 use serde::{Deserialize, Serialize};
 use webauthn_rs::prelude::WebauthnError;
 
+use crate::constants::{
+    EMAIL_INDEX, SERVICE_ACCOUNT_NAME_INDEX, USERNAME_INDEX, USERNAME_KEY_INDEX,
+};
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "error", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Error {
     MissingToken,
     InvalidToken,
+    // a resource server failed to authenticate, see `routes::introspect`
+    InvalidClient,
+    // METRICS_TOKEN isn't set, so /metrics is turned off
+    MetricsUnavailable,
 
     DatabaseError,
 
     InvalidUsername,
     UsernameAlreadyTaken,
+    UsernameReserved,
+    UsernameChangeCooldown {
+        until: u64,
+    },
     UserNotFound,
     UserExists,
     UserMismatch,
+    MissingPermission,
+    AccountPendingApproval,
+    InvalidScope,
+    InvalidName,
+    ServiceAccountExists,
+    ServiceAccountNotFound,
+    TokenNotFound,
+    InvalidExpiry,
+
+    RegistrationClosed,
+    InviteRequired,
+    InvalidInvite,
+    EmailDomainNotAllowed,
 
     InvalidEmail,
+    EmailNotVerified,
+    EmailAlreadyVerified,
     DisplayNameTooLong,
     DescriptionTooLong,
     WebsiteTooLong,
+    UnsupportedLocale,
 
     CredentialError,
+    // the server setup is missing or can't be decrypted with OPAQUE_MASTER_KEY
+    ServerSetupUnavailable,
     IncorrectCode,
+    SecondFactorRequired,
+    ResetDelayed {
+        until: u64,
+    },
 
     SessionExpired,
 
     IpMissing,
 
     InvalidCaptcha,
+    CaptchaRequired,
+    ChallengeUnavailable,
     InternalCaptchaError,
 
     InternalEmailError,
@@ -48,6 +85,18 @@ pub enum Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    // The response body, with the id of the request that failed
+    pub fn to_json(&self, request_id: &str) -> serde_json::Value {
+        let mut body =
+            serde_json::to_value(self).expect("Unexpected error: failed to serialize error");
+        if let Some(body) = body.as_object_mut() {
+            body.insert("requestId".to_string(), request_id.into());
+        }
+        body
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         unimplemented!()
@@ -59,28 +108,53 @@ impl ResponseError for Error {
         match self {
             Error::MissingToken => actix_web::http::StatusCode::UNAUTHORIZED,
             Error::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::InvalidClient => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::MetricsUnavailable => actix_web::http::StatusCode::NOT_FOUND,
 
             Error::DatabaseError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::InvalidUsername => actix_web::http::StatusCode::BAD_REQUEST,
             Error::UsernameAlreadyTaken => actix_web::http::StatusCode::CONFLICT,
+            Error::UsernameReserved => actix_web::http::StatusCode::CONFLICT,
+            Error::UsernameChangeCooldown { .. } => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
             Error::UserNotFound => actix_web::http::StatusCode::NOT_FOUND,
             Error::UserExists => actix_web::http::StatusCode::CONFLICT,
             Error::UserMismatch => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::MissingPermission => actix_web::http::StatusCode::FORBIDDEN,
+            Error::AccountPendingApproval => actix_web::http::StatusCode::FORBIDDEN,
+            Error::InvalidScope => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidName => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::ServiceAccountExists => actix_web::http::StatusCode::CONFLICT,
+            Error::ServiceAccountNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::TokenNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            Error::InvalidExpiry => actix_web::http::StatusCode::BAD_REQUEST,
+
+            Error::RegistrationClosed => actix_web::http::StatusCode::FORBIDDEN,
+            Error::InviteRequired => actix_web::http::StatusCode::FORBIDDEN,
+            Error::InvalidInvite => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::EmailDomainNotAllowed => actix_web::http::StatusCode::FORBIDDEN,
 
             Error::InvalidEmail => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::EmailNotVerified => actix_web::http::StatusCode::FORBIDDEN,
+            Error::EmailAlreadyVerified => actix_web::http::StatusCode::CONFLICT,
             Error::DisplayNameTooLong => actix_web::http::StatusCode::BAD_REQUEST,
             Error::DescriptionTooLong => actix_web::http::StatusCode::BAD_REQUEST,
             Error::WebsiteTooLong => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::UnsupportedLocale => actix_web::http::StatusCode::BAD_REQUEST,
 
             Error::CredentialError => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::ServerSetupUnavailable => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             Error::IncorrectCode => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::SecondFactorRequired => actix_web::http::StatusCode::UNAUTHORIZED,
+            Error::ResetDelayed { .. } => actix_web::http::StatusCode::FORBIDDEN,
 
             Error::SessionExpired => actix_web::http::StatusCode::UNAUTHORIZED,
 
             Error::IpMissing => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::InvalidCaptcha => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::CaptchaRequired => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::ChallengeUnavailable => actix_web::http::StatusCode::METHOD_NOT_ALLOWED,
             Error::InternalCaptchaError => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
 
             Error::RateLimited { .. } => actix_web::http::StatusCode::TOO_MANY_REQUESTS,
@@ -91,7 +165,12 @@ impl ResponseError for Error {
     }
 
     fn error_response(&self) -> actix_web::HttpResponse {
-        actix_web::HttpResponse::build(self.status_code()).json(self)
+        let mut response = actix_web::HttpResponse::build(self.status_code());
+        // RFC 7617 challenge for clients using HTTP Basic authentication
+        if let Error::InvalidClient = self {
+            response.insert_header((actix_web::http::header::WWW_AUTHENTICATE, "Basic"));
+        }
+        response.json(self)
     }
 }
 
@@ -101,8 +180,37 @@ impl From<jsonwebtoken::errors::Error> for Error {
     }
 }
 
+// Unique index violations are conflicts rather than database failures
+fn duplicate_key_message(db: &mongodb::error::Error) -> Option<&str> {
+    match &*db.kind {
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000 => Some(&e.message),
+        ErrorKind::Command(e) if e.code == 11000 => Some(&e.message),
+        _ => None,
+    }
+}
+
+pub fn is_duplicate_key(db: &mongodb::error::Error) -> bool {
+    duplicate_key_message(db).is_some()
+}
+
+fn duplicate_key_error(db: &mongodb::error::Error) -> Option<Error> {
+    let message = duplicate_key_message(db)?;
+    if message.contains(EMAIL_INDEX) {
+        Some(Error::UserExists)
+    } else if message.contains(USERNAME_INDEX) || message.contains(USERNAME_KEY_INDEX) {
+        Some(Error::UsernameAlreadyTaken)
+    } else if message.contains(SERVICE_ACCOUNT_NAME_INDEX) {
+        Some(Error::ServiceAccountExists)
+    } else {
+        None
+    }
+}
+
 impl From<mongodb::error::Error> for Error {
     fn from(db: mongodb::error::Error) -> Self {
+        if let Some(e) = duplicate_key_error(&db) {
+            return e;
+        }
         error!("Database error: {}", db);
         Error::DatabaseError
     }
@@ -0,0 +1,23 @@
+#![allow(clippy::large_enum_variant)]
+pub mod authenticate;
+pub mod captcha;
+pub mod cleanup;
+pub mod cli;
+pub mod config;
+pub mod constants;
+pub mod database;
+pub mod environment;
+pub mod errors;
+pub mod logging;
+pub mod mail;
+pub mod metrics;
+pub mod migrations;
+pub mod notifications;
+pub mod opaque;
+pub mod passkey;
+pub mod registration;
+pub mod routes;
+pub mod store;
+pub mod templates;
+pub mod username;
+pub mod utilities;
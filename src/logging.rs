@@ -0,0 +1,255 @@
+// Structured logging. The `log` macros used across the crate are bridged into `tracing`, so every
+// line carries the spans it was logged in, such as the request and its id.
+
+use std::{
+    borrow::Cow,
+    future::{ready, Ready},
+    io::{self, Write},
+    rc::Rc,
+    time::Instant,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use regex::Regex;
+use tracing::{error, info, info_span, Instrument};
+use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
+use ulid::Ulid;
+
+use crate::{
+    config,
+    database::{personal_token, service_account},
+    errors::Error,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+lazy_static! {
+    static ref JWT_RE: Regex =
+        Regex::new(r"eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*").expect("Unexpected error: failed to process regex");
+    static ref API_KEY_RE: Regex = Regex::new(&format!(
+        r"(?:{}|{})[A-Za-z0-9]+",
+        regex::escape(personal_token::TOKEN_PREFIX),
+        regex::escape(service_account::KEY_PREFIX)
+    ))
+    .expect("Unexpected error: failed to process regex");
+    // reset and recovery links, also as quoted-printable in logged emails (`=3D`, soft line breaks)
+    static ref TOKEN_PARAM_RE: Regex =
+        Regex::new(r"([?&;]token=(?:3D)?)(?:[A-Za-z0-9_-]|=\r?\n)+").expect("Unexpected error: failed to process regex");
+    static ref URI_CREDENTIALS_RE: Regex =
+        Regex::new(r"(://[^:/@\s]+):[^@\s]+@").expect("Unexpected error: failed to process regex");
+    static ref REQUEST_ID_RE: Regex =
+        Regex::new(r"^[A-Za-z0-9._-]{1,128}$").expect("Unexpected error: failed to process regex");
+    // configured secrets that must never show up in a log line
+    static ref SECRETS: Vec<String> = {
+        let config = config::get();
+        [
+            Some(config.security.jwt_secret.clone()),
+            Some(hex::encode(&config.security.opaque_master_key)),
+            config.captcha.secret.clone(),
+            config.smtp.as_ref().and_then(|smtp| smtp.password.clone()),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            config
+                .introspection
+                .clients
+                .iter()
+                .map(|client| client.secret.clone()),
+        )
+        .filter(|secret| secret.len() >= 8)
+        .collect()
+    };
+}
+
+// Masks session tokens, API keys, token links, passwords in URIs and any of `secrets`
+pub fn redact<'a>(text: &'a str, secrets: &[String]) -> Cow<'a, str> {
+    let mut text = JWT_RE.replace_all(text, "[REDACTED]");
+    if API_KEY_RE.is_match(&text) {
+        text = Cow::Owned(API_KEY_RE.replace_all(&text, "[REDACTED]").into_owned());
+    }
+    if TOKEN_PARAM_RE.is_match(&text) {
+        text = Cow::Owned(
+            TOKEN_PARAM_RE
+                .replace_all(&text, "${1}[REDACTED]")
+                .into_owned(),
+        );
+    }
+    if URI_CREDENTIALS_RE.is_match(&text) {
+        text = Cow::Owned(
+            URI_CREDENTIALS_RE
+                .replace_all(&text, "$1:[REDACTED]@")
+                .into_owned(),
+        );
+    }
+    for secret in secrets {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), "[REDACTED]"));
+        }
+    }
+    text
+}
+
+// Every event is formatted before it's written in one go, so lines can be redacted as a whole
+pub struct RedactingWriter;
+
+impl Write for RedactingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        io::stderr().write_all(redact(&line, &SECRETS).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+pub struct Redacting;
+
+impl<'a> MakeWriter<'a> for Redacting {
+    type Writer = RedactingWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter
+    }
+}
+
+// RUST_LOG still overrides LOG_LEVEL, as it did before the configuration file
+pub fn init() {
+    let logging = &config::get().logging;
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&logging.level));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Redacting);
+    if logging.format == "json" {
+        builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .init();
+    } else {
+        builder.init();
+    }
+}
+
+// Takes the id from `X-Request-Id` when a proxy already assigned one
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| REQUEST_ID_RE.is_match(id))
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| Ulid::new().to_string())
+}
+
+// Error bodies gain a `requestId`, keeping headers such as the rate limit ones
+fn with_request_id<B>(res: ServiceResponse<B>, id: &str) -> ServiceResponse<EitherBody<B>> {
+    let Some(error) = res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<Error>())
+        .cloned()
+    else {
+        return res.map_into_left_body();
+    };
+    let (req, original) = res.into_parts();
+    let mut response = HttpResponse::build(original.status()).json(error.to_json(id));
+    for (name, value) in original.headers() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    ServiceResponse::new(req, response).map_into_right_body()
+}
+
+// Assigns every request an id, echoed in `X-Request-Id`, and logs it once it's handled
+pub struct RequestTracing;
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequestTracingMiddleware<S>;
+    type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: service.into(),
+        }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::result::Result<(), Self::Error>> {
+        self.service.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let id = request_id(&req);
+        let route = req.match_pattern().unwrap_or("unmatched".to_string());
+        let ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .unwrap_or_default()
+            .to_string();
+        let span = info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            route = %route,
+        );
+        let start = Instant::now();
+        Box::pin(
+            async move {
+                let res = svc.call(req).await?;
+                let status = res.status();
+                let duration_ms = start.elapsed().as_millis() as u64;
+                if status.is_server_error() {
+                    let cause = res.response().error();
+                    error!(status = status.as_u16(), duration_ms, %ip, ?cause, "Request failed");
+                } else {
+                    info!(status = status.as_u16(), duration_ms, %ip, "Request handled");
+                }
+                let mut res = with_request_id(res, &id);
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
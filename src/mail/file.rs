@@ -0,0 +1,31 @@
+use std::fs;
+
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncStd1Executor, AsyncTransport, Message};
+
+use super::MailTransport;
+
+// Writes every message to `<directory>/<id>.eml`, for development and tests
+pub struct FileTransport {
+    transport: AsyncFileTransport<AsyncStd1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(directory: &str) -> Self {
+        fs::create_dir_all(directory).expect("Failed to create MAIL_FILE_DIRECTORY");
+        Self {
+            transport: AsyncFileTransport::new(directory),
+        }
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    async fn send(&self, message: Message) -> Result<(), String> {
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
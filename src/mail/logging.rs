@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+use lettre::Message;
+use log::info;
+
+use super::MailTransport;
+
+// Prints every message to the log instead of delivering it, for development only. Token links
+// in the body are redacted like in any other log line
+pub struct LogTransport;
+
+#[async_trait]
+impl MailTransport for LogTransport {
+    async fn send(&self, message: Message) -> Result<(), String> {
+        let to = message
+            .envelope()
+            .to()
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(
+            "Email to {}:\n{}",
+            to,
+            String::from_utf8_lossy(&message.formatted())
+        );
+        Ok(())
+    }
+}
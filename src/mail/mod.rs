@@ -0,0 +1,67 @@
+pub mod file;
+pub mod logging;
+pub mod outbox;
+pub mod smtp;
+
+use async_trait::async_trait;
+use lettre::Message;
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+
+use crate::environment::{
+    MAIL_FILE_DIRECTORY, MAIL_TRANSPORT, SMTP_FROM, SMTP_SERVER, SMTP_USERNAME,
+};
+
+// Errors are returned as a description, which the outbox records for failed deliveries
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, message: Message) -> std::result::Result<(), String>;
+}
+
+static TRANSPORT: OnceCell<Option<Box<dyn MailTransport>>> = OnceCell::new();
+
+fn create_transport() -> Option<Box<dyn MailTransport>> {
+    match MAIL_TRANSPORT.as_deref() {
+        Some("file") => Some(Box::new(file::FileTransport::new(&MAIL_FILE_DIRECTORY))),
+        Some("log") => Some(Box::new(logging::LogTransport)),
+        Some("smtp") | None => {
+            let server = SMTP_SERVER.as_ref()?;
+            SMTP_FROM.as_ref()?;
+            Some(Box::new(smtp::SmtpTransport::new(server)))
+        }
+        Some("none") => None,
+        Some(other) => panic!("Unknown MAIL_TRANSPORT: {}", other),
+    }
+}
+
+pub fn init() {
+    match get_transport() {
+        Some(_) => info!(
+            "Sending email with the {} transport",
+            MAIL_TRANSPORT.as_deref().unwrap_or("smtp")
+        ),
+        None => info!("Email is disabled"),
+    }
+    // email used to stay off without credentials, so say so in case they were left out by accident
+    if matches!(MAIL_TRANSPORT.as_deref(), Some("smtp") | None)
+        && is_enabled()
+        && SMTP_USERNAME.is_none()
+    {
+        warn!("SMTP_USERNAME and SMTP_PASSWORD are not set, sending email without authenticating");
+    }
+}
+
+pub fn get_transport() -> Option<&'static dyn MailTransport> {
+    TRANSPORT.get_or_init(create_transport).as_deref()
+}
+
+pub fn is_enabled() -> bool {
+    get_transport().is_some()
+}
+
+// Development transports don't need a real sender address
+pub fn get_from_address() -> String {
+    SMTP_FROM
+        .clone()
+        .unwrap_or_else(|| "noreply@localhost".to_string())
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    future::timeout,
+};
+use lazy_static::lazy_static;
+use lettre::{message::MultiPart, Message};
+use log::{error, info, warn};
+use ulid::Ulid;
+
+use crate::{
+    constants::{OUTBOX_CLAIM_TIMEOUT, OUTBOX_MAX_ATTEMPTS, OUTBOX_RETENTION},
+    database::outbox::{OutboxEmail, OutboxStatus},
+    errors::{Error, Result},
+    metrics,
+    store::{Delivery, OutboxStore},
+    templates::Email,
+    utilities::get_time_secs,
+};
+
+use super::{get_from_address, get_transport};
+
+lazy_static! {
+    // wakes the worker as soon as an email is queued rather than on its next run
+    static ref QUEUED: (Sender<()>, Receiver<()>) = channel::bounded(1);
+}
+
+pub async fn enqueue(outbox: &dyn OutboxStore, to: String, email: Email) -> Result<()> {
+    let now = get_time_secs();
+    outbox
+        .enqueue(OutboxEmail {
+            id: Ulid::new().to_string(),
+            to,
+            subject: email.subject,
+            text: email.text,
+            html: email.html,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            claimed_at: None,
+            last_error: None,
+            created_at: now,
+            sent_at: None,
+        })
+        .await?;
+    QUEUED.0.try_send(()).ok();
+    Ok(())
+}
+
+// Returns once an email is queued or the interval is over
+pub async fn wait(interval: Duration) {
+    timeout(interval, QUEUED.1.recv()).await.ok();
+}
+
+async fn claim(outbox: &dyn OutboxStore, ignore_backoff: bool) -> Result<Option<OutboxEmail>> {
+    let now = get_time_secs();
+    outbox
+        .claim(
+            now,
+            now.saturating_sub(OUTBOX_CLAIM_TIMEOUT),
+            ignore_backoff,
+        )
+        .await
+}
+
+fn build_message(email: &OutboxEmail) -> std::result::Result<Message, String> {
+    Message::builder()
+        .from(
+            get_from_address()
+                .parse()
+                .map_err(|e| format!("invalid sender address: {}", e))?,
+        )
+        .to(email
+            .to
+            .parse()
+            .map_err(|e| format!("invalid recipient address: {}", e))?)
+        .subject(email.subject.clone())
+        .multipart(MultiPart::alternative_plain_html(
+            email.text.clone(),
+            email.html.clone(),
+        ))
+        .map_err(|e| format!("failed to build message: {}", e))
+}
+
+// Returns whether the email was sent
+async fn deliver(outbox: &dyn OutboxStore, email: OutboxEmail) -> Result<bool> {
+    let Some(transport) = get_transport() else {
+        return Err(Error::EmailMisconfigured);
+    };
+    let result = match build_message(&email) {
+        Ok(message) => transport.send(message).await,
+        Err(e) => Err(e),
+    };
+    let now = get_time_secs();
+    let sent = result.is_ok();
+    let delivery = match result {
+        Ok(()) => {
+            metrics::email("sent");
+            Delivery::Sent
+        }
+        Err(e) => {
+            let attempts = email.attempts + 1;
+            if attempts >= OUTBOX_MAX_ATTEMPTS {
+                error!(
+                    "Giving up on email {} after {} attempts: {}",
+                    email.id, attempts, e
+                );
+                metrics::email("failed");
+                Delivery::Failed { error: e }
+            } else {
+                // 30 seconds, doubling each time, up to an hour
+                let backoff = (30u64 << attempts.min(7)).min(3600);
+                warn!(
+                    "Failed to send email {} (attempt {}), retrying in {}s: {}",
+                    email.id, attempts, backoff, e
+                );
+                metrics::email("retry");
+                Delivery::Retry {
+                    error: e,
+                    next_attempt_at: now + backoff,
+                }
+            }
+        }
+    };
+    outbox.finish(&email.id, delivery, now).await?;
+    Ok(sent)
+}
+
+pub async fn process(outbox: &dyn OutboxStore) {
+    if get_transport().is_none() {
+        return;
+    }
+    while let Ok(Some(email)) = claim(outbox, false).await {
+        if deliver(outbox, email).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Tries the queued emails regardless of backoff before the process exits. A failed email
+// would be claimed again straight away, so the first failure leaves the rest queued
+pub async fn drain(outbox: &dyn OutboxStore) {
+    if get_transport().is_none() {
+        return;
+    }
+    let result = timeout(Duration::from_secs(30), async {
+        let mut delivered = 0;
+        while let Ok(Some(email)) = claim(outbox, true).await {
+            match deliver(outbox, email).await {
+                Ok(true) => delivered += 1,
+                Ok(false) => {
+                    warn!("Stopped draining the email queue after a failed delivery");
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        delivered
+    })
+    .await;
+    match result {
+        Ok(delivered) => info!("Sent {} queued email(s) before shutdown", delivered),
+        Err(_) => warn!("Timed out while draining the email queue"),
+    }
+}
+
+// Failed emails give up within a few hours, so they are kept as long as sent ones
+pub async fn purge_finished(outbox: &dyn OutboxStore) -> Result<u64> {
+    outbox
+        .purge_finished(get_time_secs().saturating_sub(OUTBOX_RETENTION))
+        .await
+}
@@ -0,0 +1,88 @@
+use std::fs;
+
+use async_trait::async_trait;
+use lettre::{
+    message::dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+    AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message,
+};
+
+use crate::environment::{
+    DKIM_ALGORITHM, DKIM_DOMAIN, DKIM_PRIVATE_KEY_FILE, DKIM_SELECTOR, SMTP_PASSWORD, SMTP_PORT,
+    SMTP_TLS, SMTP_USERNAME,
+};
+
+use super::MailTransport;
+
+// The underlying transport keeps a pool of connections, so it is built once and reused
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<AsyncStd1Executor>,
+    dkim: Option<DkimConfig>,
+}
+
+impl SmtpTransport {
+    pub fn new(server: &str) -> Self {
+        let mut builder = match SMTP_TLS.as_deref() {
+            Some("implicit") | None => {
+                AsyncSmtpTransport::<AsyncStd1Executor>::relay(server).expect("Invalid SMTP_SERVER")
+            }
+            Some("starttls") => AsyncSmtpTransport::<AsyncStd1Executor>::starttls_relay(server)
+                .expect("Invalid SMTP_SERVER"),
+            Some("opportunistic") => AsyncSmtpTransport::<AsyncStd1Executor>::builder_dangerous(
+                server,
+            )
+            .tls(Tls::Opportunistic(
+                TlsParameters::new(server.to_string()).expect("Invalid SMTP_SERVER"),
+            )),
+            Some("none") => AsyncSmtpTransport::<AsyncStd1Executor>::builder_dangerous(server),
+            Some(other) => panic!("Unknown SMTP_TLS: {}", other),
+        };
+        if let Some(port) = *SMTP_PORT {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&*SMTP_USERNAME, &*SMTP_PASSWORD) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Self {
+            transport: builder.build(),
+            dkim: create_dkim_config(),
+        }
+    }
+}
+
+fn create_dkim_config() -> Option<DkimConfig> {
+    let (Some(selector), Some(domain), Some(key_file)) =
+        (&*DKIM_SELECTOR, &*DKIM_DOMAIN, &*DKIM_PRIVATE_KEY_FILE)
+    else {
+        return None;
+    };
+    let algorithm = match DKIM_ALGORITHM.as_deref() {
+        Some("rsa") | None => DkimSigningAlgorithm::Rsa,
+        Some("ed25519") => DkimSigningAlgorithm::Ed25519,
+        Some(other) => panic!("Unknown DKIM_ALGORITHM: {}", other),
+    };
+    let key = fs::read_to_string(key_file).expect("Failed to read DKIM_PRIVATE_KEY_FILE");
+    let key = DkimSigningKey::new(key.trim(), algorithm).expect("Invalid DKIM private key");
+    Some(DkimConfig::default_config(
+        selector.clone(),
+        domain.clone(),
+        key,
+    ))
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, mut message: Message) -> Result<(), String> {
+        if let Some(dkim) = &self.dkim {
+            message.sign(dkim);
+        }
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
@@ -1,54 +1,110 @@
-#![allow(clippy::large_enum_variant)]
-use std::time::Duration;
-
 use actix_cors::Cors;
 use actix_files::{Files, NamedFile};
 use actix_web::{
     dev::{ServiceRequest, ServiceResponse},
-    middleware::Logger,
-    web, App, HttpServer,
+    App, HttpServer,
 };
 use async_std::task;
 use log::info;
-use passkey::create_webauthn;
 
-use crate::{
-    authenticate::JwtAuthentication,
-    environment::{CORS_ORIGINS, HOST},
-    utilities::{create_rate_limiter, create_success_rate_limiter},
+use account_services::{
+    captcha, cleanup, cli, config, database,
+    environment::{CORS_ORIGINS, HOST, MIGRATE_ON_STARTUP},
+    logging, mail, migrations, opaque, registration, routes, store, templates,
 };
 
-pub mod authenticate;
-pub mod cleanup;
-pub mod constants;
-pub mod database;
-pub mod environment;
-pub mod errors;
-pub mod opaque;
-pub mod passkey;
-pub mod routes;
-pub mod utilities;
-
 #[async_std::main]
 async fn main() {
     dotenvy::dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    config::init();
+    logging::init();
 
     info!("Nextflow SSO system version {}", env!("CARGO_PKG_VERSION"));
-    info!("Connecting to MongoDB...");
-    database::connect().await;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        let result = match cli::parse(&args) {
+            Ok(command) => {
+                if command.uses_database() && store::uses_mongo() {
+                    info!("Connecting to MongoDB...");
+                    database::connect().await;
+                }
+                cli::execute(&store::create(), command).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                println!("{}", cli::failure(e));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    templates::load();
+    mail::init();
+    captcha::init();
+    registration::load();
+    if store::uses_mongo() {
+        info!("Connecting to MongoDB...");
+        database::connect().await;
+        if *MIGRATE_ON_STARTUP {
+            migrations::run(false)
+                .await
+                .expect("Failed to migrate the database");
+        } else {
+            let current = migrations::current_version()
+                .await
+                .expect("Failed to read the schema version");
+            if current < migrations::latest_version() {
+                panic!(
+                    "Database schema is at version {}, expected {}; run the migrate command first",
+                    current,
+                    migrations::latest_version()
+                );
+            }
+        }
+        database::indexes::ensure().await;
+    }
+    let stores = store::create();
 
     info!("Spawning task to clean up expired entities...");
-    task::spawn(async {
+    let cleanup_stores = stores.clone();
+    task::spawn(async move {
         loop {
             task::sleep(std::time::Duration::from_secs(60)).await;
             task::spawn(async { cleanup::run() });
+            let stores = cleanup_stores.clone();
+            task::spawn(async move { cleanup::purge(&stores).await });
+        }
+    });
+
+    info!("Spawning task to deliver queued emails...");
+    let outbox = stores.outbox.clone();
+    task::spawn(async move {
+        loop {
+            mail::outbox::process(outbox.as_ref()).await;
+            mail::outbox::wait(std::time::Duration::from_secs(10)).await;
         }
     });
 
+    // refuse to start rather than fail every login
+    let settings = stores
+        .settings
+        .get()
+        .await
+        .expect("The OPAQUE server setup is unavailable");
+    opaque::verify_settings(&settings)
+        .expect("The OPAQUE server setup can't be used with this OPAQUE_MASTER_KEY");
+
+    let outbox = stores.outbox.clone();
+    let route_state = routes::RouteState::default();
     info!("Starting server on {}...", *HOST);
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
+            .configure(|cfg| stores.configure(cfg))
             .wrap(
                 Cors::default()
                     .allowed_origin_fn(|_, head| {
@@ -66,74 +122,10 @@ async fn main() {
                     })
                     .allow_any_method()
                     .allow_any_header()
+                    .expose_headers([logging::REQUEST_ID_HEADER])
                     .supports_credentials(),
             )
-            .wrap(Logger::default())
-            .service(
-                web::scope("/api")
-                    .app_data(create_webauthn())
-                    .wrap(create_rate_limiter(Duration::from_secs(5), 20))
-                    .wrap(JwtAuthentication)
-                    .route("/", web::get().to(routes::service::handle))
-                    .route(
-                        "/forgot",
-                        web::post()
-                            .to(routes::forgot::handle)
-                            .wrap(create_success_rate_limiter(Duration::from_secs(21600), 10)),
-                    )
-                    .route("/user", web::patch().to(routes::account_settings::handle))
-                    .route("/user", web::get().to(routes::current_user::handle))
-                    .route("/user", web::delete().to(routes::delete::handle))
-                    .route("/ip", web::get().to(routes::ip::handle))
-                    .route("/session", web::get().to(routes::session::handle))
-                    .route(
-                        "/session",
-                        web::post()
-                            .to(routes::login::handle)
-                            .wrap(create_success_rate_limiter(Duration::from_secs(20), 5)),
-                    )
-                    .route("/session", web::delete().to(routes::logout::handle))
-                    .route(
-                        "/session/{id}",
-                        web::delete().to(routes::logout_other::handle),
-                    )
-                    .route("/session/all", web::delete().to(routes::logout_all::handle))
-                    .route("/user/mfa", web::patch().to(routes::mfa::handle))
-                    .route(
-                        "/user/profile",
-                        web::patch().to(routes::profile_settings::handle),
-                    )
-                    .route(
-                        "/user",
-                        web::post()
-                            .to(routes::register::handle)
-                            .wrap(create_success_rate_limiter(Duration::from_secs(21600), 5)),
-                    ) // 6 hours
-                    .route(
-                        "/user/passkeys",
-                        web::post().to(routes::register_passkey::handle),
-                    )
-                    .route(
-                        "/user/passkeys/{id}",
-                        web::delete().to(routes::delete_passkey::handle),
-                    )
-                    .route("/user/passkeys", web::get().to(routes::get_passkey::handle))
-                    .route(
-                        "/user/password",
-                        web::patch().to(routes::update_password::handle),
-                    )
-                    .route("/user/{id}", web::get().to(routes::user::handle))
-                    .route(
-                        "/session/passkeys",
-                        web::post().to(routes::login_passkey::handle),
-                    )
-                    .route(
-                        "/validate",
-                        web::post()
-                            .to(routes::validate::handle)
-                            .wrap(create_success_rate_limiter(Duration::from_secs(5), 10)),
-                    ),
-            )
+            .configure(|cfg| routes::configure(cfg, &route_state))
             .service(
                 Files::new("/", "bundle")
                     .index_file("index.html")
@@ -150,4 +142,7 @@ async fn main() {
     .run()
     .await
     .expect("Failed to start server");
+
+    info!("Server stopped, delivering queued emails...");
+    mail::outbox::drain(outbox.as_ref()).await;
 }
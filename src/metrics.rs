@@ -0,0 +1,203 @@
+// Prometheus metrics, served in the text format by `GET /metrics`
+
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    time::Instant,
+};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+
+use crate::routes::{
+    forgot, login, login_passkey, mfa, register, register_passkey, update_password, verify_email,
+};
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "Requests handled, by route pattern and status",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to handle requests, by route pattern",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "logins_total",
+        "Finished logins by method (password or passkey) and outcome",
+        &["method", "outcome"]
+    )
+    .unwrap();
+    static ref REGISTRATIONS: IntCounterVec = register_int_counter_vec!(
+        "registrations_total",
+        "Finished registrations by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref MFA_CHALLENGES: IntCounterVec = register_int_counter_vec!(
+        "mfa_challenges_total",
+        "Second factor codes entered during login, by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref EMAILS: IntCounterVec = register_int_counter_vec!(
+        "emails_total",
+        "Delivery attempts from the email outbox, by outcome (sent, retry or failed)",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+        "rate_limited_requests_total",
+        "Requests rejected by a rate limiter",
+        &["limiter"]
+    )
+    .unwrap();
+    static ref PENDING_FLOWS: IntGaugeVec = register_int_gauge_vec!(
+        "pending_flows",
+        "Multi-step flows waiting for their next request",
+        &["flow"]
+    )
+    .unwrap();
+}
+
+fn outcome(succeeded: bool) -> &'static str {
+    if succeeded {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+pub fn login(method: &str, succeeded: bool) {
+    LOGINS
+        .with_label_values(&[method, outcome(succeeded)])
+        .inc();
+}
+
+pub fn registration(succeeded: bool) {
+    REGISTRATIONS.with_label_values(&[outcome(succeeded)]).inc();
+}
+
+pub fn mfa(succeeded: bool) {
+    MFA_CHALLENGES
+        .with_label_values(&[outcome(succeeded)])
+        .inc();
+}
+
+pub fn email(outcome: &str) {
+    EMAILS.with_label_values(&[outcome]).inc();
+}
+
+pub fn rate_limited(limiter: &str) {
+    RATE_LIMITED.with_label_values(&[limiter]).inc();
+}
+
+// The maps only live in memory, so their sizes are read when scraped
+fn update_pending_flows() {
+    let flows = [
+        ("login", login::PENDING_LOGINS.len()),
+        ("login_mfa", login::PENDING_MFAS.len()),
+        ("password_upgrade", login::PENDING_UPGRADES.len()),
+        ("passkey_login", login_passkey::PENDING_LOGINS.len()),
+        ("register_email", register::PENDING_REGISTERS1.len()),
+        ("register_password", register::PENDING_REGISTERS2.len()),
+        (
+            "passkey_register",
+            register_passkey::PENDING_REGISTERS.len(),
+        ),
+        ("forgot_email", forgot::PENDING_FORGOTS1.len()),
+        ("forgot_password", forgot::PENDING_FORGOTS2.len()),
+        ("password_update", update_password::PENDING_UPDATES.len()),
+        ("mfa_setup", mfa::PENDING_MFA_SETUPS.len()),
+        (
+            "email_verification",
+            verify_email::PENDING_VERIFICATIONS.len(),
+        ),
+    ];
+    for (flow, size) in flows {
+        PENDING_FLOWS.with_label_values(&[flow]).set(size as i64);
+    }
+}
+
+pub fn render() -> String {
+    update_pending_flows();
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Unexpected error: failed to encode metrics");
+    String::from_utf8(buffer).expect("Unexpected error: metrics are not UTF-8")
+}
+
+// Counts and times every request by the route it matched, not the path, to keep ids out of labels
+pub struct RequestMetrics;
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: service.into(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::result::Result<(), Self::Error>> {
+        self.service.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let method = req.method().to_string();
+        let route = req.match_pattern().unwrap_or("unmatched".to_string());
+        let start = Instant::now();
+        Box::pin(async move {
+            let result = svc.call(req).await;
+            let status = match &result {
+                Ok(response) => response.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            HTTP_REQUESTS
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            HTTP_DURATION
+                .with_label_values(&[&method, &route])
+                .observe(start.elapsed().as_secs_f64());
+            result
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use mongodb::bson::Bson;
+
+use super::{backfill, Migration};
+use crate::{database::user, errors::Result};
+
+// Fields added to users after the first release
+pub struct UserDefaults;
+
+#[async_trait]
+impl Migration for UserDefaults {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "user_defaults"
+    }
+
+    async fn up(&self, dry_run: bool) -> Result<u64> {
+        let collection = user::get_collection();
+        let mut changed = 0;
+        changed += backfill(&collection, "security_notifications", true, dry_run).await?;
+        changed += backfill(&collection, "locale", Bson::Null, dry_run).await?;
+        // there's no telling whether older accounts confirmed their address
+        changed += backfill(&collection, "email_verified", false, dry_run).await?;
+        changed += backfill(&collection, "email_verified_at", Bson::Null, dry_run).await?;
+        changed += backfill(&collection, "pending_approval", false, dry_run).await?;
+        changed += backfill(&collection, "username_changed_at", Bson::Null, dry_run).await?;
+        Ok(changed)
+    }
+}
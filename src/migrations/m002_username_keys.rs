@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use log::warn;
+use mongodb::bson::{doc, Document};
+
+use super::Migration;
+use crate::{
+    database::user,
+    errors::{is_duplicate_key, Result},
+    username::normalize_key,
+};
+
+// Usernames registered before confusable detection have no key
+pub struct UsernameKeys;
+
+#[async_trait]
+impl Migration for UsernameKeys {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "username_keys"
+    }
+
+    async fn up(&self, dry_run: bool) -> Result<u64> {
+        let collection = user::get_collection().clone_with_type::<Document>();
+        // the unique index may not exist yet, so collisions are found here rather than by insert
+        let mut taken = collection
+            .distinct("username_key", doc! { "username_key": { "$gt": "" } })
+            .await?
+            .into_iter()
+            .filter_map(|key| key.as_str().map(String::from))
+            .collect::<HashSet<_>>();
+        let mut cursor = collection
+            .find(doc! {
+                "$or": [
+                    { "username_key": { "$exists": false } },
+                    { "username_key": "" }
+                ]
+            })
+            .projection(doc! { "id": 1, "username": 1 })
+            .sort(doc! { "id": 1 })
+            .await?;
+        let mut changed = 0;
+        while let Some(user) = cursor.next().await {
+            let user = user?;
+            let (Ok(id), Ok(username)) = (user.get_str("id"), user.get_str("username")) else {
+                continue;
+            };
+            let key = normalize_key(username);
+            // two existing usernames look alike, the later one keeps working without a key
+            if !taken.insert(key.clone()) {
+                warn!(
+                    "Username {} of user {} is confusable with another",
+                    username, id
+                );
+                continue;
+            }
+            if dry_run {
+                changed += 1;
+                continue;
+            }
+            let result = collection
+                .update_one(doc! { "id": id }, doc! { "$set": { "username_key": key } })
+                .await;
+            match result {
+                Ok(_) => changed += 1,
+                // taken by an account registered while the migration ran
+                Err(e) if is_duplicate_key(&e) => {
+                    warn!(
+                        "Username {} of user {} is confusable with another",
+                        username, id
+                    )
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+use mongodb::bson::Bson;
+
+use super::{backfill, Migration};
+use crate::{database::profile, errors::Result};
+
+// Profiles created by early clients only had a display name
+pub struct ProfileDefaults;
+
+#[async_trait]
+impl Migration for ProfileDefaults {
+    fn version(&self) -> u32 {
+        3
+    }
+
+    fn name(&self) -> &'static str {
+        "profile_defaults"
+    }
+
+    async fn up(&self, dry_run: bool) -> Result<u64> {
+        let collection = profile::get_collection();
+        let mut changed = 0;
+        changed += backfill(&collection, "description", "", dry_run).await?;
+        changed += backfill(&collection, "website", "", dry_run).await?;
+        changed += backfill(&collection, "avatar", Bson::Null, dry_run).await?;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+use mongodb::bson::{doc, Bson};
+
+use super::{backfill, Migration};
+use crate::{database::user, errors::Result};
+
+// Passwords registered before cipher suites were versioned, upgraded on their next login
+pub struct PasswordSuites;
+
+#[async_trait]
+impl Migration for PasswordSuites {
+    fn version(&self) -> u32 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "password_suites"
+    }
+
+    async fn up(&self, dry_run: bool) -> Result<u64> {
+        let legacy = doc! { "version": 1, "ksf": Bson::Null };
+        backfill(&user::get_collection(), "password_suite", legacy, dry_run).await
+    }
+}
@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use mongodb::bson::{self, doc, Bson};
+use serde::Deserialize;
+
+use super::Migration;
+use crate::{database::settings, errors::Result, opaque::seal_existing_server_setup};
+
+// The server setup used to be stored in plaintext, it's now encrypted with OPAQUE_MASTER_KEY
+pub struct SealedServerSetup;
+
+#[derive(Deserialize)]
+struct PlaintextSettings {
+    #[serde(rename = "_id")]
+    id: Bson,
+    opaque_server_setup: Vec<u8>,
+}
+
+#[async_trait]
+impl Migration for SealedServerSetup {
+    fn version(&self) -> u32 {
+        5
+    }
+
+    fn name(&self) -> &'static str {
+        "sealed_server_setup"
+    }
+
+    async fn up(&self, dry_run: bool) -> Result<u64> {
+        let collection = settings::get_collection().clone_with_type::<PlaintextSettings>();
+        let filter = doc! { "opaque_server_setup": { "$exists": true } };
+        if dry_run {
+            return Ok(collection.count_documents(filter).await?);
+        }
+        let Some(plaintext) = collection.find_one(filter).await? else {
+            return Ok(0);
+        };
+        let sealed = bson::to_bson(&vec![seal_existing_server_setup(
+            plaintext.opaque_server_setup,
+        )])
+        .expect("Unexpected error: failed to serialize server setup");
+        collection
+            .update_one(
+                doc! { "_id": plaintext.id },
+                doc! {
+                    "$set": { "server_setups": sealed, "current_server_setup": 0 },
+                    "$unset": { "opaque_server_setup": "" },
+                },
+            )
+            .await?;
+        Ok(1)
+    }
+}
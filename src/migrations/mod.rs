@@ -0,0 +1,194 @@
+mod m001_user_defaults;
+mod m002_username_keys;
+mod m003_profile_defaults;
+mod m004_password_suites;
+mod m005_sealed_server_setup;
+
+use std::time::Duration;
+
+use async_std::task;
+use async_trait::async_trait;
+use log::{info, warn};
+use mongodb::{
+    bson::{doc, Bson},
+    Collection,
+};
+use ulid::Ulid;
+
+use crate::{
+    constants::MIGRATION_LOCK_TIMEOUT,
+    database::settings::{get_schema_collection, SCHEMA_ID},
+    errors::{is_duplicate_key, Result},
+    utilities::get_time_secs,
+};
+
+// Migrations have to be idempotent, a replica may die halfway through one and it runs again
+#[async_trait]
+pub trait Migration: Send + Sync {
+    fn version(&self) -> u32;
+    fn name(&self) -> &'static str;
+    // returns the number of documents changed, or that would be changed on a dry run
+    async fn up(&self, dry_run: bool) -> Result<u64>;
+}
+
+// Ordered by version, append new migrations at the end
+fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(m001_user_defaults::UserDefaults),
+        Box::new(m002_username_keys::UsernameKeys),
+        Box::new(m003_profile_defaults::ProfileDefaults),
+        Box::new(m004_password_suites::PasswordSuites),
+        Box::new(m005_sealed_server_setup::SealedServerSetup),
+    ]
+}
+
+pub fn latest_version() -> u32 {
+    migrations().last().map(|m| m.version()).unwrap_or(0)
+}
+
+pub async fn current_version() -> Result<u32> {
+    Ok(get_schema_collection()
+        .find_one(doc! { "_id": SCHEMA_ID })
+        .await?
+        .map(|state| state.version)
+        .unwrap_or(0))
+}
+
+// Sets `field` to `value` on every document that doesn't have it yet
+pub(crate) async fn backfill<T: Send + Sync>(
+    collection: &Collection<T>,
+    field: &str,
+    value: impl Into<Bson>,
+    dry_run: bool,
+) -> Result<u64> {
+    let filter = doc! { field: { "$exists": false } };
+    if dry_run {
+        return Ok(collection.count_documents(filter).await?);
+    }
+    let result = collection
+        .update_many(filter, doc! { "$set": { field: value.into() } })
+        .await?;
+    Ok(result.modified_count)
+}
+
+async fn try_lock(instance: &str) -> Result<bool> {
+    let now = get_time_secs();
+    let result = get_schema_collection()
+        .update_one(
+            doc! {
+                "_id": SCHEMA_ID,
+                "$or": [
+                    { "locked_until": null },
+                    { "locked_until": { "$lt": now as i64 } }
+                ]
+            },
+            doc! {
+                "$set": {
+                    "locked_by": instance,
+                    "locked_until": (now + MIGRATION_LOCK_TIMEOUT) as i64
+                },
+                "$setOnInsert": {
+                    "version": 0
+                }
+            },
+        )
+        .upsert(true)
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        // the document exists but another replica holds the lock
+        Err(e) if is_duplicate_key(&e) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn set_version(instance: &str, version: u32) -> Result<()> {
+    get_schema_collection()
+        .update_one(
+            doc! {
+                "_id": SCHEMA_ID,
+                "locked_by": instance
+            },
+            doc! {
+                "$set": {
+                    "version": version,
+                    "locked_until": (get_time_secs() + MIGRATION_LOCK_TIMEOUT) as i64
+                }
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+async fn unlock(instance: &str) -> Result<()> {
+    get_schema_collection()
+        .update_one(
+            doc! {
+                "_id": SCHEMA_ID,
+                "locked_by": instance
+            },
+            doc! {
+                "$set": {
+                    "locked_by": null,
+                    "locked_until": null
+                }
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+async fn apply(instance: &str) -> Result<()> {
+    let current = current_version().await?;
+    for migration in migrations().iter().filter(|m| m.version() > current) {
+        info!(
+            "Running migration {} ({})...",
+            migration.version(),
+            migration.name()
+        );
+        let changed = migration.up(false).await?;
+        set_version(instance, migration.version()).await?;
+        info!(
+            "Migration {} done, {} documents changed",
+            migration.version(),
+            changed
+        );
+    }
+    Ok(())
+}
+
+pub async fn run(dry_run: bool) -> Result<()> {
+    let latest = latest_version();
+    let current = current_version().await?;
+    if current >= latest {
+        info!("Database schema is up to date (version {})", current);
+        return Ok(());
+    }
+    if dry_run {
+        for migration in migrations().iter().filter(|m| m.version() > current) {
+            let changed = migration.up(true).await?;
+            info!(
+                "Migration {} ({}) would change {} documents",
+                migration.version(),
+                migration.name(),
+                changed
+            );
+        }
+        return Ok(());
+    }
+    let instance = Ulid::new().to_string();
+    while !try_lock(&instance).await? {
+        // wait for whichever replica holds the lock to finish
+        if current_version().await? >= latest {
+            info!("Database schema was migrated by another instance");
+            return Ok(());
+        }
+        info!("Waiting for another instance to finish migrating...");
+        task::sleep(Duration::from_secs(5)).await;
+    }
+    let result = apply(&instance).await;
+    if let Err(e) = unlock(&instance).await {
+        warn!("Failed to release the migration lock: {:?}", e);
+    }
+    result
+}
@@ -0,0 +1,132 @@
+use actix_web::{web::Data, HttpRequest};
+use chrono::DateTime;
+use log::error;
+
+use crate::{
+    database::recovery::RecoveryToken,
+    environment::{PUBLIC_ROOT, RESET_DELAY_HOURS},
+    errors::Result,
+    mail,
+    store::{RecoveryStore, SecurityEventStore, UserStore},
+    templates::{self, negotiate_locale},
+    utilities::{generate_continue_token_long, get_time_secs, send_email},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityNotification {
+    PasswordChanged,
+    PasswordReset,
+    PasswordResetRequested,
+    MfaDisabled,
+    PasskeyAdded,
+    UsernameChanged,
+    NewDevice,
+}
+
+impl SecurityNotification {
+    // critical notifications are always sent, regardless of the user's preference
+    pub fn is_critical(&self) -> bool {
+        !matches!(
+            self,
+            SecurityNotification::UsernameChanged | SecurityNotification::NewDevice
+        )
+    }
+
+    fn template(&self) -> &'static str {
+        match self {
+            SecurityNotification::PasswordChanged => "password_changed",
+            SecurityNotification::PasswordReset => "password_reset",
+            SecurityNotification::PasswordResetRequested => "password_reset_requested",
+            SecurityNotification::MfaDisabled => "mfa_disabled",
+            SecurityNotification::PasskeyAdded => "passkey_added",
+            SecurityNotification::UsernameChanged => "username_changed",
+            SecurityNotification::NewDevice => "new_device",
+        }
+    }
+}
+
+// Sent after the change is committed, so failing to queue the email only gets logged
+pub async fn notify(req: &HttpRequest, user_id: &str, notification: SecurityNotification) {
+    if let Err(e) = send(req, user_id, notification).await {
+        error!(
+            "Failed to notify user {} of {:?}: {:?}",
+            user_id, notification, e
+        );
+    }
+}
+
+async fn send(req: &HttpRequest, user_id: &str, notification: SecurityNotification) -> Result<()> {
+    if !mail::is_enabled() {
+        return Ok(());
+    }
+    let users = req
+        .app_data::<Data<dyn UserStore>>()
+        .expect("Unexpected error: user store not configured");
+    let Some(user) = users.find(user_id).await? else {
+        return Ok(());
+    };
+    if !notification.is_critical() && !user.security_notifications {
+        return Ok(());
+    }
+    let time = get_time_secs();
+    let token = generate_continue_token_long();
+    let recovery_tokens = req
+        .app_data::<Data<dyn RecoveryStore>>()
+        .expect("Unexpected error: recovery store not configured");
+    recovery_tokens
+        .create(RecoveryToken {
+            token: token.clone(),
+            user_id: user.id.clone(),
+            created_at: time,
+        })
+        .await?;
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("Unknown")
+        .to_string();
+    let device = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("Unknown")
+        .to_string();
+    let time = DateTime::from_timestamp(time as i64, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default();
+    let recover_url = format!("{}/recover?token={}", &*PUBLIC_ROOT, token);
+    let locale = negotiate_locale(req, user.locale.as_deref());
+    let email = templates::render(
+        notification.template(),
+        &locale,
+        &[
+            ("username", user.username),
+            ("time", time),
+            ("ip", ip),
+            ("device", device),
+            ("url", recover_url),
+            ("delay_hours", RESET_DELAY_HOURS.to_string()),
+        ],
+    );
+    send_email(req, user.email, email).await?;
+    Ok(())
+}
+
+// Must be called before the login itself is recorded
+pub async fn notify_new_device(req: &HttpRequest, user_id: &str) {
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok());
+    let events = req
+        .app_data::<Data<dyn SecurityEventStore>>()
+        .expect("Unexpected error: security event store not configured");
+    match events.is_new_device(user_id, user_agent).await {
+        Ok(true) => notify(req, user_id, SecurityNotification::NewDevice).await,
+        Ok(false) => {}
+        Err(e) => error!(
+            "Failed to check for a new device of user {}: {:?}",
+            user_id, e
+        ),
+    }
+}
@@ -1,77 +1,304 @@
+use aes_gcm::{Aes256Gcm, KeyInit};
+use argon2::{Algorithm, Argon2, Params, Version};
+use log::error;
 use opaque_ke::{
     CipherSuite, CredentialFinalization, CredentialRequest, RegistrationRequest,
     RegistrationUpload, ServerLogin, ServerLoginStartParameters, ServerRegistration, ServerSetup,
 };
 use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
 
-use crate::database::settings::get_settings;
+use crate::{
+    database::settings::{SealedServerSetup, Settings},
+    environment::{
+        OPAQUE_ARGON2_ITERATIONS, OPAQUE_ARGON2_MEMORY, OPAQUE_ARGON2_PARALLELISM,
+        OPAQUE_MASTER_KEY,
+    },
+    errors::{Error, Result},
+    store::SettingsStore,
+    utilities::{decrypt, encrypt, get_time_secs},
+};
 
-pub struct Default;
-impl CipherSuite for Default {
+// Ristretto255 without key stretching, what accounts registered before suites were versioned use
+pub struct Legacy;
+impl CipherSuite for Legacy {
     type OprfCs = opaque_ke::Ristretto255;
     type KeGroup = opaque_ke::Ristretto255;
     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
     type Ksf = opaque_ke::ksf::Identity;
 }
 
-pub fn create_server_setup() -> ServerSetup<Default> {
+// Ristretto255 with Argon2id stretching the password on the client
+pub struct Argon2id;
+impl CipherSuite for Argon2id {
+    type OprfCs = opaque_ke::Ristretto255;
+    type KeGroup = opaque_ke::Ristretto255;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDh;
+    type Ksf = Argon2<'static>;
+}
+
+// Stored as a number next to the password file. Suites on another group (e.g. P-256) need their
+// own server setup, everything else only needs a variant and an arm in `with_suite!`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum Suite {
+    Legacy = 1,
+    Argon2id = 2,
+}
+
+impl TryFrom<u32> for Suite {
+    type Error = String;
+
+    fn try_from(version: u32) -> std::result::Result<Self, Self::Error> {
+        match version {
+            1 => Ok(Suite::Legacy),
+            2 => Ok(Suite::Argon2id),
+            _ => Err(format!("unknown cipher suite {}", version)),
+        }
+    }
+}
+
+impl From<Suite> for u32 {
+    fn from(suite: Suite) -> u32 {
+        suite as u32
+    }
+}
+
+// Argon2id parameters, memory in KiB
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KsfParams {
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KsfParams {
+    // what clients stretch the password with
+    pub fn argon2(&self) -> Argon2<'static> {
+        let params = Params::new(self.memory, self.iterations, self.parallelism, None)
+            .expect("Argon2 parameters are out of range");
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+}
+
+// Everything the client needs to derive the same password file again, so the parameters a
+// password was registered with keep working after the configuration changes
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PasswordSuite {
+    pub version: Suite,
+    pub ksf: Option<KsfParams>,
+    // id of the server setup the password file belongs to
+    #[serde(default)]
+    pub setup: u32,
+}
+
+impl PasswordSuite {
+    pub fn legacy() -> PasswordSuite {
+        PasswordSuite {
+            version: Suite::Legacy,
+            ksf: None,
+            setup: 0,
+        }
+    }
+
+    // what new passwords are registered with; logins on anything else get upgraded
+    pub fn current(setup: u32) -> PasswordSuite {
+        PasswordSuite {
+            version: Suite::Argon2id,
+            ksf: Some(KsfParams {
+                memory: *OPAQUE_ARGON2_MEMORY,
+                iterations: *OPAQUE_ARGON2_ITERATIONS,
+                parallelism: *OPAQUE_ARGON2_PARALLELISM,
+            }),
+            setup,
+        }
+    }
+}
+
+impl Default for PasswordSuite {
+    fn default() -> Self {
+        PasswordSuite::legacy()
+    }
+}
+
+// Runs `$body` with `$cs` naming the cipher suite type of `$suite`
+macro_rules! with_suite {
+    ($suite:expr, $cs:ident => $body:expr) => {
+        match $suite {
+            Suite::Legacy => {
+                type $cs = Legacy;
+                $body
+            }
+            Suite::Argon2id => {
+                type $cs = Argon2id;
+                $body
+            }
+        }
+    };
+}
+
+#[derive(Clone)]
+pub enum LoginState {
+    Legacy(ServerLogin<Legacy>),
+    Argon2id(ServerLogin<Argon2id>),
+}
+
+impl From<ServerLogin<Legacy>> for LoginState {
+    fn from(state: ServerLogin<Legacy>) -> Self {
+        LoginState::Legacy(state)
+    }
+}
+
+impl From<ServerLogin<Argon2id>> for LoginState {
+    fn from(state: ServerLogin<Argon2id>) -> Self {
+        LoginState::Argon2id(state)
+    }
+}
+
+fn master_key() -> Aes256Gcm {
+    Aes256Gcm::new_from_slice(&OPAQUE_MASTER_KEY).expect("OPAQUE_MASTER_KEY must be 32 bytes")
+}
+
+// Every current suite shares Ristretto255, so they share one server setup
+pub fn seal_server_setup(id: u32) -> SealedServerSetup {
     let mut rng = OsRng;
-    ServerSetup::<Default>::new(&mut rng)
+    let setup = ServerSetup::<Legacy>::new(&mut rng);
+    SealedServerSetup {
+        id,
+        data: encrypt(setup.serialize().to_vec(), master_key()),
+        created_at: get_time_secs(),
+    }
+}
+
+// Encrypts a setup from before they were sealed, keeping its id at 0
+pub fn seal_existing_server_setup(data: Vec<u8>) -> SealedServerSetup {
+    SealedServerSetup {
+        id: 0,
+        data: encrypt(data, master_key()),
+        created_at: get_time_secs(),
+    }
+}
+
+pub fn create_settings() -> Settings {
+    Settings {
+        server_setups: vec![seal_server_setup(0)],
+        current_server_setup: 0,
+    }
+}
+
+// Adds a new setup for registrations and upgrades, returning its id
+pub fn rotate_server_setup(settings: &mut Settings) -> u32 {
+    let id = settings
+        .server_setups
+        .iter()
+        .map(|setup| setup.id + 1)
+        .max()
+        .unwrap_or(0);
+    settings.server_setups.push(seal_server_setup(id));
+    settings.current_server_setup = id;
+    id
+}
+
+// The serialized `ServerSetup` with that id
+pub fn open_server_setup(settings: &Settings, id: u32) -> Result<Vec<u8>> {
+    let Some(sealed) = settings.server_setups.iter().find(|setup| setup.id == id) else {
+        error!("OPAQUE server setup {} does not exist", id);
+        return Err(Error::ServerSetupUnavailable);
+    };
+    let Some(data) = decrypt(sealed.data.clone(), Some(master_key())) else {
+        error!(
+            "OPAQUE server setup {} can't be decrypted with OPAQUE_MASTER_KEY",
+            id
+        );
+        return Err(Error::ServerSetupUnavailable);
+    };
+    ServerSetup::<Legacy>::deserialize(&data).map_err(|_| {
+        error!("OPAQUE server setup {} is corrupted", id);
+        Error::ServerSetupUnavailable
+    })?;
+    Ok(data)
+}
+
+// Checks that every setup can be used, before starting or importing
+pub fn verify_settings(settings: &Settings) -> Result<()> {
+    for setup in &settings.server_setups {
+        open_server_setup(settings, setup.id)?;
+    }
+    open_server_setup(settings, settings.current_server_setup)?;
+    Ok(())
+}
+
+pub async fn current_suite(settings: &dyn SettingsStore) -> Result<PasswordSuite> {
+    Ok(PasswordSuite::current(
+        settings.get().await?.current_server_setup,
+    ))
 }
 
-pub async fn get_server_setup() -> ServerSetup<Default> {
-    let settings = get_settings().await;
-    ServerSetup::<Default>::deserialize(&settings.opaque_server_setup[..]).unwrap()
+async fn get_server_setup(settings: &dyn SettingsStore, id: u32) -> Result<Vec<u8>> {
+    open_server_setup(&settings.get().await?, id)
 }
 
 pub async fn begin_registration(
+    settings: &dyn SettingsStore,
+    suite: &PasswordSuite,
     email: String,
-    client_message: RegistrationRequest<Default>,
-) -> crate::errors::Result<Vec<u8>> {
-    let server_setup = get_server_setup().await;
-    let server_registration_start_result =
-        ServerRegistration::<Default>::start(&server_setup, client_message, email.as_bytes())?;
-    Ok(server_registration_start_result
-        .message
-        .serialize()
-        .to_vec())
+    client_message: &[u8],
+) -> Result<Vec<u8>> {
+    let server_setup = get_server_setup(settings, suite.setup).await?;
+    with_suite!(suite.version, CS => {
+        let server_setup = ServerSetup::<CS>::deserialize(&server_setup)?;
+        let result = ServerRegistration::<CS>::start(
+            &server_setup,
+            RegistrationRequest::deserialize(client_message)?,
+            email.as_bytes(),
+        )?;
+        Ok(result.message.serialize().to_vec())
+    })
 }
 
-pub fn finish_registration(
-    client_message: RegistrationUpload<Default>,
-) -> crate::errors::Result<Vec<u8>> {
-    let password_file = ServerRegistration::<Default>::finish(client_message);
-    Ok(password_file.serialize().to_vec())
+pub fn finish_registration(suite: &PasswordSuite, client_message: &[u8]) -> Result<Vec<u8>> {
+    with_suite!(suite.version, CS => {
+        let password_file =
+            ServerRegistration::<CS>::finish(RegistrationUpload::deserialize(client_message)?);
+        Ok(password_file.serialize().to_vec())
+    })
 }
 
+// Without a password file the server answers with a fake record for `suite`
 pub async fn begin_login(
+    settings: &dyn SettingsStore,
+    suite: &PasswordSuite,
     email: String,
     password_data: Option<Vec<u8>>,
-    client_message: CredentialRequest<Default>,
-) -> crate::errors::Result<(Vec<u8>, ServerLogin<Default>)> {
-    let password_file = password_data
-        .map(|x| ServerRegistration::<Default>::deserialize(&x))
-        .transpose()?;
+    client_message: &[u8],
+) -> Result<(Vec<u8>, LoginState)> {
+    let server_setup = get_server_setup(settings, suite.setup).await?;
     let mut server_rng = OsRng;
-    let server_setup = get_server_setup().await;
-    let server_login_start_result = ServerLogin::start(
-        &mut server_rng,
-        &server_setup,
-        password_file,
-        client_message,
-        email.as_bytes(),
-        ServerLoginStartParameters::default(),
-    )?;
-    Ok((
-        server_login_start_result.message.serialize().to_vec(),
-        server_login_start_result.state,
-    ))
+    with_suite!(suite.version, CS => {
+        let server_setup = ServerSetup::<CS>::deserialize(&server_setup)?;
+        let password_file = password_data
+            .map(|x| ServerRegistration::<CS>::deserialize(&x))
+            .transpose()?;
+        let result = ServerLogin::start(
+            &mut server_rng,
+            &server_setup,
+            password_file,
+            CredentialRequest::<CS>::deserialize(client_message)?,
+            email.as_bytes(),
+            ServerLoginStartParameters::default(),
+        )?;
+        Ok((result.message.serialize().to_vec(), result.state.into()))
+    })
 }
 
-pub fn finish_login(
-    state: ServerLogin<Default>,
-    client_message: CredentialFinalization<Default>,
-) -> crate::errors::Result<()> {
-    state.finish(client_message)?;
+pub fn finish_login(state: LoginState, client_message: &[u8]) -> Result<()> {
+    match state {
+        LoginState::Legacy(state) => {
+            state.finish(CredentialFinalization::deserialize(client_message)?)?;
+        }
+        LoginState::Argon2id(state) => {
+            state.finish(CredentialFinalization::deserialize(client_message)?)?;
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,118 @@
+// Registration policy: who may create an account, and whether it needs approval first
+
+use std::{collections::HashSet, fs};
+
+use lazy_static::lazy_static;
+use log::info;
+
+use crate::{
+    environment::{
+        DISPOSABLE_DOMAINS_FILE, REGISTRATION_ALLOWED_DOMAINS, REGISTRATION_APPROVAL,
+        REGISTRATION_DENIED_DOMAINS, REGISTRATION_MODE,
+    },
+    errors::{Error, Result},
+    store::InviteStore,
+    utilities::get_time_secs,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationMode {
+    Open,
+    InviteOnly,
+    Closed,
+}
+
+lazy_static! {
+    pub static ref MODE: RegistrationMode = match REGISTRATION_MODE.as_deref() {
+        Some("open") | None => RegistrationMode::Open,
+        Some("invite") => RegistrationMode::InviteOnly,
+        Some("closed") => RegistrationMode::Closed,
+        Some(other) => panic!("Unknown REGISTRATION_MODE: {}", other),
+    };
+    static ref DISPOSABLE_DOMAINS: HashSet<String> = load_disposable_domains();
+}
+
+// One domain per line, blank lines and lines starting with # are ignored
+fn load_disposable_domains() -> HashSet<String> {
+    let Some(file) = &*DISPOSABLE_DOMAINS_FILE else {
+        return HashSet::new();
+    };
+    fs::read_to_string(file)
+        .expect("Failed to read DISPOSABLE_DOMAINS_FILE")
+        .lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+pub fn load() {
+    info!(
+        "Registration is {:?}{}, with {} disposable domain(s) blocked",
+        *MODE,
+        if *REGISTRATION_APPROVAL {
+            " and requires approval"
+        } else {
+            ""
+        },
+        DISPOSABLE_DOMAINS.len()
+    );
+}
+
+// Matches the domain itself and any of its subdomains
+fn matches_domain(domain: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_start_matches('.').to_lowercase();
+    domain == pattern || domain.ends_with(&format!(".{}", pattern))
+}
+
+pub fn check_email(email: &str) -> Result<()> {
+    let Some((_, domain)) = email.trim().rsplit_once('@') else {
+        return Err(Error::InvalidEmail);
+    };
+    let domain = domain.to_lowercase();
+    if !REGISTRATION_ALLOWED_DOMAINS.is_empty()
+        && !REGISTRATION_ALLOWED_DOMAINS
+            .iter()
+            .any(|allowed| matches_domain(&domain, allowed))
+    {
+        return Err(Error::EmailDomainNotAllowed);
+    }
+    if REGISTRATION_DENIED_DOMAINS
+        .iter()
+        .any(|denied| matches_domain(&domain, denied))
+    {
+        return Err(Error::EmailDomainNotAllowed);
+    }
+    if DISPOSABLE_DOMAINS
+        .iter()
+        .any(|disposable| matches_domain(&domain, disposable))
+    {
+        return Err(Error::EmailDomainNotAllowed);
+    }
+    Ok(())
+}
+
+// Invites are only checked here, they are redeemed once the account is created
+pub async fn check_invite(invites: &dyn InviteStore, invite_code: Option<&str>) -> Result<()> {
+    match *MODE {
+        RegistrationMode::Open => Ok(()),
+        RegistrationMode::Closed => Err(Error::RegistrationClosed),
+        RegistrationMode::InviteOnly => {
+            let Some(code) = invite_code else {
+                return Err(Error::InviteRequired);
+            };
+            if !invites.is_usable(code, get_time_secs()).await? {
+                return Err(Error::InvalidInvite);
+            }
+            Ok(())
+        }
+    }
+}
+
+// The invite `UserStore::create` has to redeem, if registration needs one
+pub fn invite_to_redeem(invite_code: Option<&str>) -> Result<Option<&str>> {
+    match (*MODE, invite_code) {
+        (RegistrationMode::InviteOnly, None) => Err(Error::InviteRequired),
+        (RegistrationMode::InviteOnly, code) => Ok(code),
+        _ => Ok(None),
+    }
+}
@@ -1,18 +1,29 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
-    database::user::get_collection,
+    database::{
+        security_event::{self, SecurityEventKind},
+        username_history::UsernameHistory,
+    },
+    environment::{USERNAME_CHANGE_COOLDOWN_DAYS, USERNAME_HOLD_DAYS},
     errors::{Error, Result},
-    utilities::{validate_escalation, USERNAME_RE},
+    notifications::{notify, SecurityNotification},
+    store::{SessionStore, SettingsUpdate, UserStore},
+    templates, username,
+    utilities::{get_time_secs, validate_escalation},
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountSettings {
     username: Option<String>,
+    security_notifications: Option<bool>,
+    locale: Option<String>,
     // destructive actions
     escalation_token: String,
 }
@@ -22,37 +33,75 @@ pub struct AccountSettings {
 pub struct AccountSettingsResponse {}
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     account_settings: web::Json<AccountSettings>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
     let account_settings = account_settings.into_inner();
-    validate_escalation(account_settings.escalation_token, jwt.jwt).await?;
-    let user_collection = get_collection();
-    let mut update_query = doc! {};
-    if let Some(username) = account_settings.username {
-        if !USERNAME_RE.is_match(username.trim()) {
-            return Err(Error::InvalidUsername);
+    validate_escalation(
+        sessions.get_ref(),
+        account_settings.escalation_token,
+        jwt.jwt.clone(),
+    )
+    .await?;
+    let user = users
+        .find(&jwt.jwt_content.id)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let mut update = SettingsUpdate::default();
+    if let Some(security_notifications) = account_settings.security_notifications {
+        update.security_notifications = Some(security_notifications);
+    }
+    if let Some(locale) = account_settings.locale {
+        if !templates::is_supported(&locale) {
+            return Err(Error::UnsupportedLocale);
         }
-        let user = user_collection
-            .find_one(doc! {
-                "username": username.trim()
-            })
-            .await?;
-        if user.is_some() {
-            return Err(Error::UsernameAlreadyTaken);
+        update.locale = Some(templates::normalize_locale(&locale));
+    }
+    let mut username_changed = false;
+    if let Some(username) = account_settings.username {
+        let (username, username_key) = username::validate(&username)?;
+        if username != user.username {
+            let now = get_time_secs();
+            if let Some(changed_at) = user.username_changed_at {
+                let until = changed_at + *USERNAME_CHANGE_COOLDOWN_DAYS * 86400;
+                if now < until {
+                    return Err(Error::UsernameChangeCooldown { until });
+                }
+            }
+            username::ensure_available(users.get_ref(), &username_key, Some(&user.id)).await?;
+            // written together with the new username
+            let history = UsernameHistory {
+                username: user.username.clone(),
+                username_key: username::normalize_key(&user.username),
+                user_id: user.id.clone(),
+                changed_at: now,
+                released_at: now + *USERNAME_HOLD_DAYS * 86400,
+            };
+            users
+                .change_username(&user.id, &username, &username_key, history)
+                .await?;
+            username_changed = true;
         }
-        update_query.insert("username", username.trim());
     }
-    user_collection
-        .update_one(
-            doc! {
-                "id": jwt.jwt_content.id.clone()
-            },
-            doc! {
-                "$set": update_query
-            },
+    users.update_settings(&user.id, update).await?;
+    security_event::record(
+        &req,
+        SecurityEventKind::AccountSettingsChanged,
+        &jwt.jwt_content.id,
+        Some(&jwt.session_id),
+    )
+    .await;
+    if username_changed {
+        notify(
+            &req,
+            &jwt.jwt_content.id,
+            SecurityNotification::UsernameChanged,
         )
-        .await?;
+        .await;
+    }
     Ok(web::Json(AccountSettingsResponse {}))
 }
@@ -0,0 +1,61 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    authenticate::Authenticate,
+    database::service_account::{validate_scopes, ServiceAccount, KEY_PREFIX},
+    errors::{Error, Result},
+    routes::admin_service_accounts::ServiceAccountResponse,
+    store::{ServiceAccountStore, UserStore},
+    utilities::{generate_api_key, get_time_secs, hash_api_key, validate_administrator},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateServiceAccount {
+    name: String,
+    scopes: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateServiceAccountResponse {
+    service_account: ServiceAccountResponse,
+    // only ever returned here
+    key: String,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    create: web::Json<CreateServiceAccount>,
+    users: Data<dyn UserStore>,
+    accounts: Data<dyn ServiceAccountStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let admin = validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let create = create.into_inner();
+    let name = create.name.trim().to_string();
+    if name.is_empty() || name.len() > 64 {
+        return Err(Error::InvalidName);
+    }
+    validate_scopes(&create.scopes)?;
+    let key = generate_api_key(KEY_PREFIX);
+    let account = ServiceAccount {
+        id: Ulid::new().to_string(),
+        name,
+        key_hash: hash_api_key(&key),
+        scopes: create.scopes,
+        created_by: admin.id,
+        created_at: get_time_secs(),
+        last_used_at: None,
+    };
+    accounts.create(account.clone()).await?;
+    Ok(web::Json(CreateServiceAccountResponse {
+        service_account: account.into(),
+        key,
+    }))
+}
@@ -0,0 +1,26 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+
+use crate::{
+    authenticate::Authenticate,
+    errors::{Error, Result},
+    store::{ServiceAccountStore, UserStore},
+    utilities::validate_administrator,
+};
+
+// The key stops working right away
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    id: web::Path<String>,
+    users: Data<dyn UserStore>,
+    accounts: Data<dyn ServiceAccountStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    if !accounts.delete(&id).await? {
+        return Err(Error::ServiceAccountNotFound);
+    }
+    Ok(web::Json("null"))
+}
@@ -0,0 +1,98 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::outbox::{OutboxEmail, OutboxStatus},
+    errors::Result,
+    store::{OutboxStore, UserStore},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxQuery {
+    status: Option<OutboxStatus>,
+    limit: Option<i64>,
+    // id of the last email on the previous page
+    before: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxEntry {
+    id: String,
+    to: String,
+    subject: String,
+    status: OutboxStatus,
+    attempts: u32,
+    next_attempt_at: u64,
+    last_error: Option<String>,
+    created_at: u64,
+    sent_at: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxCounts {
+    pending: u64,
+    sending: u64,
+    sent: u64,
+    failed: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxResponse {
+    counts: OutboxCounts,
+    emails: Vec<OutboxEntry>,
+    next: Option<String>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    query: web::Query<OutboxQuery>,
+    users: Data<dyn UserStore>,
+    outbox: Data<dyn OutboxStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(50).clamp(1, 100) as usize;
+    let emails = outbox
+        .list(query.status, query.before.as_deref(), limit)
+        .await?;
+    let next = if emails.len() == limit {
+        emails.last().map(|e| e.id.clone())
+    } else {
+        None
+    };
+    let counts = OutboxCounts {
+        pending: outbox.count(OutboxStatus::Pending).await?,
+        sending: outbox.count(OutboxStatus::Sending).await?,
+        sent: outbox.count(OutboxStatus::Sent).await?,
+        failed: outbox.count(OutboxStatus::Failed).await?,
+    };
+    let emails = emails
+        .into_iter()
+        .map(|e: OutboxEmail| OutboxEntry {
+            id: e.id,
+            to: e.to,
+            subject: e.subject,
+            status: e.status,
+            attempts: e.attempts,
+            next_attempt_at: e.next_attempt_at,
+            last_error: e.last_error,
+            created_at: e.created_at,
+            sent_at: e.sent_at,
+        })
+        .collect();
+    Ok(web::Json(OutboxResponse {
+        counts,
+        emails,
+        next,
+    }))
+}
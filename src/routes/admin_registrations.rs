@@ -0,0 +1,38 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate, errors::Result, store::UserStore, utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingRegistration {
+    id: String,
+    email: String,
+    username: String,
+    email_verified: bool,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    users: Data<dyn UserStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let users = users
+        .list_pending_approval()
+        .await?
+        .into_iter()
+        .map(|u| PendingRegistration {
+            id: u.id,
+            email: u.email,
+            username: u.username,
+            email_verified: u.email_verified,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(users))
+}
@@ -0,0 +1,39 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    errors::{Error, Result},
+    store::UserStore,
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReviewRegistration {
+    // rejected registrations are deleted
+    approve: bool,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+    review: web::Json<ReviewRegistration>,
+    users: Data<dyn UserStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let user_id = user_id.into_inner();
+    let reviewed = if review.approve {
+        users.approve(&user_id).await?
+    } else {
+        users.delete_pending(&user_id).await?
+    };
+    if !reviewed {
+        return Err(Error::UserNotFound);
+    }
+    Ok(web::Json("null"))
+}
@@ -0,0 +1,25 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+
+use crate::{
+    authenticate::Authenticate,
+    errors::Result,
+    routes::security_events::{list_events, SecurityEventsQuery},
+    store::{SecurityEventStore, UserStore},
+    utilities::validate_administrator,
+};
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    user_id: web::Path<String>,
+    query: web::Query<SecurityEventsQuery>,
+    users: Data<dyn UserStore>,
+    events: Data<dyn SecurityEventStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let response = list_events(events.get_ref(), &user_id.into_inner(), query.into_inner()).await?;
+    Ok(web::Json(response))
+}
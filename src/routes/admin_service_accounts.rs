@@ -0,0 +1,54 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::service_account::ServiceAccount,
+    errors::Result,
+    store::{ServiceAccountStore, UserStore},
+    utilities::validate_administrator,
+};
+
+// Everything but the key hash
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccountResponse {
+    id: String,
+    name: String,
+    scopes: Vec<String>,
+    created_by: String,
+    created_at: u64,
+    last_used_at: Option<u64>,
+}
+
+impl From<ServiceAccount> for ServiceAccountResponse {
+    fn from(account: ServiceAccount) -> Self {
+        ServiceAccountResponse {
+            id: account.id,
+            name: account.name,
+            scopes: account.scopes,
+            created_by: account.created_by,
+            created_at: account.created_at,
+            last_used_at: account.last_used_at,
+        }
+    }
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    users: Data<dyn UserStore>,
+    accounts: Data<dyn ServiceAccountStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    let accounts = accounts
+        .list()
+        .await?
+        .into_iter()
+        .map(ServiceAccountResponse::from)
+        .collect::<Vec<_>>();
+    Ok(web::Json(accounts))
+}
@@ -0,0 +1,37 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::service_account::validate_scopes,
+    errors::{Error, Result},
+    routes::admin_service_accounts::ServiceAccountResponse,
+    store::{ServiceAccountStore, UserStore},
+    utilities::validate_administrator,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateServiceAccount {
+    scopes: Vec<String>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    id: web::Path<String>,
+    update: web::Json<UpdateServiceAccount>,
+    users: Data<dyn UserStore>,
+    accounts: Data<dyn ServiceAccountStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    validate_administrator(users.get_ref(), &jwt.jwt_content.id).await?;
+    validate_scopes(&update.scopes)?;
+    let account = accounts
+        .set_scopes(&id, &update.scopes)
+        .await?
+        .ok_or(Error::ServiceAccountNotFound)?;
+    Ok(web::Json(ServiceAccountResponse::from(account)))
+}
@@ -0,0 +1,34 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    captcha::pow::{self, IssuedChallenges},
+    errors::{Error, Result},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChallengeResponse {
+    challenge: String,
+    difficulty: u32,
+    expires_at: u64,
+}
+
+pub async fn handle(req: HttpRequest, issued: Data<IssuedChallenges>) -> Result<impl Responder> {
+    if !pow::is_enabled() {
+        return Err(Error::ChallengeUnavailable);
+    }
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.to_string());
+    let challenge = pow::issue(&issued, ip.as_deref()).await;
+    Ok(web::Json(ChallengeResponse {
+        challenge: challenge.challenge,
+        difficulty: challenge.difficulty,
+        expires_at: challenge.expires_at,
+    }))
+}
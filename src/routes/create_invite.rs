@@ -0,0 +1,68 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::invite::Invite,
+    environment::USER_INVITES,
+    errors::{Error, Result},
+    store::{InviteStore, UserStore},
+    utilities::{generate_invite_code, get_time_secs},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateInvite {
+    // administrators only, users always create single-use invites
+    max_uses: Option<u32>,
+    expires_in_hours: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateInviteResponse {
+    code: String,
+    max_uses: Option<u32>,
+    expires_at: Option<u64>,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    create_invite: web::Json<CreateInvite>,
+    users: Data<dyn UserStore>,
+    invites: Data<dyn InviteStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let user = users
+        .find(&jwt.jwt_content.id)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    let max_uses = if user.platform_administrator {
+        create_invite.max_uses
+    } else if *USER_INVITES {
+        Some(1)
+    } else {
+        return Err(Error::MissingPermission);
+    };
+    let now = get_time_secs();
+    let expires_at = create_invite.expires_in_hours.map(|h| now + h * 3600);
+    let code = generate_invite_code();
+    invites
+        .create(Invite {
+            code: code.clone(),
+            created_by: user.id,
+            max_uses,
+            uses: 0,
+            expires_at,
+            created_at: now,
+        })
+        .await?;
+    Ok(web::Json(CreateInviteResponse {
+        code,
+        max_uses,
+        expires_at,
+    }))
+}
@@ -0,0 +1,81 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use crate::{
+    authenticate::Authenticate,
+    constants::PERSONAL_TOKEN_MAX_DAYS,
+    database::{
+        personal_token::{validate_scopes, PersonalToken, TOKEN_PREFIX},
+        security_event::{self, SecurityEventKind},
+    },
+    errors::{Error, Result},
+    routes::get_tokens::PersonalTokenResponse,
+    store::{PersonalTokenStore, SessionStore},
+    utilities::{generate_api_key, get_time_secs, hash_api_key, validate_escalation},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateToken {
+    name: String,
+    scopes: Vec<String>,
+    expires_in_days: u64,
+    escalation_token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTokenResponse {
+    #[serde(flatten)]
+    personal_token: PersonalTokenResponse,
+    // only ever returned here
+    token: String,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    create: web::Json<CreateToken>,
+    sessions: Data<dyn SessionStore>,
+    tokens: Data<dyn PersonalTokenStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let create = create.into_inner();
+    validate_escalation(sessions.get_ref(), create.escalation_token, jwt.jwt).await?;
+    let name = create.name.trim().to_string();
+    if name.is_empty() || name.len() > 64 {
+        return Err(Error::InvalidName);
+    }
+    validate_scopes(&create.scopes)?;
+    if !(1..=PERSONAL_TOKEN_MAX_DAYS).contains(&create.expires_in_days) {
+        return Err(Error::InvalidExpiry);
+    }
+    let token = generate_api_key(TOKEN_PREFIX);
+    let now = get_time_secs();
+    let personal_token = PersonalToken {
+        id: Ulid::new().to_string(),
+        user_id: jwt.jwt_content.id.clone(),
+        name,
+        token_hash: hash_api_key(&token),
+        scopes: create.scopes,
+        created_at: now,
+        expires_at: now + create.expires_in_days * 86400,
+        last_used_at: None,
+    };
+    tokens.create(personal_token.clone()).await?;
+    security_event::record(
+        &req,
+        SecurityEventKind::PersonalTokenCreated,
+        &jwt.jwt_content.id,
+        Some(&jwt.session_id),
+    )
+    .await;
+    Ok(web::Json(CreateTokenResponse {
+        personal_token: personal_token.into(),
+        token,
+    }))
+}
@@ -1,12 +1,13 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
-    database::profile,
-    database::user,
     errors::{Error, Result},
+    store::{ProfileStore, UserStore},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -14,8 +15,12 @@ use crate::{
 pub struct CurrentUserResponse {
     id: String,
     email: String,
+    email_verified: bool,
+    email_verified_at: Option<u64>,
     username: String,
     mfa_enabled: bool,
+    security_notifications: bool,
+    locale: Option<String>,
     display_name: String,
     description: String,
     website: String,
@@ -23,20 +28,18 @@ pub struct CurrentUserResponse {
     avatar: Option<String>,
 }
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    users: Data<dyn UserStore>,
+    profiles: Data<dyn ProfileStore>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let collection = user::get_collection();
-    let profile_collection = profile::get_collection();
-    let result = collection
-        .find_one(doc! {
-            "id": jwt.jwt_content.id.clone()
-        })
+    let result = users
+        .find(&jwt.jwt_content.id)
         .await?
         .ok_or(Error::DatabaseError)?;
-    let profile_result = profile_collection
-        .find_one(doc! {
-            "id": jwt.jwt_content.id.clone()
-        })
+    let profile_result = profiles
+        .find(&jwt.jwt_content.id)
         .await?
         .ok_or(Error::DatabaseError)?;
     Ok(web::Json(CurrentUserResponse {
@@ -45,7 +48,11 @@ pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Resp
         display_name: profile_result.display_name,
         id: jwt.jwt_content.id,
         email: result.email,
+        email_verified: result.email_verified,
+        email_verified_at: result.email_verified_at,
         mfa_enabled: result.mfa_enabled,
+        security_notifications: result.security_notifications,
+        locale: result.locale,
         username: result.username,
         website: profile_result.website,
     }))
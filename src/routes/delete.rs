@@ -1,12 +1,14 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
-    database::{files::File, passkey, profile, session, user},
-    errors::{Error, Result},
-    utilities::validate_escalation,
+    errors::Result,
+    store::{FileStore, SessionStore, UserStore},
+    utilities::{clear_pending_state, validate_escalation},
 };
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -22,39 +24,19 @@ pub struct DeleteResponse {}
 pub async fn handle(
     jwt: web::ReqData<Result<Authenticate>>,
     delete: web::Json<Delete>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+    files: Data<dyn FileStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    validate_escalation(delete.escalation_token.clone(), jwt.jwt).await?;
-    let collection = user::get_collection();
-    let sessions = session::get_collection();
-    sessions
-        .delete_many(doc! { "user_id": &jwt.jwt_content.id })
-        .await?;
-    collection
-        .delete_one(doc! {
-            "id": &jwt.jwt_content.id
-        })
-        .await?;
-    let profile = profile::get_collection()
-        .find_one(doc! {
-            "id": &jwt.jwt_content.id,
-        })
-        .await?
-        .ok_or(Error::DatabaseError)?;
-    if let Some(avatar) = profile.avatar {
-        if let Ok(avatar) = File::get(&avatar).await {
-            avatar.detach().await?;
+    validate_escalation(sessions.get_ref(), delete.escalation_token.clone(), jwt.jwt).await?;
+    let profile = users.delete(&jwt.jwt_content.id).await?;
+    clear_pending_state(&jwt.jwt_content.id);
+    // files live in the CDN database, outside the user's transaction
+    if let Some(avatar) = profile.and_then(|profile| profile.avatar) {
+        if let Some(avatar) = files.find(&avatar).await? {
+            files.set_attached(&avatar.id, false).await?;
         }
     }
-    profile::get_collection()
-        .delete_one(doc! {
-            "id": &jwt.jwt_content.id
-        })
-        .await?;
-    passkey::get_collection()
-        .delete_many(doc! {
-            "user_id": jwt.jwt_content.id
-        })
-        .await?;
     Ok(web::Json(DeleteResponse {}))
 }
@@ -0,0 +1,16 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+
+use crate::{authenticate::Authenticate, errors::Result, store::InviteStore};
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    code: web::Path<String>,
+    invites: Data<dyn InviteStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    invites.delete(&code, &jwt.jwt_content.id).await?;
+    Ok(web::Json("null"))
+}
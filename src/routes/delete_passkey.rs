@@ -1,9 +1,15 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    authenticate::Authenticate, database::passkey, errors::Result, utilities::validate_escalation,
+    authenticate::Authenticate,
+    database::security_event::{self, SecurityEventKind},
+    errors::Result,
+    store::{PasskeyStore, SessionStore},
+    utilities::validate_escalation,
 };
 
 #[derive(Deserialize, Serialize)]
@@ -13,17 +19,31 @@ pub struct DeletePasskey {
 }
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     passkey_id: web::Path<String>,
     delete_passkey: web::Json<DeletePasskey>,
+    passkeys: Data<dyn PasskeyStore>,
+    sessions: Data<dyn SessionStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    validate_escalation(delete_passkey.escalation_token.clone(), jwt.jwt).await?;
-    passkey::get_collection()
-        .delete_one(doc! {
-            "id": &passkey_id.into_inner(),
-            "user_id": jwt.jwt_content.id,
-        })
-        .await?;
+    validate_escalation(
+        sessions.get_ref(),
+        delete_passkey.escalation_token.clone(),
+        jwt.jwt,
+    )
+    .await?;
+    if passkeys
+        .delete(&passkey_id.into_inner(), &jwt.jwt_content.id)
+        .await?
+    {
+        security_event::record(
+            &req,
+            SecurityEventKind::PasskeyRemoved,
+            &jwt.jwt_content.id,
+            Some(&jwt.session_id),
+        )
+        .await;
+    }
     Ok(web::Json("null"))
 }
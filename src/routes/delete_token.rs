@@ -0,0 +1,31 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+
+use crate::{
+    authenticate::Authenticate,
+    database::security_event::{self, SecurityEventKind},
+    errors::{Error, Result},
+    store::PersonalTokenStore,
+};
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    id: web::Path<String>,
+    tokens: Data<dyn PersonalTokenStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    if !tokens.delete(&id, &jwt.jwt_content.id).await? {
+        return Err(Error::TokenNotFound);
+    }
+    security_event::record(
+        &req,
+        SecurityEventKind::PersonalTokenRevoked,
+        &jwt.jwt_content.id,
+        Some(&jwt.session_id),
+    )
+    .await;
+    Ok(web::Json("null"))
+}
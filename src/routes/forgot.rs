@@ -1,24 +1,56 @@
-use actix_web::{web, Responder};
-use async_std::task;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
-use mongodb::bson::{self, doc, Binary};
-use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
+use webauthn_rs::{
+    prelude::{PasskeyAuthentication, PublicKeyCredential, RequestChallengeResponse},
+    Webauthn,
+};
 
 use crate::{
+    captcha,
+    constants::{CONTINUE_TIMEOUT, RECOVERY_TIMEOUT},
+    database::{
+        reset_request::ResetRequest,
+        security_event::{self, SecurityEventKind},
+    },
+    environment::{CAPTCHA_ON_FORGOT, RESET_DELAY_HOURS},
     errors::{Error, Result},
-    opaque::{begin_registration, finish_registration},
-    utilities::{generate_continue_token_long, get_time_secs, send_reset_email},
+    notifications::{notify, SecurityNotification},
+    opaque::{begin_registration, current_suite, finish_registration, PasswordSuite},
+    store::{CodeStore, PasskeyStore, ResetRequestStore, SettingsStore, UserStore},
+    templates::negotiate_locale,
+    utilities::{
+        clear_pending_state, generate_continue_token_long, get_time_secs, has_second_factor,
+        send_reset_email, verify_totp,
+    },
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
 pub enum Forgot {
+    #[serde(rename_all = "camelCase")]
     VerifyEmail {
         email: String,
+        captcha_token: Option<String>,
+    },
+    // accounts with MFA or passkeys have to prove a second factor before resetting
+    #[serde(rename_all = "camelCase")]
+    BeginVerification { continue_token: String },
+    #[serde(rename_all = "camelCase")]
+    VerifySecondFactor {
+        continue_token: String,
+        // TOTP or recovery code
+        code: Option<String>,
+        credential: Option<PublicKeyCredential>,
     },
+    // for users who lost their second factor, the reset is allowed after a delay
+    #[serde(rename_all = "camelCase")]
+    RequestDelayedReset { continue_token: String },
     #[serde(rename_all = "camelCase")]
     ResetPassword {
         continue_token: String,
@@ -36,9 +68,21 @@ pub enum Forgot {
 pub enum ForgotResponse {
     VerifyEmail {},
     #[serde(rename_all = "camelCase")]
+    BeginVerification {
+        second_factor_required: bool,
+        mfa_enabled: bool,
+        passkey_challenge: Option<RequestChallengeResponse>,
+    },
+    VerifySecondFactor {},
+    #[serde(rename_all = "camelCase")]
+    RequestDelayedReset {
+        available_at: u64,
+    },
+    #[serde(rename_all = "camelCase")]
     ResetPassword {
         continue_token: String,
         message: String,
+        suite: PasswordSuite,
     },
     FinishReset {},
 }
@@ -47,6 +91,13 @@ pub struct PendingForgot {
     pub time: u64,
     pub user_id: String,
     pub email: String,
+    pub verified: bool,
+    pub attempts: u32,
+    pub passkey_state: Option<PasskeyAuthentication>,
+    // chosen when the new password's registration begins
+    pub password_suite: Option<PasswordSuite>,
+    // the delayed reset being used, deleted once the new password is set
+    pub reset_request: Option<String>,
 }
 
 lazy_static! {
@@ -54,94 +105,281 @@ lazy_static! {
     pub static ref PENDING_FORGOTS2: DashMap<String, PendingForgot> = DashMap::new();
 }
 
-pub async fn handle(forgot: web::Json<Forgot>) -> Result<impl Responder> {
+const MAX_VERIFICATION_ATTEMPTS: u32 = 5;
+
+fn get_pending(continue_token: &str) -> Result<(String, String, bool)> {
+    let Some(pending) = PENDING_FORGOTS1.get(continue_token) else {
+        return Err(Error::SessionExpired);
+    };
+    if get_time_secs() - pending.time > CONTINUE_TIMEOUT {
+        drop(pending);
+        PENDING_FORGOTS1.remove(continue_token);
+        return Err(Error::SessionExpired);
+    }
+    Ok((
+        pending.user_id.clone(),
+        pending.email.clone(),
+        pending.verified,
+    ))
+}
+
+// A delayed reset whose waiting period is over. It stays usable until the reset finishes, so a
+// malformed message doesn't throw the wait away.
+async fn find_delayed_reset(
+    reset_requests: &dyn ResetRequestStore,
+    continue_token: &str,
+) -> Result<Option<ResetRequest>> {
+    let Some(request) = reset_requests.find(continue_token).await? else {
+        return Ok(None);
+    };
+    let now = get_time_secs();
+    if now < request.available_at {
+        return Err(Error::ResetDelayed {
+            until: request.available_at,
+        });
+    }
+    if now - request.available_at > RECOVERY_TIMEOUT {
+        reset_requests.delete(continue_token).await?;
+        return Err(Error::SessionExpired);
+    }
+    Ok(Some(request))
+}
+
+// every extractor is a store or setting the flow needs at some stage
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    req: HttpRequest,
+    forgot: web::Json<Forgot>,
+    webauthn: Data<Webauthn>,
+    users: Data<dyn UserStore>,
+    passkeys: Data<dyn PasskeyStore>,
+    codes: Data<dyn CodeStore>,
+    settings: Data<dyn SettingsStore>,
+    reset_requests: Data<dyn ResetRequestStore>,
+) -> Result<impl Responder> {
     let forgot = forgot.into_inner();
     match forgot {
-        Forgot::VerifyEmail { email } => {
-            let collection = crate::database::user::get_collection();
-            let result = collection
-                .find_one(doc! {
-                    "email": email.clone()
-                })
-                .await?;
-            if let Some(result) = result {
+        Forgot::VerifyEmail {
+            email,
+            captcha_token,
+        } => {
+            if *CAPTCHA_ON_FORGOT {
+                captcha::validate(&req, captcha_token).await?;
+            }
+            if let Some(result) = users.find_by_email(&email).await? {
                 let token = generate_continue_token_long();
-                task::spawn(send_reset_email(email.clone(), token.clone()));
+                let locale = negotiate_locale(&req, result.locale.as_deref());
+                // the new password is registered under the stored address, not the one typed in
+                send_reset_email(&req, result.email.clone(), token.clone(), locale).await?;
                 PENDING_FORGOTS1.insert(
                     token,
                     PendingForgot {
                         time: get_time_secs(),
                         user_id: result.id,
-                        email,
+                        email: result.email,
+                        verified: false,
+                        attempts: 0,
+                        passkey_state: None,
+                        password_suite: None,
+                        reset_request: None,
                     },
                 );
             }
             Ok(web::Json(ForgotResponse::VerifyEmail {}))
         }
+        Forgot::BeginVerification { continue_token } => {
+            let (user_id, _, _) = get_pending(&continue_token)?;
+            let user = users.find(&user_id).await?.ok_or(Error::UserNotFound)?;
+            let second_factor_required = has_second_factor(passkeys.get_ref(), &user).await?;
+            let credentials = passkeys
+                .list(&user_id)
+                .await?
+                .into_iter()
+                .map(|p| p.credential)
+                .collect::<Vec<_>>();
+            let passkey_challenge = if credentials.is_empty() {
+                None
+            } else {
+                let (rcr, auth_state) = webauthn.start_passkey_authentication(&credentials)?;
+                if let Some(mut pending) = PENDING_FORGOTS1.get_mut(&continue_token) {
+                    pending.passkey_state = Some(auth_state);
+                }
+                Some(rcr)
+            };
+            Ok(web::Json(ForgotResponse::BeginVerification {
+                second_factor_required,
+                mfa_enabled: user.mfa_enabled,
+                passkey_challenge,
+            }))
+        }
+        Forgot::VerifySecondFactor {
+            continue_token,
+            code,
+            credential,
+        } => {
+            let (user_id, email, _) = get_pending(&continue_token)?;
+            let user = users.find(&user_id).await?.ok_or(Error::UserNotFound)?;
+            let verified = if let Some(code) = code {
+                match (&user.mfa_secret, user.mfa_enabled) {
+                    (Some(secret), true) if verify_totp(secret, &email, &code) => true,
+                    (_, true) => codes.consume(&user_id, &code).await?,
+                    _ => false,
+                }
+            } else if let Some(credential) = credential {
+                let state = PENDING_FORGOTS1
+                    .get(&continue_token)
+                    .and_then(|pending| pending.passkey_state.clone());
+                match state {
+                    Some(state) => webauthn
+                        .finish_passkey_authentication(&credential, &state)
+                        .is_ok(),
+                    None => false,
+                }
+            } else {
+                false
+            };
+            if !verified {
+                security_event::record(&req, SecurityEventKind::LoginFailed, &user_id, None).await;
+                let attempts = PENDING_FORGOTS1
+                    .get_mut(&continue_token)
+                    .map(|mut pending| {
+                        pending.attempts += 1;
+                        pending.attempts
+                    })
+                    .unwrap_or(MAX_VERIFICATION_ATTEMPTS);
+                if attempts >= MAX_VERIFICATION_ATTEMPTS {
+                    PENDING_FORGOTS1.remove(&continue_token);
+                }
+                return Err(Error::IncorrectCode);
+            }
+            if let Some(mut pending) = PENDING_FORGOTS1.get_mut(&continue_token) {
+                pending.verified = true;
+                pending.passkey_state = None;
+            }
+            Ok(web::Json(ForgotResponse::VerifySecondFactor {}))
+        }
+        Forgot::RequestDelayedReset { continue_token } => {
+            let (user_id, email, _) = get_pending(&continue_token)?;
+            let now = get_time_secs();
+            let available_at = now + *RESET_DELAY_HOURS * 3600;
+            reset_requests
+                .create(ResetRequest {
+                    token: continue_token.clone(),
+                    user_id: user_id.clone(),
+                    email,
+                    created_at: now,
+                    available_at,
+                })
+                .await?;
+            PENDING_FORGOTS1.remove(&continue_token);
+            security_event::record(
+                &req,
+                SecurityEventKind::PasswordResetRequested,
+                &user_id,
+                None,
+            )
+            .await;
+            notify(&req, &user_id, SecurityNotification::PasswordResetRequested).await;
+            Ok(web::Json(ForgotResponse::RequestDelayedReset {
+                available_at,
+            }))
+        }
         Forgot::ResetPassword {
             continue_token,
             message,
         } => {
-            let forgot_session = PENDING_FORGOTS1.get(&continue_token);
-            let Some(forgot_session) = forgot_session else {
-                return Err(Error::SessionExpired);
+            let (user_id, email, reset_request) = match get_pending(&continue_token) {
+                Ok((user_id, email, verified)) => {
+                    if !verified {
+                        let user = users.find(&user_id).await?.ok_or(Error::UserNotFound)?;
+                        if has_second_factor(passkeys.get_ref(), &user).await? {
+                            return Err(Error::SecondFactorRequired);
+                        }
+                    }
+                    PENDING_FORGOTS1.remove(&continue_token);
+                    (user_id, email, None)
+                }
+                Err(e) => {
+                    let request = find_delayed_reset(reset_requests.get_ref(), &continue_token)
+                        .await?
+                        .ok_or(e)?;
+                    (request.user_id, request.email, Some(request.token))
+                }
             };
-            if get_time_secs() - forgot_session.time > 3600 {
-                drop(forgot_session);
-                PENDING_FORGOTS1.remove(&continue_token);
-                return Err(Error::SessionExpired);
-            }
+            let password_suite = current_suite(settings.get_ref()).await?;
             let result = begin_registration(
-                forgot_session.email.clone(),
-                RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
+                settings.get_ref(),
+                &password_suite,
+                email.clone(),
+                &BASE64.decode(message)?,
             )
             .await?;
-            PENDING_FORGOTS1.remove(&continue_token);
             let new_continue_token = generate_continue_token_long();
             PENDING_FORGOTS2.insert(
                 new_continue_token.clone(),
                 PendingForgot {
                     time: get_time_secs(),
-                    user_id: forgot_session.user_id.clone(),
-                    email: forgot_session.email.clone(),
+                    user_id,
+                    email,
+                    verified: true,
+                    attempts: 0,
+                    passkey_state: None,
+                    password_suite: Some(password_suite.clone()),
+                    reset_request,
                 },
             );
-            drop(forgot_session);
             Ok(web::Json(ForgotResponse::ResetPassword {
                 continue_token: new_continue_token.clone(),
                 message: BASE64.encode(result),
+                suite: password_suite,
             }))
         }
         Forgot::FinishReset {
             continue_token,
             message,
         } => {
-            let Some(session) = PENDING_FORGOTS2.get(&continue_token) else {
+            let Some((time, user_id, Some(password_suite), reset_request)) =
+                PENDING_FORGOTS2.get(&continue_token).map(|session| {
+                    (
+                        session.time,
+                        session.user_id.clone(),
+                        session.password_suite.clone(),
+                        session.reset_request.clone(),
+                    )
+                })
+            else {
                 return Err(Error::SessionExpired);
             };
-            if get_time_secs() - session.time > 600 {
+            if get_time_secs() - time > 600 {
                 PENDING_FORGOTS2.remove(&continue_token);
                 return Err(Error::SessionExpired);
             }
-            let password_data =
-                finish_registration(RegistrationUpload::deserialize(&BASE64.decode(message)?)?)?;
-            let bin = Binary {
-                bytes: password_data,
-                subtype: bson::spec::BinarySubtype::Generic,
-            };
-            let collection = crate::database::user::get_collection();
-            collection
-                .update_one(
-                    doc! {
-                        "id": session.user_id.clone()
-                    },
-                    doc! {
-                        "$set": {
-                            "password_data": bin
-                        }
-                    },
-                )
+            let password_data = finish_registration(&password_suite, &BASE64.decode(message)?)?;
+            if let Some(token) = &reset_request {
+                // used by another reset meanwhile, or cancelled by the owner's recovery
+                if reset_requests.find(token).await?.is_none() {
+                    PENDING_FORGOTS2.remove(&continue_token);
+                    return Err(Error::SessionExpired);
+                }
+            }
+            let revoked_tokens = users
+                .set_password(&user_id, password_data, password_suite, true)
                 .await?;
+            if let Some(token) = &reset_request {
+                reset_requests.delete(token).await?;
+            }
+            clear_pending_state(&user_id);
+            security_event::record(&req, SecurityEventKind::PasswordReset, &user_id, None).await;
+            if revoked_tokens > 0 {
+                security_event::record(
+                    &req,
+                    SecurityEventKind::PersonalTokensRevoked,
+                    &user_id,
+                    None,
+                )
+                .await;
+            }
+            notify(&req, &user_id, SecurityNotification::PasswordReset).await;
             Ok(web::Json(ForgotResponse::FinishReset {}))
         }
     }
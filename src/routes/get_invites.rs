@@ -0,0 +1,37 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{authenticate::Authenticate, errors::Result, store::InviteStore};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteEntry {
+    code: String,
+    max_uses: Option<u32>,
+    uses: u32,
+    expires_at: Option<u64>,
+    created_at: u64,
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    invites: Data<dyn InviteStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let invites = invites
+        .list(&jwt.jwt_content.id)
+        .await?
+        .into_iter()
+        .map(|i| InviteEntry {
+            code: i.code,
+            max_uses: i.max_uses,
+            uses: i.uses,
+            expires_at: i.expires_at,
+            created_at: i.created_at,
+        })
+        .collect::<Vec<_>>();
+    Ok(web::Json(invites))
+}
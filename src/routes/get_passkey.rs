@@ -1,10 +1,11 @@
-use actix_web::{web, Responder};
-use futures_util::StreamExt;
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
-use crate::{authenticate::Authenticate, database::passkey, errors::Result};
+use crate::{authenticate::Authenticate, errors::Result, store::PasskeyStore};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -13,17 +14,12 @@ pub struct PasskeyEntry {
     pub friendly_name: String,
 }
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    passkeys: Data<dyn PasskeyStore>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let passkeys = passkey::get_collection()
-        .find(doc! {
-            "user_id": jwt.jwt_content.id
-        })
-        .await?;
-    let passkeys = passkeys.collect::<Vec<_>>().await;
-    let mut passkeys = passkeys
-        .into_iter()
-        .collect::<std::result::Result<Vec<_>, mongodb::error::Error>>()?;
+    let mut passkeys = passkeys.list(&jwt.jwt_content.id).await?;
     passkeys.sort_by(|a, b| {
         Ulid::from_string(&b.id)
             .unwrap()
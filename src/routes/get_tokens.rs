@@ -0,0 +1,49 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate, database::personal_token::PersonalToken, errors::Result,
+    store::PersonalTokenStore,
+};
+
+// Everything but the token hash
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalTokenResponse {
+    id: String,
+    name: String,
+    scopes: Vec<String>,
+    created_at: u64,
+    expires_at: u64,
+    last_used_at: Option<u64>,
+}
+
+impl From<PersonalToken> for PersonalTokenResponse {
+    fn from(token: PersonalToken) -> Self {
+        PersonalTokenResponse {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    tokens: Data<dyn PersonalTokenStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let tokens = tokens
+        .list(&jwt.jwt_content.id)
+        .await?
+        .into_iter()
+        .map(PersonalTokenResponse::from)
+        .collect::<Vec<_>>();
+    Ok(web::Json(tokens))
+}
@@ -0,0 +1,12 @@
+use actix_web::{web, Responder};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+}
+
+// Liveness only, so a slow database doesn't get the process restarted
+pub async fn handle() -> impl Responder {
+    web::Json(HealthResponse { status: "ok" })
+}
@@ -0,0 +1,146 @@
+// RFC 7662 token introspection for resource servers, which authenticate with HTTP Basic using
+// one of INTROSPECTION_CLIENTS, or as a service account with the `introspect` scope. Tokens that
+// can't be used are only ever reported as inactive.
+
+use actix_web::{
+    http::header::{CacheControl, CacheDirective, AUTHORIZATION},
+    web::{self, Data},
+    HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::{validate_token, ServiceAuthenticate},
+    database::service_account::SCOPE_INTROSPECT,
+    environment::{
+        ESCALATION_TIMEOUT, INTROSPECTION_CACHE_SECONDS, INTROSPECTION_CLIENTS,
+        UNVERIFIED_BLOCKED_SERVICES,
+    },
+    errors::{Error, Result},
+    store::{SessionStore, UserStore},
+    utilities::{get_time_secs, secret_matches},
+};
+
+use super::login::ACTIVE_ESCALATIONS;
+
+#[derive(Deserialize, Serialize)]
+pub struct Introspect {
+    token: String,
+    // there is only one kind of token, so the hint is accepted and ignored
+    token_type_hint: Option<String>,
+}
+
+// Field names are the registered RFC 7662 and RFC 7519 claims, hence not camelCase
+#[derive(Default, Deserialize, Serialize)]
+pub struct IntrospectResponse {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amr: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    escalated: Option<bool>,
+    // when the session's escalation runs out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    escalation_exp: Option<u64>,
+}
+
+fn authenticate_client(req: &HttpRequest) -> Result<String> {
+    let credentials = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Basic "))
+        .and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(Error::InvalidClient)?;
+    let (id, secret) = credentials.split_once(':').ok_or(Error::InvalidClient)?;
+    INTROSPECTION_CLIENTS
+        .iter()
+        .find(|(client, expected)| client == id && secret_matches(secret, expected))
+        .map(|(client, _)| client.clone())
+        .ok_or(Error::InvalidClient)
+}
+
+// The latest escalation of the session that is still valid
+fn escalation_expiry(session_id: &str) -> Option<u64> {
+    let now = get_time_secs();
+    ACTIVE_ESCALATIONS
+        .iter()
+        .filter(|escalation| escalation.session_id == session_id)
+        .map(|escalation| escalation.time + *ESCALATION_TIMEOUT)
+        .filter(|expires_at| *expires_at >= now)
+        .max()
+}
+
+fn inactive() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(IntrospectResponse::default())
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    service: web::ReqData<Result<ServiceAuthenticate>>,
+    introspect: web::Form<Introspect>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+) -> Result<HttpResponse> {
+    let client_id = match service.into_inner() {
+        Ok(service) => {
+            service.require(SCOPE_INTROSPECT)?;
+            service.name
+        }
+        Err(_) => authenticate_client(&req)?,
+    };
+    let Ok(token) = validate_token(sessions.get_ref(), &introspect.token).await else {
+        return Ok(inactive());
+    };
+    let Some(user) = users.find(&token.jwt_content.id).await? else {
+        return Ok(inactive());
+    };
+    if !user.email_verified && UNVERIFIED_BLOCKED_SERVICES.contains(&client_id) {
+        return Ok(inactive());
+    }
+    let session = sessions.find(&token.session_id).await?;
+    let escalation_exp = escalation_expiry(&token.session_id);
+    let exp = (token.jwt_content.expires_at / 1000) as u64;
+    // never cached past the token's own expiry
+    let max_age = (*INTROSPECTION_CACHE_SECONDS).min(exp.saturating_sub(get_time_secs()));
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::Private,
+            CacheDirective::MaxAge(max_age as u32),
+        ]))
+        .json(IntrospectResponse {
+            active: true,
+            client_id: Some(client_id),
+            token_type: Some("Bearer".to_string()),
+            sub: Some(user.id),
+            username: Some(user.username),
+            sid: Some(token.session_id),
+            iat: Some((token.jwt_content.issued_at / 1000) as u64),
+            exp: Some(exp),
+            amr: session
+                .map(|session| session.auth_methods)
+                .filter(|methods| !methods.is_empty()),
+            email_verified: Some(user.email_verified),
+            escalated: Some(escalation_exp.is_some()),
+            escalation_exp,
+        }))
+}
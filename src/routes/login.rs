@@ -1,27 +1,42 @@
-use actix_web::{web, Responder};
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
 use lazy_static::lazy_static;
-use mongodb::bson::doc;
-use opaque_ke::{CredentialFinalization, CredentialRequest, ServerLogin};
 use serde::{Deserialize, Serialize};
 use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::instrument;
 use ulid::Ulid;
 
 use crate::{
-    authenticate::{validate_token, UserJwt},
-    constants::{LONG_SESSION, SHORT_SESSION},
-    database::{self, session::Session, user::User},
-    environment::{JWT_SECRET, SERVICE_NAME},
+    authenticate::{sign_token, validate_token, UserJwt},
+    captcha,
+    constants::CONTINUE_TIMEOUT,
+    database::{
+        security_event::{self, SecurityEventKind},
+        session::Session,
+        user::User,
+    },
+    environment::{CAPTCHA_LOGIN_THRESHOLD, LONG_SESSION, SERVICE_NAME, SHORT_SESSION},
     errors::{Error, Result},
-    opaque::{begin_login, finish_login, Default},
-    utilities::{generate_continue_token_long, get_time_millis, get_time_secs},
+    metrics,
+    notifications::notify_new_device,
+    opaque::{
+        begin_login, begin_registration, current_suite, finish_login, finish_registration,
+        LoginState, PasswordSuite,
+    },
+    store::{CodeStore, SessionStore, SettingsStore, UserStore},
+    utilities::{
+        clear_password_flows, generate_continue_token_long, get_time_millis, get_time_secs,
+    },
 };
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
 pub enum Login {
+    #[serde(rename_all = "camelCase")]
     BeginLogin {
         email: String,
         message: String,
@@ -29,6 +44,8 @@ pub enum Login {
         escalate: bool,
         // req'd if escalating an existing session
         token: Option<String>,
+        // req'd after repeated failed attempts
+        captcha_token: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     FinishLogin {
@@ -42,6 +59,30 @@ pub enum Login {
         code: String,
         continue_token: String,
     },
+    // re-registers the same password with the current suite, keeping the upgrade token
+    #[serde(rename_all = "camelCase")]
+    BeginUpgrade {
+        continue_token: String,
+        message: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    FinishUpgrade {
+        continue_token: String,
+        message: String,
+    },
+}
+
+impl Login {
+    // for spans, as sent in `stage`
+    fn stage(&self) -> &'static str {
+        match self {
+            Login::BeginLogin { .. } => "BEGIN_LOGIN",
+            Login::FinishLogin { .. } => "FINISH_LOGIN",
+            Login::Mfa { .. } => "MFA",
+            Login::BeginUpgrade { .. } => "BEGIN_UPGRADE",
+            Login::FinishUpgrade { .. } => "FINISH_UPGRADE",
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -51,26 +92,40 @@ pub enum LoginResponse {
     BeginLogin {
         continue_token: String,
         message: String,
+        // what the client has to finish the login with
+        suite: PasswordSuite,
     },
     #[serde(rename_all = "camelCase")]
     FinishLogin {
         mfa_enabled: bool,
         continue_token: Option<String>,
         token: Option<String>,
+        // set once logged in if the password should be upgraded to the current suite
+        upgrade_token: Option<String>,
     },
+    #[serde(rename_all = "camelCase")]
     Mfa {
         token: String,
+        upgrade_token: Option<String>,
     },
+    #[serde(rename_all = "camelCase")]
+    BeginUpgrade {
+        message: String,
+        suite: PasswordSuite,
+    },
+    FinishUpgrade {},
 }
 
+#[derive(Clone)]
 pub struct PendingLogin {
     pub time: u64,
     pub user: User,
     pub email: String,
-    pub data: ServerLogin<Default>,
+    pub data: LoginState,
     pub existing_session: Option<Session>,
 }
 
+#[derive(Clone)]
 pub struct PendingMfa {
     pub time: u64,
     pub user: User,
@@ -80,6 +135,22 @@ pub struct PendingMfa {
     pub existing_session: Option<Session>,
 }
 
+#[derive(Clone)]
+pub struct PendingUpgrade {
+    pub time: u64,
+    pub user_id: String,
+    pub email: String,
+    // the session the login created or escalated, the upgrade ends with it
+    pub session_id: String,
+    // chosen when the upgrade begins
+    pub password_suite: Option<PasswordSuite>,
+}
+
+pub struct FailedLogins {
+    pub time: u64,
+    pub count: u64,
+}
+
 pub struct ActiveEscalation {
     pub session_id: String,
     pub user_id: String,
@@ -91,9 +162,82 @@ lazy_static! {
     pub static ref PENDING_LOGINS: DashMap<String, PendingLogin> = DashMap::new();
     pub static ref PENDING_MFAS: DashMap<String, PendingMfa> = DashMap::new();
     pub static ref ACTIVE_ESCALATIONS: DashMap<String, ActiveEscalation> = DashMap::new();
+    pub static ref PENDING_UPGRADES: DashMap<String, PendingUpgrade> = DashMap::new();
+    // keyed by user id, so every spelling of an email and every way of signing in counts
+    pub static ref FAILED_LOGINS: DashMap<String, FailedLogins> = DashMap::new();
+}
+
+pub fn record_failed_login(user_id: &str) {
+    let mut failed = FAILED_LOGINS
+        .entry(user_id.to_string())
+        .or_insert(FailedLogins { time: 0, count: 0 });
+    failed.time = get_time_secs();
+    failed.count += 1;
+}
+
+// Only handed out after every factor passed, since the client could register any password
+fn offer_upgrade(
+    user: &User,
+    email: &str,
+    session_id: &str,
+    current: &PasswordSuite,
+) -> Option<String> {
+    if user.password_suite == *current {
+        return None;
+    }
+    let upgrade_token = generate_continue_token_long();
+    PENDING_UPGRADES.insert(
+        upgrade_token.clone(),
+        PendingUpgrade {
+            time: get_time_secs(),
+            user_id: user.id.clone(),
+            email: email.to_string(),
+            session_id: session_id.to_string(),
+            password_suite: None,
+        },
+    );
+    Some(upgrade_token)
+}
+
+// The upgrade only lives as long as the session it was offered to
+async fn get_upgrade(sessions: &dyn SessionStore, continue_token: &str) -> Result<PendingUpgrade> {
+    let Some(upgrade) = PENDING_UPGRADES
+        .get(continue_token)
+        .map(|upgrade| upgrade.clone())
+    else {
+        return Err(Error::SessionExpired);
+    };
+    if get_time_secs() - upgrade.time > CONTINUE_TIMEOUT
+        || sessions.find(&upgrade.session_id).await?.is_none()
+    {
+        PENDING_UPGRADES.remove(continue_token);
+        return Err(Error::SessionExpired);
+    }
+    Ok(upgrade)
+}
+
+fn requires_captcha(user_id: &str) -> bool {
+    if *CAPTCHA_LOGIN_THRESHOLD == 0 {
+        return false;
+    }
+    FAILED_LOGINS
+        .get(user_id)
+        .map(|failed| {
+            get_time_secs().saturating_sub(failed.time) <= CONTINUE_TIMEOUT
+                && failed.count >= *CAPTCHA_LOGIN_THRESHOLD
+        })
+        .unwrap_or(false)
 }
 
-pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
+#[instrument(name = "login", skip_all, fields(stage = login.stage()))]
+pub async fn handle(
+    req: HttpRequest,
+    login: web::Json<Login>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+    codes: Data<dyn CodeStore>,
+    settings: Data<dyn SettingsStore>,
+) -> Result<impl Responder> {
     let login = login.into_inner();
     match login {
         Login::BeginLogin {
@@ -101,42 +245,47 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             message,
             escalate,
             token,
+            captcha_token,
         } => {
+            let user = users.find_by_email(&email).await?;
+            if user.as_ref().is_some_and(|user| requires_captcha(&user.id)) {
+                captcha::validate(&req, captcha_token).await?;
+            }
             let existing_session = if escalate {
                 let Some(token) = token else {
                     return Err(Error::MissingToken);
                 };
-                validate_token(&token.clone()).await?;
-                let collection = crate::database::session::get_collection();
-                let session = collection
-                    .find_one(doc! {
-                        "token": token.clone()
-                    })
+                validate_token(sessions.get_ref(), &token).await?;
+                let session = sessions
+                    .find_by_token(&token)
                     .await?
                     .ok_or(Error::SessionExpired)?;
                 Some(session)
             } else {
                 None
             };
-            let collection = crate::database::user::get_collection();
-            let user = collection
-                .find_one(doc! {
-                    "email": email.clone()
-                })
-                .await?;
+            // unknown emails look like accounts that have been upgraded
+            let suite = match &user {
+                Some(user) => user.password_suite.clone(),
+                None => current_suite(settings.get_ref()).await?,
+            };
             let password_data = user.clone().map(|x| x.password_data);
+            // the password file is bound to the stored address, which may differ in case
+            let identifier = user.as_ref().map(|x| x.email.clone()).unwrap_or(email);
             let (data, state) = begin_login(
-                email.clone(),
+                settings.get_ref(),
+                &suite,
+                identifier,
                 password_data,
-                CredentialRequest::deserialize(&BASE64.decode(message)?)?,
+                &BASE64.decode(message)?,
             )
             .await?;
             let continue_token = generate_continue_token_long();
             if let Some(user) = user {
                 let pending_login = PendingLogin {
                     time: get_time_secs(),
+                    email: user.email.clone(),
                     user,
-                    email,
                     data: state,
                     existing_session,
                 };
@@ -145,6 +294,7 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             Ok(web::Json(LoginResponse::BeginLogin {
                 continue_token,
                 message: BASE64.encode(data),
+                suite,
             }))
         }
         Login::FinishLogin {
@@ -153,21 +303,34 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             persist,
             friendly_name,
         } => {
-            let pending_login = PENDING_LOGINS.get(&continue_token);
+            // cloned so no shard lock is held across the awaits below
+            let pending_login = PENDING_LOGINS
+                .get(&continue_token)
+                .map(|pending| pending.clone());
             let pending_login = match pending_login {
                 Some(pending_login) => pending_login,
                 None => return Err(Error::SessionExpired),
             };
             if get_time_secs() - pending_login.time > 3600 {
-                drop(pending_login);
                 PENDING_LOGINS.remove(&continue_token);
                 return Err(Error::SessionExpired);
             }
-            finish_login(
-                pending_login.data.clone(),
-                CredentialFinalization::deserialize(&BASE64.decode(message)?)?,
-            )?;
+            if let Err(e) = finish_login(pending_login.data.clone(), &BASE64.decode(message)?) {
+                security_event::record(
+                    &req,
+                    SecurityEventKind::LoginFailed,
+                    &pending_login.user.id,
+                    None,
+                )
+                .await;
+                record_failed_login(&pending_login.user.id);
+                metrics::login("password", false);
+                return Err(e);
+            }
             let user = pending_login.user.clone();
+            if user.pending_approval {
+                return Err(Error::AccountPendingApproval);
+            }
             if let Some(existing_session) = pending_login.existing_session.clone() {
                 if user.id != existing_session.user_id {
                     return Err(Error::UserMismatch);
@@ -184,58 +347,64 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
                     existing_session: pending_login.existing_session.clone(),
                 };
                 PENDING_MFAS.insert(new_continue_token.clone(), mfa_session);
-                drop(pending_login);
                 PENDING_LOGINS.remove(&continue_token);
                 Ok(web::Json(LoginResponse::FinishLogin {
                     mfa_enabled: true,
                     continue_token: Some(new_continue_token),
                     token: None,
+                    upgrade_token: None,
                 }))
             } else {
                 let persist = persist.unwrap_or(false);
                 let millis = get_time_millis();
                 let expires_at = if persist {
-                    millis + LONG_SESSION
+                    millis + *LONG_SESSION
                 } else {
-                    millis + SHORT_SESSION
+                    millis + *SHORT_SESSION
                 };
-                let token = encode(
-                    &Header::default(),
-                    &UserJwt {
-                        id: user.id.clone(),
-                        issued_at: millis,
-                        expires_at,
-                    },
-                    &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-                )
-                .expect("Unexpected error: failed to encode token");
-                if let Some(existing_session) = pending_login.existing_session.clone() {
-                    ACTIVE_ESCALATIONS.insert(
-                        token.clone(),
-                        ActiveEscalation {
-                            session_id: existing_session.id.clone(),
-                            time: get_time_secs(),
+                let token = sign_token(&UserJwt {
+                    id: user.id.clone(),
+                    issued_at: millis,
+                    expires_at,
+                });
+                let session_id =
+                    if let Some(existing_session) = pending_login.existing_session.clone() {
+                        ACTIVE_ESCALATIONS.insert(
+                            token.clone(),
+                            ActiveEscalation {
+                                session_id: existing_session.id.clone(),
+                                time: get_time_secs(),
+                                token: token.clone(),
+                                user_id: user.id.clone(),
+                            },
+                        );
+                        existing_session.id
+                    } else {
+                        let sid = Ulid::new().to_string();
+                        let session = Session {
+                            id: sid.clone(),
                             token: token.clone(),
+                            friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
                             user_id: user.id.clone(),
-                        },
-                    );
-                } else {
-                    let sid = Ulid::new().to_string();
-                    let session = Session {
-                        id: sid,
-                        token: token.clone(),
-                        friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
-                        user_id: user.id.clone(),
+                            auth_methods: vec!["pwd".to_string()],
+                        };
+                        sessions.create(session).await?;
+                        notify_new_device(&req, &user.id).await;
+                        sid
                     };
-                    let sessions = crate::database::session::get_collection();
-                    sessions.insert_one(session).await?;
-                }
-                drop(pending_login);
+                security_event::record(&req, SecurityEventKind::Login, &user.id, Some(&session_id))
+                    .await;
+                metrics::login("password", true);
+                FAILED_LOGINS.remove(&user.id);
+                let current = current_suite(settings.get_ref()).await?;
+                let upgrade_token =
+                    offer_upgrade(&user, &pending_login.email, &session_id, &current);
                 PENDING_LOGINS.remove(&continue_token);
                 Ok(web::Json(LoginResponse::FinishLogin {
                     token: Some(token),
                     continue_token: None,
                     mfa_enabled: false,
+                    upgrade_token,
                 }))
             }
         }
@@ -243,12 +412,13 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             code,
             continue_token,
         } => {
-            let mfa_session = PENDING_MFAS.get(&continue_token);
+            let mfa_session = PENDING_MFAS
+                .get(&continue_token)
+                .map(|pending| pending.clone());
             let Some(mfa_session) = mfa_session else {
                 return Err(Error::SessionExpired);
             };
             if get_time_secs() - mfa_session.time > 3600 {
-                drop(mfa_session);
                 PENDING_MFAS.remove(&continue_token);
                 return Err(Error::SessionExpired);
             }
@@ -267,42 +437,33 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
             let current_code = totp
                 .generate_current()
                 .expect("Unexpected error: failed to generate code");
-            if current_code != code {
-                let codes = database::code::get_collection();
-                let code = codes
-                    .find_one(doc! {
-                        "code": code,
-                        "user_id": &mfa_session.user.id
-                    })
-                    .await?;
-                let Some(code) = code else {
-                    return Err(Error::IncorrectCode);
-                };
-                codes
-                    .delete_one(doc! {
-                        "code": code.code
-                    })
-                    .await?;
+            if current_code != code && !codes.consume(&mfa_session.user.id, &code).await? {
+                security_event::record(
+                    &req,
+                    SecurityEventKind::LoginFailed,
+                    &mfa_session.user.id,
+                    None,
+                )
+                .await;
+                record_failed_login(&mfa_session.user.id);
+                metrics::mfa(false);
+                metrics::login("password", false);
+                return Err(Error::IncorrectCode);
             }
             let persist = mfa_session.persist.unwrap_or(false);
             let millis = get_time_millis();
             let expires_at = if persist {
-                millis + 2592000000
+                millis + *LONG_SESSION
             } else {
-                millis + 604800000
+                millis + *SHORT_SESSION
             };
             let id = mfa_session.user.id.clone();
-            let token = encode(
-                &Header::default(),
-                &UserJwt {
-                    id: id.clone(),
-                    issued_at: millis,
-                    expires_at,
-                },
-                &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-            )
-            .expect("Unexpected error: failed to encode token");
-            if let Some(existing_session) = mfa_session.existing_session.clone() {
+            let token = sign_token(&UserJwt {
+                id: id.clone(),
+                issued_at: millis,
+                expires_at,
+            });
+            let session_id = if let Some(existing_session) = mfa_session.existing_session.clone() {
                 ACTIVE_ESCALATIONS.insert(
                     token.clone(),
                     ActiveEscalation {
@@ -312,23 +473,78 @@ pub async fn handle(login: web::Json<Login>) -> Result<impl Responder> {
                         user_id: id.clone(),
                     },
                 );
+                existing_session.id
             } else {
                 let sid = ulid::Ulid::new().to_string();
                 let session = Session {
-                    id: sid,
+                    id: sid.clone(),
                     token: token.clone(),
                     friendly_name: mfa_session
                         .friendly_name
                         .clone()
                         .unwrap_or("Unknown".to_owned()),
-                    user_id: id,
+                    user_id: id.clone(),
+                    auth_methods: vec!["pwd".to_string(), "otp".to_string(), "mfa".to_string()],
                 };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-            }
-            drop(mfa_session);
+                sessions.create(session).await?;
+                notify_new_device(&req, &id).await;
+                sid
+            };
+            security_event::record(&req, SecurityEventKind::Login, &id, Some(&session_id)).await;
+            metrics::mfa(true);
+            metrics::login("password", true);
+            FAILED_LOGINS.remove(&id);
+            let current = current_suite(settings.get_ref()).await?;
+            let upgrade_token =
+                offer_upgrade(&mfa_session.user, &mfa_session.email, &session_id, &current);
             PENDING_MFAS.remove(&continue_token);
-            Ok(web::Json(LoginResponse::Mfa { token }))
+            Ok(web::Json(LoginResponse::Mfa {
+                token,
+                upgrade_token,
+            }))
+        }
+        Login::BeginUpgrade {
+            continue_token,
+            message,
+        } => {
+            let upgrade = get_upgrade(sessions.get_ref(), &continue_token).await?;
+            let suite = current_suite(settings.get_ref()).await?;
+            let result = begin_registration(
+                settings.get_ref(),
+                &suite,
+                upgrade.email,
+                &BASE64.decode(message)?,
+            )
+            .await?;
+            if let Some(mut upgrade) = PENDING_UPGRADES.get_mut(&continue_token) {
+                upgrade.password_suite = Some(suite.clone());
+            }
+            Ok(web::Json(LoginResponse::BeginUpgrade {
+                message: BASE64.encode(result),
+                suite,
+            }))
+        }
+        Login::FinishUpgrade {
+            continue_token,
+            message,
+        } => {
+            let upgrade = get_upgrade(sessions.get_ref(), &continue_token).await?;
+            let Some(suite) = upgrade.password_suite else {
+                return Err(Error::SessionExpired);
+            };
+            let password_data = finish_registration(&suite, &BASE64.decode(message)?)?;
+            users
+                .set_password(&upgrade.user_id, password_data, suite, false)
+                .await?;
+            clear_password_flows(&upgrade.user_id);
+            security_event::record(
+                &req,
+                SecurityEventKind::PasswordUpgraded,
+                &upgrade.user_id,
+                Some(&upgrade.session_id),
+            )
+            .await;
+            Ok(web::Json(LoginResponse::FinishUpgrade {}))
         }
     }
 }
@@ -1,12 +1,11 @@
 use actix_web::{
     web::{self, Data},
-    Responder,
+    HttpRequest, Responder,
 };
 use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
 use lazy_static::lazy_static;
-use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 use ulid::Ulid;
 use webauthn_rs::{
     prelude::{
@@ -16,15 +15,20 @@ use webauthn_rs::{
 };
 
 use crate::{
-    authenticate::UserJwt,
-    constants::{LONG_SESSION, SHORT_SESSION},
-    database::{self, passkey::get_collection, session::Session},
-    environment::JWT_SECRET,
+    authenticate::{sign_token, UserJwt},
+    database::{
+        security_event::{self, SecurityEventKind},
+        session::Session,
+    },
+    environment::{LONG_SESSION, SHORT_SESSION},
     errors::{Error, Result},
+    metrics,
+    notifications::notify_new_device,
+    store::{PasskeyStore, SessionStore, UserStore},
     utilities::{generate_continue_token_long, get_time_millis, get_time_secs},
 };
 
-use super::login::{ActiveEscalation, ACTIVE_ESCALATIONS};
+use super::login::{record_failed_login, ActiveEscalation, ACTIVE_ESCALATIONS, FAILED_LOGINS};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
@@ -43,6 +47,15 @@ pub enum Login {
     },
 }
 
+impl Login {
+    fn stage(&self) -> &'static str {
+        match self {
+            Login::BeginLogin { .. } => "BEGIN_LOGIN",
+            Login::FinishLogin { .. } => "FINISH_LOGIN",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum LoginResponse {
@@ -66,7 +79,15 @@ lazy_static! {
     pub static ref PENDING_LOGINS: DashMap<String, PendingLogin> = DashMap::new();
 }
 
-pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result<impl Responder> {
+#[instrument(name = "login_passkey", skip_all, fields(stage = login.stage()))]
+pub async fn handle(
+    req: HttpRequest,
+    login: web::Json<Login>,
+    webauthn: Data<Webauthn>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+    passkeys: Data<dyn PasskeyStore>,
+) -> Result<impl Responder> {
     let login = login.into_inner();
     match login {
         Login::BeginLogin { escalate, token } => {
@@ -74,11 +95,8 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                 let Some(token) = token else {
                     return Err(Error::MissingToken);
                 };
-                let collection = crate::database::session::get_collection();
-                let session = collection
-                    .find_one(doc! {
-                        "token": token.clone()
-                    })
+                let session = sessions
+                    .find_by_token(&token)
                     .await?
                     .ok_or(Error::SessionExpired)?;
                 Some(session)
@@ -116,23 +134,33 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                 PENDING_LOGINS.remove(&continue_token);
                 return Err(Error::SessionExpired);
             }
-            let passkey = get_collection()
-                .find_one(doc! {
-                    "credential_id": &message.id
-                })
+            let passkey = passkeys
+                .find_by_credential_id(&message.id)
                 .await?
                 .ok_or(Error::CredentialError)?;
-            webauthn.finish_discoverable_authentication(
+            if let Err(e) = webauthn.finish_discoverable_authentication(
                 &message,
                 pending_login.data.clone(),
                 &[DiscoverableKey::from(passkey.credential)],
-            )?;
-            let user = database::user::get_collection()
-                .find_one(doc! {
-                    "id": passkey.user_id.clone()
-                })
+            ) {
+                security_event::record(
+                    &req,
+                    SecurityEventKind::LoginFailed,
+                    &passkey.user_id,
+                    None,
+                )
+                .await;
+                record_failed_login(&passkey.user_id);
+                metrics::login("passkey", false);
+                return Err(e.into());
+            }
+            let user = users
+                .find(&passkey.user_id)
                 .await?
                 .ok_or(Error::CredentialError)?;
+            if user.pending_approval {
+                return Err(Error::AccountPendingApproval);
+            }
             if let Some(s) = &pending_login.existing_session {
                 if user.id != s.user_id {
                     return Err(Error::UserMismatch);
@@ -141,21 +169,17 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
             let persist = persist.unwrap_or(false);
             let millis = get_time_millis();
             let expires_at = if persist {
-                millis + LONG_SESSION
+                millis + *LONG_SESSION
             } else {
-                millis + SHORT_SESSION
+                millis + *SHORT_SESSION
             };
-            let token = encode(
-                &Header::default(),
-                &UserJwt {
-                    id: user.id.clone(),
-                    issued_at: millis,
-                    expires_at,
-                },
-                &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-            )
-            .expect("Unexpected error: failed to encode token");
-            if let Some(existing_session) = pending_login.existing_session.clone() {
+            let token = sign_token(&UserJwt {
+                id: user.id.clone(),
+                issued_at: millis,
+                expires_at,
+            });
+            let session_id = if let Some(existing_session) = pending_login.existing_session.clone()
+            {
                 ACTIVE_ESCALATIONS.insert(
                     token.clone(),
                     ActiveEscalation {
@@ -165,17 +189,25 @@ pub async fn handle(login: web::Json<Login>, webauthn: Data<Webauthn>) -> Result
                         user_id: user.id.clone(),
                     },
                 );
+                existing_session.id
             } else {
                 let sid = Ulid::new().to_string();
                 let session = Session {
-                    id: sid,
+                    id: sid.clone(),
                     token: token.clone(),
                     friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
                     user_id: user.id.clone(),
+                    // passkeys are hardware- or platform-bound keys
+                    auth_methods: vec!["hwk".to_string()],
                 };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-            }
+                sessions.create(session).await?;
+                notify_new_device(&req, &user.id).await;
+                sid
+            };
+            security_event::record(&req, SecurityEventKind::Login, &user.id, Some(&session_id))
+                .await;
+            metrics::login("passkey", true);
+            FAILED_LOGINS.remove(&user.id);
             drop(pending_login);
             PENDING_LOGINS.remove(&continue_token);
             Ok(web::Json(LoginResponse::FinishLogin { token }))
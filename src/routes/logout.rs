@@ -1,16 +1,20 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{authenticate::Authenticate, errors::Result, store::SessionStore};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogoutResponse {}
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    sessions: Data<dyn SessionStore>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions.delete_one(doc! { "token": jwt.jwt }).await?;
+    sessions.delete_by_token(&jwt.jwt).await?;
     Ok(web::Json(LogoutResponse {}))
 }
@@ -1,21 +1,44 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{
+    authenticate::Authenticate,
+    database::security_event::{self, SecurityEventKind},
+    errors::Result,
+    store::UserStore,
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogoutAllResponse {}
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    users: Data<dyn UserStore>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions
-        .delete_many(doc! {
-            "user_id": &jwt.jwt_content.id,
-            "token": doc! { "$ne": jwt.jwt }
-        })
+    let revoked_tokens = users
+        .revoke_sessions(&jwt.jwt_content.id, Some(&jwt.jwt))
         .await?;
+    security_event::record(
+        &req,
+        SecurityEventKind::OtherSessionsRevoked,
+        &jwt.jwt_content.id,
+        Some(&jwt.session_id),
+    )
+    .await;
+    if revoked_tokens > 0 {
+        security_event::record(
+            &req,
+            SecurityEventKind::PersonalTokensRevoked,
+            &jwt.jwt_content.id,
+            Some(&jwt.session_id),
+        )
+        .await;
+    }
     Ok(web::Json(LogoutAllResponse {}))
 }
@@ -1,21 +1,38 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::Authenticate, database::session::get_collection, errors::Result};
+use crate::{
+    authenticate::Authenticate,
+    database::security_event::{self, SecurityEventKind},
+    errors::Result,
+    store::SessionStore,
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LogoutOtherResponse {}
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     logout_other: web::Path<String>,
+    sessions: Data<dyn SessionStore>,
 ) -> Result<impl Responder> {
-    jwt.into_inner()?;
-    let sessions = get_collection();
-    sessions
-        .delete_one(doc! { "id": &logout_other.into_inner() })
-        .await?;
+    let jwt = jwt.into_inner()?;
+    if sessions
+        .delete(&logout_other.into_inner(), &jwt.jwt_content.id)
+        .await?
+    {
+        security_event::record(
+            &req,
+            SecurityEventKind::SessionRevoked,
+            &jwt.jwt_content.id,
+            Some(&jwt.session_id),
+        )
+        .await;
+    }
     Ok(web::Json(LogoutOtherResponse {}))
 }
@@ -0,0 +1,24 @@
+use actix_web::{http::header::AUTHORIZATION, HttpRequest, HttpResponse, Responder};
+
+use crate::{
+    environment::METRICS_TOKEN,
+    errors::{Error, Result},
+    metrics,
+    utilities::secret_matches,
+};
+
+pub async fn handle(req: HttpRequest) -> Result<impl Responder> {
+    let expected = METRICS_TOKEN.as_ref().ok_or(Error::MetricsUnavailable)?;
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(Error::MissingToken)?;
+    if !secret_matches(token, expected) {
+        return Err(Error::InvalidToken);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render()))
+}
@@ -1,18 +1,22 @@
-use actix_web::{web, Responder};
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
-use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use totp_rs::{Secret, TOTP};
 
 use crate::{
     authenticate::Authenticate,
     database::{
-        code,
-        user::{self, User},
+        security_event::{self, SecurityEventKind},
+        user::User,
     },
     environment::SERVICE_NAME,
     errors::{Error, Result},
+    notifications::{notify, SecurityNotification},
+    store::{SessionStore, UserStore},
     utilities::{generate_codes, get_time_secs, random_number, validate_escalation},
 };
 
@@ -47,43 +51,39 @@ pub struct PendingMfaSetup {
     pub secret: String,
     pub time: u64,
     pub user: User,
+    // recovery codes shown to the user, stored once setup is verified
+    pub codes: Vec<String>,
 }
 lazy_static! {
     pub static ref PENDING_MFA_SETUPS: DashMap<String, PendingMfaSetup> = DashMap::new();
 }
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     mfa: web::Json<Mfa>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
     let mfa = mfa.into_inner();
     match mfa {
         Mfa::Toggle { escalation_token } => {
-            validate_escalation(escalation_token, jwt.jwt).await?;
-            let user = user::get_collection()
-                .find_one(doc! {"id": jwt.jwt_content.id})
+            validate_escalation(sessions.get_ref(), escalation_token, jwt.jwt).await?;
+            let user = users
+                .find(&jwt.jwt_content.id)
                 .await?
                 .ok_or(Error::DatabaseError)?;
             if user.mfa_enabled {
-                user::get_collection()
-                    .update_one(
-                        doc! {
-                            "id": user.id.clone(),
-                        },
-                        doc! {
-                            "$set": {
-                                "mfa_enabled": false,
-                                "mfa_secret": None::<String>
-                            }
-                        },
-                    )
-                    .await?;
-                code::get_collection()
-                    .delete_many(doc! {
-                        "user_id": user.id.clone()
-                    })
-                    .await?;
+                users.disable_mfa(&user.id).await?;
+                security_event::record(
+                    &req,
+                    SecurityEventKind::MfaDisabled,
+                    &user.id,
+                    Some(&jwt.session_id),
+                )
+                .await;
+                notify(&req, &user.id, SecurityNotification::MfaDisabled).await;
                 Ok(web::Json(MfaResponse::Disable {}))
             } else {
                 let secret = random_number(160);
@@ -102,14 +102,15 @@ pub async fn handle(
                     .expect("Unexpected error: failed to generate QR code");
                 let continue_token = ulid::Ulid::new().to_string();
                 let code = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+                let codes = generate_codes();
                 let session = PendingMfaSetup {
                     time: get_time_secs(),
                     user,
                     secret: code.clone(),
                     totp,
+                    codes: codes.clone(),
                 };
                 PENDING_MFA_SETUPS.insert(continue_token.clone(), session);
-                let codes = generate_codes();
                 Ok(web::Json(MfaResponse::Enable {
                     continue_token,
                     qr,
@@ -129,6 +130,9 @@ pub async fn handle(
                     PENDING_MFA_SETUPS.remove(&continue_token);
                     return Err(Error::SessionExpired);
                 }
+                if enable_session.user.id != jwt.jwt_content.id {
+                    return Err(Error::UserMismatch);
+                }
                 let current = enable_session
                     .totp
                     .generate_current()
@@ -136,22 +140,19 @@ pub async fn handle(
                 if current != code {
                     return Err(Error::IncorrectCode);
                 }
-                let collection = user::get_collection();
-                collection
-                    .update_one(
-                        doc! {
-                            "id": enable_session.user.id.clone(),
-                        },
-                        doc! {
-                            "$set": {
-                                "mfa_enabled": true,
-                                "mfa_secret": enable_session.secret.clone()
-                            }
-                        },
-                    )
-                    .await?;
+                let user_id = enable_session.user.id.clone();
+                let secret = enable_session.secret.clone();
+                let codes = enable_session.codes.clone();
                 drop(enable_session);
+                users.enable_mfa(&user_id, &secret, codes).await?;
                 PENDING_MFA_SETUPS.remove(&continue_token);
+                security_event::record(
+                    &req,
+                    SecurityEventKind::MfaEnabled,
+                    &user_id,
+                    Some(&jwt.session_id),
+                )
+                .await;
                 Ok(web::Json(MfaResponse::EnableVerify {}))
             } else {
                 Err(Error::SessionExpired)
@@ -1,21 +1,231 @@
+use actix_extensible_rate_limit::backend::memory::InMemoryBackend;
+use actix_web::web::{self, Data};
+
+use crate::{
+    authenticate::JwtAuthentication,
+    captcha::pow::IssuedChallenges,
+    config,
+    logging::RequestTracing,
+    metrics::RequestMetrics,
+    passkey::create_webauthn,
+    utilities::{create_rate_limiter, create_success_rate_limiter},
+};
+
 pub mod account_settings;
+pub mod admin_create_service_account;
+pub mod admin_delete_service_account;
+pub mod admin_outbox;
+pub mod admin_registrations;
+pub mod admin_review_registration;
+pub mod admin_security_events;
+pub mod admin_service_accounts;
+pub mod admin_update_service_account;
+pub mod challenge;
+pub mod create_invite;
+pub mod create_token;
 pub mod current_user;
 pub mod delete;
+pub mod delete_invite;
 pub mod delete_passkey;
+pub mod delete_token;
 pub mod forgot;
+pub mod get_invites;
 pub mod get_passkey;
+pub mod get_tokens;
+pub mod healthz;
+pub mod introspect;
 pub mod ip;
 pub mod login;
 pub mod login_passkey;
 pub mod logout;
 pub mod logout_all;
 pub mod logout_other;
+pub mod metrics;
 pub mod mfa;
 pub mod profile_settings;
+pub mod readyz;
+pub mod recover;
 pub mod register;
 pub mod register_passkey;
+pub mod security_events;
 pub mod service;
 pub mod session;
 pub mod update_password;
 pub mod user;
+pub mod user_by_username;
 pub mod validate;
+pub mod verify_email;
+
+// Counters every worker has to share, `configure` runs once per worker so `main` builds
+// these once and clones them in
+#[derive(Clone)]
+pub struct RouteState {
+    challenges: Data<IssuedChallenges>,
+    api: InMemoryBackend,
+    forgot: InMemoryBackend,
+    recover: InMemoryBackend,
+    login: InMemoryBackend,
+    registration: InMemoryBackend,
+    verify_email: InMemoryBackend,
+    validate: InMemoryBackend,
+}
+
+impl Default for RouteState {
+    fn default() -> Self {
+        RouteState {
+            challenges: Data::new(IssuedChallenges::default()),
+            api: InMemoryBackend::builder().build(),
+            forgot: InMemoryBackend::builder().build(),
+            recover: InMemoryBackend::builder().build(),
+            login: InMemoryBackend::builder().build(),
+            registration: InMemoryBackend::builder().build(),
+            verify_email: InMemoryBackend::builder().build(),
+            validate: InMemoryBackend::builder().build(),
+        }
+    }
+}
+
+// The API as served by `main`, stores have to be registered on the app separately
+pub fn configure(cfg: &mut web::ServiceConfig, state: &RouteState) {
+    let limits = &config::get().rate_limits;
+    cfg.route("/healthz", web::get().to(healthz::handle))
+        .route("/readyz", web::get().to(readyz::handle))
+        .route("/metrics", web::get().to(metrics::handle));
+    cfg.service(
+        web::scope("/api")
+            .app_data(create_webauthn())
+            .app_data(state.challenges.clone())
+            .wrap(create_rate_limiter("api", &limits.api, state.api.clone()))
+            .wrap(JwtAuthentication)
+            .wrap(RequestMetrics)
+            .wrap(RequestTracing)
+            .route("/", web::get().to(service::handle))
+            .route(
+                "/forgot",
+                web::post()
+                    .to(forgot::handle)
+                    .wrap(create_success_rate_limiter(
+                        "forgot",
+                        &limits.forgot,
+                        state.forgot.clone(),
+                    )),
+            )
+            .route(
+                "/recover",
+                web::post()
+                    .to(recover::handle)
+                    .wrap(create_success_rate_limiter(
+                        "forgot",
+                        &limits.forgot,
+                        state.recover.clone(),
+                    )),
+            )
+            .route("/user", web::patch().to(account_settings::handle))
+            .route("/user", web::get().to(current_user::handle))
+            .route("/user", web::delete().to(delete::handle))
+            .route("/ip", web::get().to(ip::handle))
+            .route("/challenge", web::get().to(challenge::handle))
+            .route("/session", web::get().to(session::handle))
+            .route(
+                "/session",
+                web::post()
+                    .to(login::handle)
+                    .wrap(create_success_rate_limiter(
+                        "login",
+                        &limits.login,
+                        state.login.clone(),
+                    )),
+            )
+            .route("/session", web::delete().to(logout::handle))
+            .route("/session/{id}", web::delete().to(logout_other::handle))
+            .route("/session/all", web::delete().to(logout_all::handle))
+            .route("/user/mfa", web::patch().to(mfa::handle))
+            .route("/user/profile", web::patch().to(profile_settings::handle))
+            .route(
+                "/user",
+                web::post()
+                    .to(register::handle)
+                    .wrap(create_success_rate_limiter(
+                        "registration",
+                        &limits.registration,
+                        state.registration.clone(),
+                    )),
+            )
+            .route("/user/passkeys", web::post().to(register_passkey::handle))
+            .route(
+                "/user/passkeys/{id}",
+                web::delete().to(delete_passkey::handle),
+            )
+            .route("/user/passkeys", web::get().to(get_passkey::handle))
+            .route("/user/password", web::patch().to(update_password::handle))
+            .route(
+                "/user/verify-email",
+                web::post()
+                    .to(verify_email::handle)
+                    .wrap(create_success_rate_limiter(
+                        "verify_email",
+                        &limits.verify_email,
+                        state.verify_email.clone(),
+                    )),
+            )
+            .route(
+                "/user/security-events",
+                web::get().to(security_events::handle),
+            )
+            .route(
+                "/admin/users/{id}/security-events",
+                web::get().to(admin_security_events::handle),
+            )
+            .route("/admin/outbox", web::get().to(admin_outbox::handle))
+            .route(
+                "/admin/registrations",
+                web::get().to(admin_registrations::handle),
+            )
+            .route(
+                "/admin/registrations/{id}",
+                web::post().to(admin_review_registration::handle),
+            )
+            .route(
+                "/admin/service-accounts",
+                web::get().to(admin_service_accounts::handle),
+            )
+            .route(
+                "/admin/service-accounts",
+                web::post().to(admin_create_service_account::handle),
+            )
+            .route(
+                "/admin/service-accounts/{id}",
+                web::patch().to(admin_update_service_account::handle),
+            )
+            .route(
+                "/admin/service-accounts/{id}",
+                web::delete().to(admin_delete_service_account::handle),
+            )
+            .route("/user/invites", web::get().to(get_invites::handle))
+            .route("/user/invites", web::post().to(create_invite::handle))
+            .route(
+                "/user/invites/{code}",
+                web::delete().to(delete_invite::handle),
+            )
+            .route("/user/tokens", web::get().to(get_tokens::handle))
+            .route("/user/tokens", web::post().to(create_token::handle))
+            .route("/user/tokens/{id}", web::delete().to(delete_token::handle))
+            .route(
+                "/user/by-username/{username}",
+                web::get().to(user_by_username::handle),
+            )
+            .route("/user/{id}", web::get().to(user::handle))
+            .route("/session/passkeys", web::post().to(login_passkey::handle))
+            .route("/introspect", web::post().to(introspect::handle))
+            .route(
+                "/validate",
+                web::post()
+                    .to(validate::handle)
+                    .wrap(create_success_rate_limiter(
+                        "validate",
+                        &limits.validate,
+                        state.validate.clone(),
+                    )),
+            ),
+    );
+}
@@ -1,11 +1,13 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
-    database::{files::File, profile::get_collection},
     errors::{Error, Result},
+    store::{FileStore, ProfileStore, ProfileUpdate},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -24,51 +26,45 @@ pub struct ProfileSettingsResponse {}
 pub async fn handle(
     jwt: web::ReqData<Result<Authenticate>>,
     profile_settings: web::Json<ProfileSettings>,
+    profiles: Data<dyn ProfileStore>,
+    files: Data<dyn FileStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
     let profile_settings = profile_settings.into_inner();
 
-    let collection = get_collection();
-    let profile = collection
-        .find_one(doc! {"id": jwt.jwt_content.id.clone()})
+    let profile = profiles
+        .find(&jwt.jwt_content.id)
         .await?
         .ok_or(Error::DatabaseError)?;
-    let mut update_query = doc! {};
+    let mut update = ProfileUpdate::default();
     if let Some(display_name) = profile_settings.display_name {
         if display_name.trim().len() > 64 {
             return Err(Error::DisplayNameTooLong);
         }
-        update_query.insert("display_name", display_name.trim());
+        update.display_name = Some(display_name.trim().to_string());
     }
     if let Some(description) = profile_settings.description {
         if description.trim().len() > 2048 {
             return Err(Error::DescriptionTooLong);
         }
-        update_query.insert("description", description.trim());
+        update.description = Some(description.trim().to_string());
     }
     if let Some(website) = profile_settings.website {
         if website.trim().len() > 256 {
             return Err(Error::WebsiteTooLong);
         }
-        update_query.insert("website", website.trim());
+        update.website = Some(website.trim().to_string());
     }
     if let Some(avatar) = profile_settings.avatar {
         if avatar != "default" {
-            let file = File::get(&avatar).await?;
-            file.attach().await?;
+            let file = files.find(&avatar).await?.ok_or(Error::DatabaseError)?;
+            files.set_attached(&file.id, true).await?;
         }
-        if let Ok(file) = File::get(&profile.id).await {
-            file.detach().await?;
+        if let Some(file) = files.find(&profile.id).await? {
+            files.set_attached(&file.id, false).await?;
         }
-        update_query.insert("avatar", avatar);
+        update.avatar = Some(avatar);
     }
-    collection
-        .update_one(
-            doc! {"id": jwt.jwt_content.id},
-            doc! {
-                "$set": update_query
-            },
-        )
-        .await?;
+    profiles.update(&jwt.jwt_content.id, update).await?;
     Ok(web::Json(ProfileSettingsResponse {}))
 }
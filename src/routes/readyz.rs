@@ -0,0 +1,64 @@
+use actix_web::{web::Data, HttpResponse, Responder};
+use log::warn;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database,
+    opaque::open_server_setup,
+    store::{self, SettingsStore},
+};
+
+#[derive(Deserialize, Serialize)]
+pub struct ReadyResponse {
+    pub status: &'static str,
+    pub database: bool,
+    pub settings: bool,
+}
+
+async fn database_ready() -> bool {
+    // the in-memory stores never connect
+    if !store::uses_mongo() {
+        return true;
+    }
+    match database::get_database()
+        .run_command(doc! { "ping": 1 })
+        .await
+    {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Readiness check failed to ping MongoDB: {}", e);
+            false
+        }
+    }
+}
+
+// The current server setup has to be usable, or every login would fail
+async fn settings_ready(settings: &dyn SettingsStore) -> bool {
+    match settings.find().await {
+        Ok(Some(settings)) => open_server_setup(&settings, settings.current_server_setup).is_ok(),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Readiness check failed to read the server setup: {}", e);
+            false
+        }
+    }
+}
+
+pub async fn handle(settings: Data<dyn SettingsStore>) -> impl Responder {
+    let database = database_ready().await;
+    let settings = settings_ready(settings.get_ref()).await;
+    if database && settings {
+        HttpResponse::Ok().json(ReadyResponse {
+            status: "ready",
+            database,
+            settings,
+        })
+    } else {
+        HttpResponse::ServiceUnavailable().json(ReadyResponse {
+            status: "unavailable",
+            database,
+            settings,
+        })
+    }
+}
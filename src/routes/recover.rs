@@ -0,0 +1,75 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::RECOVERY_TIMEOUT,
+    database::security_event::{self, SecurityEventKind},
+    errors::{Error, Result},
+    routes::forgot::{PendingForgot, PENDING_FORGOTS1},
+    store::{RecoveryStore, UserStore},
+    utilities::{clear_pending_state, generate_continue_token_long, get_time_secs},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recover {
+    token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoverResponse {
+    // continues the forgot password flow at the RESET_PASSWORD stage
+    continue_token: String,
+}
+
+pub async fn handle(
+    req: HttpRequest,
+    recover: web::Json<Recover>,
+    users: Data<dyn UserStore>,
+    recovery_tokens: Data<dyn RecoveryStore>,
+) -> Result<impl Responder> {
+    let recover = recover.into_inner();
+    let recovery_token = recovery_tokens
+        .take(&recover.token)
+        .await?
+        .ok_or(Error::SessionExpired)?;
+    if get_time_secs() - recovery_token.created_at > RECOVERY_TIMEOUT {
+        return Err(Error::SessionExpired);
+    }
+    let user = users
+        .find(&recovery_token.user_id)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    // also cancels any delayed reset an attacker may have requested
+    let revoked_tokens = users.recover(&user.id).await?;
+    clear_pending_state(&user.id);
+    security_event::record(&req, SecurityEventKind::AccountRecovery, &user.id, None).await;
+    if revoked_tokens > 0 {
+        security_event::record(
+            &req,
+            SecurityEventKind::PersonalTokensRevoked,
+            &user.id,
+            None,
+        )
+        .await;
+    }
+    let continue_token = generate_continue_token_long();
+    PENDING_FORGOTS1.insert(
+        continue_token.clone(),
+        PendingForgot {
+            time: get_time_secs(),
+            user_id: user.id,
+            email: user.email,
+            verified: false,
+            attempts: 0,
+            passkey_state: None,
+            password_suite: None,
+            reset_request: None,
+        },
+    );
+    Ok(web::Json(RecoverResponse { continue_token }))
+}
@@ -1,24 +1,35 @@
-use actix_web::{web, Responder};
-use async_std::task;
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use dashmap::DashMap;
-use jsonwebtoken::{encode, EncodingKey, Header};
 use lazy_static::lazy_static;
-use mongodb::bson::doc;
-use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 use ulid::Ulid;
 
 use crate::{
-    authenticate::UserJwt,
-    constants::{LONG_SESSION, SHORT_SESSION},
-    database::{profile::UserProfile, session::Session, user::User},
-    environment::{JWT_SECRET, SMTP_ENABLED},
+    authenticate::{sign_token, UserJwt},
+    captcha,
+    constants::VERIFY_TIMEOUT,
+    database::{
+        profile::UserProfile,
+        security_event::{self, SecurityEventKind},
+        session::Session,
+        user::User,
+    },
+    environment::{LONG_SESSION, REGISTRATION_APPROVAL, SHORT_SESSION},
     errors::{Error, Result},
-    opaque::{begin_registration, finish_registration},
+    mail, metrics,
+    opaque::{begin_registration, current_suite, finish_registration, PasswordSuite},
+    registration,
+    store::{InviteStore, SettingsStore, UserStore},
+    templates::negotiate_locale,
+    username,
     utilities::{
         generate_codes, generate_continue_token_long, get_time_millis, get_time_secs,
-        send_in_use_email, send_verify_email, validate_captcha, EMAIL_RE, USERNAME_RE,
+        send_in_use_email, send_verify_email, EMAIL_RE,
     },
 };
 
@@ -29,7 +40,9 @@ pub enum Register {
     VerifyEmail {
         // stage 1: email & captcha
         email: String,
-        captcha_token: String,
+        captcha_token: Option<String>,
+        // req'd when registration is invite-only
+        invite_code: Option<String>,
     },
     #[serde(rename_all = "camelCase")]
     BeginRegistration {
@@ -51,6 +64,16 @@ pub enum Register {
     },
 }
 
+impl Register {
+    fn stage(&self) -> &'static str {
+        match self {
+            Register::VerifyEmail { .. } => "VERIFY_EMAIL",
+            Register::BeginRegistration { .. } => "BEGIN_REGISTRATION",
+            Register::Register { .. } => "REGISTER",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum RegisterResponse {
@@ -65,9 +88,13 @@ pub enum RegisterResponse {
         continue_token: String,
         message: String,
         // opaque data
+        suite: PasswordSuite,
     },
+    #[serde(rename_all = "camelCase")]
     Register {
-        token: String,
+        // not set while the account is waiting for approval
+        token: Option<String>,
+        pending_approval: bool,
         // opaque data 2
     },
 }
@@ -75,6 +102,11 @@ pub enum RegisterResponse {
 pub struct PendingRegister {
     pub time: u64,
     pub email: String,
+    // whether the email token was delivered by email rather than returned directly
+    pub verified: bool,
+    pub invite_code: Option<String>,
+    // chosen when the password registration begins
+    pub password_suite: Option<PasswordSuite>,
 }
 
 lazy_static! {
@@ -82,34 +114,43 @@ lazy_static! {
     pub static ref PENDING_REGISTERS2: DashMap<String, PendingRegister> = DashMap::new();
 }
 
-pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
+#[instrument(name = "register", skip_all, fields(stage = register.stage()))]
+pub async fn handle(
+    req: HttpRequest,
+    register: web::Json<Register>,
+    users: Data<dyn UserStore>,
+    settings: Data<dyn SettingsStore>,
+    invites: Data<dyn InviteStore>,
+) -> Result<impl Responder> {
     let register = register.into_inner();
     match register {
         Register::VerifyEmail {
             email,
             captcha_token,
+            invite_code,
         } => {
-            validate_captcha(captcha_token).await?;
+            captcha::validate(&req, captcha_token).await?;
             if !EMAIL_RE.is_match(email.trim()) {
                 return Err(Error::InvalidEmail);
             }
-            let collection = crate::database::user::get_collection();
-            let user = collection
-                .find_one(doc! {
-                    "email": email.clone()
-                })
-                .await?;
-            if *SMTP_ENABLED {
+            registration::check_email(&email)?;
+            registration::check_invite(invites.get_ref(), invite_code.as_deref()).await?;
+            let user = users.find_by_email(&email).await?;
+            if mail::is_enabled() {
+                let locale = negotiate_locale(&req, None);
                 if user.is_some() {
-                    task::spawn(send_in_use_email(email.clone()));
+                    send_in_use_email(&req, email.clone(), locale).await?;
                 } else {
                     let token = generate_codes().first().unwrap().to_string();
-                    task::spawn(send_verify_email(email.clone(), token.clone()));
+                    send_verify_email(&req, email.clone(), token.clone(), locale).await?;
                     PENDING_REGISTERS1.insert(
                         token,
                         PendingRegister {
                             time: get_time_secs(),
                             email,
+                            verified: true,
+                            invite_code,
+                            password_suite: None,
                         },
                     );
                 }
@@ -127,6 +168,9 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
                     PendingRegister {
                         time: get_time_secs(),
                         email,
+                        verified: false,
+                        invite_code,
+                        password_suite: None,
                     },
                 );
                 Ok(web::Json(RegisterResponse::VerifyEmail {
@@ -141,24 +185,39 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
         } => {
             if let Some(session) = PENDING_REGISTERS1.get(&token) {
                 let time = get_time_secs();
-                if time - session.time > 600 {
+                if time - session.time > VERIFY_TIMEOUT {
                     drop(session);
                     PENDING_REGISTERS1.remove(&token);
                     return Err(Error::SessionExpired);
                 }
                 let email = session.email.clone();
+                let verified = session.verified;
+                let invite_code = session.invite_code.clone();
+                drop(session);
+                let password_suite = current_suite(settings.get_ref()).await?;
                 let result = begin_registration(
+                    settings.get_ref(),
+                    &password_suite,
                     email.clone(),
-                    RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
+                    &BASE64.decode(message)?,
                 )
                 .await?;
-                drop(session);
                 PENDING_REGISTERS1.remove(&token);
                 let continue_token = generate_continue_token_long();
-                PENDING_REGISTERS2.insert(continue_token.clone(), PendingRegister { time, email });
+                PENDING_REGISTERS2.insert(
+                    continue_token.clone(),
+                    PendingRegister {
+                        time,
+                        email,
+                        verified,
+                        invite_code,
+                        password_suite: Some(password_suite.clone()),
+                    },
+                );
                 return Ok(web::Json(RegisterResponse::BeginRegistration {
                     continue_token,
                     message: BASE64.encode(result),
+                    suite: password_suite,
                 }));
             }
             Err(Error::SessionExpired)
@@ -169,83 +228,101 @@ pub async fn handle(register: web::Json<Register>) -> Result<impl Responder> {
             persist,
             display_name,
             message,
-            continue_token: token,
+            continue_token,
         } => {
-            if let Some(session) = PENDING_REGISTERS2.get(&token) {
-                if get_time_secs() - session.time > 600 {
-                    PENDING_REGISTERS2.remove(&token);
-                    return Err(Error::SessionExpired);
-                }
-                if display_name.trim().len() > 64 {
-                    return Err(Error::DisplayNameTooLong);
-                }
-                if !USERNAME_RE.is_match(username.trim()) {
-                    return Err(Error::InvalidUsername);
-                }
-                let collection = crate::database::user::get_collection();
-                let user = collection
-                    .find_one(doc! {
-                        "username": username.trim()
-                    })
-                    .await?;
-                if user.is_some() {
-                    return Err(Error::UsernameAlreadyTaken);
-                }
-                let password_data = finish_registration(RegistrationUpload::deserialize(
-                    &BASE64.decode(message)?,
-                )?)?;
-                let user_id = Ulid::new().to_string();
-                let user_document = User {
-                    id: user_id.clone(),
-                    mfa_enabled: false,
-                    mfa_secret: None,
-                    username: username.trim().to_string(),
-                    email: session.email.trim().to_string(),
-                    password_data,
-                    platform_administrator: false,
-                };
-                let profile_document = UserProfile {
-                    id: user_id.clone(),
-                    display_name: display_name.trim().to_string(),
-                    description: String::new(),
-                    website: String::new(),
-                    avatar: None,
-                };
-                let user_collection = crate::database::user::get_collection();
-                user_collection.insert_one(user_document).await?;
-                let profile_collection = crate::database::profile::get_collection();
-                profile_collection.insert_one(profile_document).await?;
+            let Some((time, email, verified, invite_code, Some(password_suite))) =
+                PENDING_REGISTERS2.get(&continue_token).map(|session| {
+                    (
+                        session.time,
+                        session.email.clone(),
+                        session.verified,
+                        session.invite_code.clone(),
+                        session.password_suite.clone(),
+                    )
+                })
+            else {
+                return Err(Error::SessionExpired);
+            };
+            if get_time_secs() - time > 600 {
+                PENDING_REGISTERS2.remove(&continue_token);
+                return Err(Error::SessionExpired);
+            }
+            if display_name.trim().len() > 64 {
+                return Err(Error::DisplayNameTooLong);
+            }
+            let (username, username_key) = username::validate(&username)?;
+            username::ensure_available(users.get_ref(), &username_key, None).await?;
+            let password_data = finish_registration(&password_suite, &BASE64.decode(message)?)
+                .inspect_err(|_| metrics::registration(false))?;
+            let invite = registration::invite_to_redeem(invite_code.as_deref())?;
+            let user_id = Ulid::new().to_string();
+            let user_document = User {
+                id: user_id.clone(),
+                mfa_enabled: false,
+                mfa_secret: None,
+                username,
+                username_key,
+                username_changed_at: None,
+                email: email.trim().to_string(),
+                password_data,
+                password_suite,
+                platform_administrator: false,
+                security_notifications: true,
+                locale: None,
+                email_verified: verified,
+                email_verified_at: verified.then(get_time_secs),
+                pending_approval: *REGISTRATION_APPROVAL,
+            };
+            let profile_document = UserProfile {
+                id: user_id.clone(),
+                display_name: display_name.trim().to_string(),
+                description: String::new(),
+                website: String::new(),
+                avatar: None,
+            };
+            // no session until an administrator approves the account
+            let session = if *REGISTRATION_APPROVAL {
+                None
+            } else {
                 let persist = persist.unwrap_or(false);
                 let millis = get_time_millis();
                 let expires_at = if persist {
-                    millis + LONG_SESSION
+                    millis + *LONG_SESSION
                 } else {
-                    millis + SHORT_SESSION
+                    millis + *SHORT_SESSION
                 };
                 let jwt_object = UserJwt {
                     id: user_id.clone(),
                     issued_at: millis,
                     expires_at,
                 };
-                let token = encode(
-                    &Header::default(),
-                    &jwt_object,
-                    &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-                )
-                .expect("Unexpected error: failed to encode token");
-                let sid = ulid::Ulid::new().to_string();
-                let session = Session {
-                    id: sid,
-                    token: token.clone(),
+                let token = sign_token(&jwt_object);
+                Some(Session {
+                    id: ulid::Ulid::new().to_string(),
+                    token,
                     friendly_name: friendly_name.unwrap_or("Unknown".to_owned()),
-                    user_id,
-                };
-                let sessions = crate::database::session::get_collection();
-                sessions.insert_one(session).await?;
-                PENDING_REGISTERS2.remove(&token);
-                return Ok(web::Json(RegisterResponse::Register { token }));
-            }
-            Err(Error::SessionExpired)
+                    user_id: user_id.clone(),
+                    auth_methods: vec!["pwd".to_string()],
+                })
+            };
+            let signed_in = session.as_ref().map(|s| (s.id.clone(), s.token.clone()));
+            users
+                .create(user_document, profile_document, invite, session)
+                .await
+                .inspect_err(|_| metrics::registration(false))?;
+            PENDING_REGISTERS2.remove(&continue_token);
+            metrics::registration(true);
+            let Some((sid, token)) = signed_in else {
+                return Ok(web::Json(RegisterResponse::Register {
+                    token: None,
+                    pending_approval: true,
+                }));
+            };
+            security_event::record(&req, SecurityEventKind::Login, &user_id, Some(&sid)).await;
+            Ok(web::Json(RegisterResponse::Register {
+                token: Some(token),
+                pending_approval: false,
+            }))
         }
     }
 }
@@ -1,12 +1,12 @@
 use actix_web::{
     web::{self, Data},
-    Responder,
+    HttpRequest, Responder,
 };
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
-use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 use ulid::Ulid;
 use webauthn_rs::{
     prelude::{CreationChallengeResponse, PasskeyRegistration, RegisterPublicKeyCredential},
@@ -16,10 +16,13 @@ use webauthn_rs::{
 use crate::{
     authenticate::Authenticate,
     database::{
-        passkey::{self, Passkey},
+        passkey::Passkey,
+        security_event::{self, SecurityEventKind},
         user::User,
     },
     errors::{Error, Result},
+    notifications::{notify, SecurityNotification},
+    store::{PasskeyStore, SessionStore, UserStore},
     utilities::{generate_continue_token_long, get_time_secs, validate_escalation},
 };
 
@@ -36,6 +39,15 @@ pub enum Register {
     },
 }
 
+impl Register {
+    fn stage(&self) -> &'static str {
+        match self {
+            Register::BeginRegister { .. } => "BEGIN_REGISTER",
+            Register::FinishRegister { .. } => "FINISH_REGISTER",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum RegisterResponse {
@@ -58,21 +70,23 @@ lazy_static! {
     pub static ref PENDING_REGISTERS: DashMap<String, PendingRegister> = DashMap::new();
 }
 
+#[instrument(name = "register_passkey", skip_all, fields(stage = register.stage()))]
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     register: web::Json<Register>,
     webauthn: Data<Webauthn>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+    passkeys: Data<dyn PasskeyStore>,
 ) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
     let register = register.into_inner();
     match register {
         Register::BeginRegister { escalation_token } => {
-            let user_id = validate_escalation(escalation_token, jwt.into_inner()?.jwt).await?;
-            let user = crate::database::user::get_collection()
-                .find_one(doc! {
-                    "id": user_id.clone()
-                })
-                .await?
-                .ok_or(Error::DatabaseError)?;
+            let user_id =
+                validate_escalation(sessions.get_ref(), escalation_token, jwt.jwt).await?;
+            let user = users.find(&user_id).await?.ok_or(Error::DatabaseError)?;
             let uuid = webauthn_rs::prelude::Uuid::from_bytes(
                 Ulid::from_string(&user.id).expect("S").to_bytes(),
             );
@@ -105,12 +119,16 @@ pub async fn handle(
                 PENDING_REGISTERS.remove(&continue_token);
                 return Err(Error::SessionExpired);
             }
+            if pending_register.user.id != jwt.jwt_content.id {
+                return Err(Error::UserMismatch);
+            }
             let auth_result =
                 webauthn.finish_passkey_registration(&message, &pending_register.data)?;
             let credential_id = auth_result.cred_id().as_ref().to_vec();
             let user = pending_register.user.clone();
-            passkey::get_collection()
-                .insert_one(Passkey {
+            drop(pending_register);
+            passkeys
+                .create(Passkey {
                     id: Ulid::new().to_string(),
                     credential: auth_result,
                     credential_id: BASE64.encode(credential_id),
@@ -118,7 +136,14 @@ pub async fn handle(
                     friendly_name: friendly_name.unwrap_or("Passkey".to_string()),
                 })
                 .await?;
-            drop(pending_register);
+            security_event::record(
+                &req,
+                SecurityEventKind::PasskeyAdded,
+                &user.id,
+                Some(&jwt.session_id),
+            )
+            .await;
+            notify(&req, &user.id, SecurityNotification::PasskeyAdded).await;
             PENDING_REGISTERS.remove(&continue_token);
             Ok(web::Json(RegisterResponse::FinishRegister {}))
         }
@@ -0,0 +1,74 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    database::security_event::{SecurityEvent, SecurityEventKind},
+    errors::Result,
+    store::SecurityEventStore,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityEventsQuery {
+    pub limit: Option<i64>,
+    // id of the last event on the previous page
+    pub before: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityEventEntry {
+    id: String,
+    kind: SecurityEventKind,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    session_id: Option<String>,
+    created_at: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityEventsResponse {
+    events: Vec<SecurityEventEntry>,
+    next: Option<String>,
+}
+
+pub async fn list_events(
+    events: &dyn SecurityEventStore,
+    user_id: &str,
+    query: SecurityEventsQuery,
+) -> Result<SecurityEventsResponse> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100) as usize;
+    let events = events.list(user_id, query.before.as_deref(), limit).await?;
+    let next = if events.len() == limit {
+        events.last().map(|e| e.id.clone())
+    } else {
+        None
+    };
+    let events = events
+        .into_iter()
+        .map(|e: SecurityEvent| SecurityEventEntry {
+            id: e.id,
+            kind: e.kind,
+            ip: e.ip,
+            user_agent: e.user_agent,
+            session_id: e.session_id,
+            created_at: e.created_at,
+        })
+        .collect();
+    Ok(SecurityEventsResponse { events, next })
+}
+
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    query: web::Query<SecurityEventsQuery>,
+    events: Data<dyn SecurityEventStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let response = list_events(events.get_ref(), &jwt.jwt_content.id, query.into_inner()).await?;
+    Ok(web::Json(response))
+}
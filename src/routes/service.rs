@@ -1,17 +1,29 @@
-use actix_web::{web, Responder};
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::constants::{SERVICE, VERSION};
+use crate::{
+    constants::{SERVICE, VERSION},
+    errors::Result,
+    opaque::{current_suite, PasswordSuite},
+    store::SettingsStore,
+};
 
 #[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ServiceResponse {
     pub service: &'static str,
     pub version: &'static str,
+    // the suite new passwords are registered with
+    pub password_suite: PasswordSuite,
 }
 
-pub async fn handle() -> impl Responder {
-    web::Json(ServiceResponse {
+pub async fn handle(settings: Data<dyn SettingsStore>) -> Result<impl Responder> {
+    Ok(web::Json(ServiceResponse {
         service: SERVICE,
         version: VERSION,
-    })
+        password_suite: current_suite(settings.get_ref()).await?,
+    }))
 }
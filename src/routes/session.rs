@@ -1,11 +1,12 @@
-use actix_web::{web, Responder};
-use futures_util::StreamExt;
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::authenticate::Authenticate;
-use crate::database::session::{self, Session};
 use crate::errors::Result;
+use crate::store::SessionStore;
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,18 +15,12 @@ pub struct ClientSession {
     friendly_name: String,
 }
 
-pub async fn handle(jwt: web::ReqData<Result<Authenticate>>) -> Result<impl Responder> {
+pub async fn handle(
+    jwt: web::ReqData<Result<Authenticate>>,
+    sessions: Data<dyn SessionStore>,
+) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
-    let sessions = session::get_collection();
-    let result = sessions
-        .find(doc! {
-            "user_id": &jwt.jwt_content.id
-        })
-        .await?
-        .collect::<Vec<std::result::Result<Session, _>>>()
-        .await
-        .into_iter()
-        .collect::<std::result::Result<Vec<Session>, _>>()?;
+    let result = sessions.list(&jwt.jwt_content.id).await?;
 
     let result = result
         .into_iter()
@@ -1,16 +1,22 @@
-use actix_web::{web, Responder};
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
-use mongodb::bson::{self, doc, Binary};
-use opaque_ke::{RegistrationRequest, RegistrationUpload};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     authenticate::Authenticate,
+    database::security_event::{self, SecurityEventKind},
     errors::{Error, Result},
-    opaque::{begin_registration, finish_registration},
-    utilities::{generate_continue_token_long, get_time_secs, validate_escalation},
+    notifications::{notify, SecurityNotification},
+    opaque::{begin_registration, current_suite, finish_registration, PasswordSuite},
+    store::{SessionStore, SettingsStore, UserStore},
+    utilities::{
+        clear_password_flows, generate_continue_token_long, get_time_secs, validate_escalation,
+    },
 };
 
 #[derive(Deserialize, Serialize)]
@@ -35,13 +41,16 @@ pub enum UpdatePasswordResponse {
     BeginUpdate {
         continue_token: String,
         message: String,
+        suite: PasswordSuite,
     },
     FinishUpdate {},
 }
 
 pub struct PendingUpdate {
     pub time: u64,
+    pub user_id: String,
     pub email: String,
+    pub password_suite: PasswordSuite,
 }
 
 lazy_static! {
@@ -49,8 +58,12 @@ lazy_static! {
 }
 
 pub async fn handle(
+    req: HttpRequest,
     jwt: web::ReqData<Result<Authenticate>>,
     register: web::Json<UpdatePassword>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+    settings: Data<dyn SettingsStore>,
 ) -> Result<impl Responder> {
     let jwt = jwt.into_inner()?;
     let register = register.into_inner();
@@ -59,17 +72,17 @@ pub async fn handle(
             escalation_token,
             message,
         } => {
-            validate_escalation(escalation_token, jwt.jwt).await?;
-            let user_collection = crate::database::user::get_collection();
-            let user = user_collection
-                .find_one(doc! {
-                    "id": jwt.jwt_content.id.clone()
-                })
+            validate_escalation(sessions.get_ref(), escalation_token, jwt.jwt).await?;
+            let user = users
+                .find(&jwt.jwt_content.id)
                 .await?
                 .ok_or(Error::DatabaseError)?;
+            let password_suite = current_suite(settings.get_ref()).await?;
             let result = begin_registration(
+                settings.get_ref(),
+                &password_suite,
                 user.email.clone(),
-                RegistrationRequest::deserialize(&BASE64.decode(message)?)?,
+                &BASE64.decode(message)?,
             )
             .await?;
             let continue_token = generate_continue_token_long();
@@ -77,12 +90,15 @@ pub async fn handle(
                 continue_token.clone(),
                 PendingUpdate {
                     time: get_time_secs(),
+                    user_id: user.id.clone(),
                     email: user.email.clone(),
+                    password_suite: password_suite.clone(),
                 },
             );
             Ok(web::Json(UpdatePasswordResponse::BeginUpdate {
                 continue_token,
                 message: BASE64.encode(result),
+                suite: password_suite,
             }))
         }
         UpdatePassword::FinishUpdate {
@@ -91,30 +107,29 @@ pub async fn handle(
         } => {
             if let Some(session) = PENDING_UPDATES.get(&continue_token) {
                 if get_time_secs() - session.time > 600 {
+                    drop(session);
                     PENDING_UPDATES.remove(&continue_token);
                     return Err(Error::SessionExpired);
                 }
-                let password_data = finish_registration(RegistrationUpload::deserialize(
-                    &BASE64.decode(message)?,
-                )?)?;
-                let binary = Binary {
-                    subtype: bson::spec::BinarySubtype::Generic,
-                    bytes: password_data,
-                };
-                let user_collection = crate::database::user::get_collection();
-                user_collection
-                    .update_one(
-                        doc! {
-                            "id": session.email.clone()
-                        },
-                        doc! {
-                            "$set": {
-                                "password_data": binary,
-                            }
-                        },
-                    )
+                if session.user_id != jwt.jwt_content.id {
+                    return Err(Error::UserMismatch);
+                }
+                let user_id = session.user_id.clone();
+                let password_suite = session.password_suite.clone();
+                drop(session);
+                let password_data = finish_registration(&password_suite, &BASE64.decode(message)?)?;
+                users
+                    .set_password(&user_id, password_data, password_suite, false)
                     .await?;
-                PENDING_UPDATES.remove(&continue_token);
+                security_event::record(
+                    &req,
+                    SecurityEventKind::PasswordChanged,
+                    &user_id,
+                    Some(&jwt.session_id),
+                )
+                .await;
+                notify(&req, &user_id, SecurityNotification::PasswordChanged).await;
+                clear_password_flows(&user_id);
                 return Ok(web::Json(UpdatePasswordResponse::FinishUpdate {}));
             }
             Err(Error::InvalidToken)
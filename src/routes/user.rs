@@ -1,12 +1,14 @@
-use actix_web::{web, Responder};
-use mongodb::bson::doc;
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    authenticate::Authenticate,
-    database::profile,
-    database::user,
+    authenticate::{require_user_or_scope, Authenticate, ServiceAuthenticate},
+    database::service_account::SCOPE_USERS_READ,
     errors::{Error, Result},
+    store::{ProfileStore, UserStore},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -20,35 +22,38 @@ pub struct UserResponse {
     avatar: Option<String>,
 }
 
-pub async fn handle(
-    user_id: web::Path<String>,
-    jwt: web::ReqData<Result<Authenticate>>,
-) -> Result<impl Responder> {
-    jwt.into_inner()?;
-    let collection = user::get_collection();
-    let profile_collection = profile::get_collection();
-    let result = collection
-        .find_one(doc! {
-            "id": user_id.clone()
-        })
-        .await?;
-    let profile_result = profile_collection
-        .find_one(doc! {
-            "id": user_id.clone()
-        })
-        .await?;
+pub async fn get_user(
+    users: &dyn UserStore,
+    profiles: &dyn ProfileStore,
+    user_id: &str,
+) -> Result<UserResponse> {
+    let result = users.find(user_id).await?;
+    let profile_result = profiles.find(user_id).await?;
     let Some(result) = result else {
         return Err(Error::UserNotFound);
     };
     let Some(profile_result) = profile_result else {
         return Err(Error::UserNotFound);
     };
-    Ok(web::Json(UserResponse {
+    Ok(UserResponse {
         avatar: profile_result.avatar,
         description: profile_result.description,
         display_name: profile_result.display_name,
         id: user_id.to_string(),
         username: result.username,
         website: profile_result.website,
-    }))
+    })
+}
+
+pub async fn handle(
+    user_id: web::Path<String>,
+    jwt: web::ReqData<Result<Authenticate>>,
+    service: web::ReqData<Result<ServiceAuthenticate>>,
+    users: Data<dyn UserStore>,
+    profiles: Data<dyn ProfileStore>,
+) -> Result<impl Responder> {
+    require_user_or_scope(jwt.into_inner(), service.into_inner(), SCOPE_USERS_READ)?;
+    Ok(web::Json(
+        get_user(users.get_ref(), profiles.get_ref(), &user_id).await?,
+    ))
 }
@@ -0,0 +1,50 @@
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::{require_user_or_scope, Authenticate, ServiceAuthenticate},
+    database::service_account::SCOPE_USERS_READ,
+    errors::{Error, Result},
+    routes::user::{get_user, UserResponse},
+    store::{ProfileStore, UserStore},
+    username::normalize_key,
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsernameLookupResponse {
+    #[serde(flatten)]
+    user: UserResponse,
+    // set when the user was found by a previous username
+    redirected_from: Option<String>,
+}
+
+pub async fn handle(
+    username: web::Path<String>,
+    jwt: web::ReqData<Result<Authenticate>>,
+    service: web::ReqData<Result<ServiceAuthenticate>>,
+    users: Data<dyn UserStore>,
+    profiles: Data<dyn ProfileStore>,
+) -> Result<impl Responder> {
+    require_user_or_scope(jwt.into_inner(), service.into_inner(), SCOPE_USERS_READ)?;
+    let username = username.into_inner();
+    let key = normalize_key(&username);
+    let current = users.find_by_username(&username, &key).await?;
+    if let Some(current) = current {
+        return Ok(web::Json(UsernameLookupResponse {
+            user: get_user(users.get_ref(), profiles.get_ref(), &current.id).await?,
+            redirected_from: None,
+        }));
+    }
+    let previous = users
+        .find_username_history(&key, None, None)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    Ok(web::Json(UsernameLookupResponse {
+        user: get_user(users.get_ref(), profiles.get_ref(), &previous.user_id).await?,
+        redirected_from: Some(previous.username),
+    }))
+}
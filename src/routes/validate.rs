@@ -1,34 +1,60 @@
-use actix_web::{web, Responder};
+use actix_web::{
+    web::{self, Data},
+    Responder,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{authenticate::validate_token, errors::Result, utilities::validate_escalation};
+use crate::{
+    authenticate::validate_token,
+    environment::UNVERIFIED_BLOCKED_SERVICES,
+    errors::{Error, Result},
+    store::{SessionStore, UserStore},
+    utilities::validate_escalation,
+};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Validate {
     token: String,
     escalation_token: Option<String>,
+    // the calling service, checked against UNVERIFIED_BLOCKED_SERVICES
+    service: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ValidateResponse {
     escalated: bool,
+    email_verified: bool,
 }
 
-pub async fn handle(validate: web::Json<Validate>) -> Result<impl Responder> {
-    let token = validate_token(&validate.token).await;
-    if let Err(token) = token {
-        Err(token)
-    } else {
-        let Some(escalation) = &validate.escalation_token else {
-            return Ok(web::Json(ValidateResponse { escalated: false }));
-        };
-        let escalation = validate_escalation(escalation.to_string(), validate.token.clone()).await;
-        if escalation.is_err() {
-            Ok(web::Json(ValidateResponse { escalated: false }))
-        } else {
-            Ok(web::Json(ValidateResponse { escalated: true }))
+pub async fn handle(
+    validate: web::Json<Validate>,
+    users: Data<dyn UserStore>,
+    sessions: Data<dyn SessionStore>,
+) -> Result<impl Responder> {
+    let token = validate_token(sessions.get_ref(), &validate.token).await?;
+    let user = users
+        .find(&token.jwt_content.id)
+        .await?
+        .ok_or(Error::InvalidToken)?;
+    if let Some(service) = &validate.service {
+        if !user.email_verified && UNVERIFIED_BLOCKED_SERVICES.contains(service) {
+            return Err(Error::EmailNotVerified);
         }
     }
+    let escalated = match &validate.escalation_token {
+        Some(escalation) => validate_escalation(
+            sessions.get_ref(),
+            escalation.to_string(),
+            validate.token.clone(),
+        )
+        .await
+        .is_ok(),
+        None => false,
+    };
+    Ok(web::Json(ValidateResponse {
+        escalated,
+        email_verified: user.email_verified,
+    }))
 }
@@ -0,0 +1,113 @@
+use actix_web::{
+    web::{self, Data},
+    HttpRequest, Responder,
+};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    authenticate::Authenticate,
+    constants::VERIFY_TIMEOUT,
+    errors::{Error, Result},
+    mail,
+    store::UserStore,
+    templates::negotiate_locale,
+    utilities::{generate_codes, get_time_secs, send_verify_email},
+};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", tag = "stage")]
+pub enum VerifyEmail {
+    SendCode,
+    VerifyCode { code: String },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", untagged)]
+pub enum VerifyEmailResponse {
+    #[serde(rename_all = "camelCase")]
+    SendCode { expires_at: u64 },
+    #[serde(rename_all = "camelCase")]
+    VerifyCode { email_verified_at: u64 },
+}
+
+pub struct PendingVerification {
+    pub time: u64,
+    pub code: String,
+    pub email: String,
+    pub attempts: u32,
+}
+
+lazy_static! {
+    // keyed by user id, so sending a new code replaces the previous one
+    pub static ref PENDING_VERIFICATIONS: DashMap<String, PendingVerification> = DashMap::new();
+}
+
+const MAX_VERIFICATION_ATTEMPTS: u32 = 5;
+
+pub async fn handle(
+    req: HttpRequest,
+    jwt: web::ReqData<Result<Authenticate>>,
+    verify: web::Json<VerifyEmail>,
+    users: Data<dyn UserStore>,
+) -> Result<impl Responder> {
+    let jwt = jwt.into_inner()?;
+    let user = users
+        .find(&jwt.jwt_content.id)
+        .await?
+        .ok_or(Error::UserNotFound)?;
+    if user.email_verified {
+        return Err(Error::EmailAlreadyVerified);
+    }
+    match verify.into_inner() {
+        VerifyEmail::SendCode => {
+            if !mail::is_enabled() {
+                return Err(Error::EmailMisconfigured);
+            }
+            let code = generate_codes().first().unwrap().to_string();
+            let locale = negotiate_locale(&req, user.locale.as_deref());
+            send_verify_email(&req, user.email.clone(), code.clone(), locale).await?;
+            let time = get_time_secs();
+            PENDING_VERIFICATIONS.insert(
+                user.id,
+                PendingVerification {
+                    time,
+                    code,
+                    email: user.email,
+                    attempts: 0,
+                },
+            );
+            Ok(web::Json(VerifyEmailResponse::SendCode {
+                expires_at: time + VERIFY_TIMEOUT,
+            }))
+        }
+        VerifyEmail::VerifyCode { code } => {
+            let Some(mut pending) = PENDING_VERIFICATIONS.get_mut(&user.id) else {
+                return Err(Error::SessionExpired);
+            };
+            // the code only proves ownership of the address it was sent to
+            if get_time_secs() - pending.time > VERIFY_TIMEOUT || pending.email != user.email {
+                drop(pending);
+                PENDING_VERIFICATIONS.remove(&user.id);
+                return Err(Error::SessionExpired);
+            }
+            if pending.code != code {
+                pending.attempts += 1;
+                let attempts = pending.attempts;
+                drop(pending);
+                if attempts >= MAX_VERIFICATION_ATTEMPTS {
+                    PENDING_VERIFICATIONS.remove(&user.id);
+                }
+                return Err(Error::IncorrectCode);
+            }
+            drop(pending);
+            PENDING_VERIFICATIONS.remove(&user.id);
+            let now = get_time_secs();
+            users.set_email_verified(&user.id, now).await?;
+            Ok(web::Json(VerifyEmailResponse::VerifyCode {
+                email_verified_at: now,
+            }))
+        }
+    }
+}
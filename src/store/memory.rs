@@ -0,0 +1,871 @@
+use std::{
+    collections::HashMap,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use async_trait::async_trait;
+
+use super::{
+    ChallengeStore, CodeStore, Delivery, FileStore, InviteStore, OutboxStore, PasskeyStore,
+    PersonalTokenStore, ProfileStore, ProfileUpdate, RecoveryStore, ResetRequestStore,
+    SecurityEventStore, ServiceAccountStore, SessionStore, SettingsStore, SettingsUpdate,
+    UserStore,
+};
+use crate::{
+    database::{
+        code::Code,
+        files::File,
+        invite::Invite,
+        outbox::{OutboxEmail, OutboxStatus},
+        passkey::Passkey,
+        personal_token::PersonalToken,
+        profile::UserProfile,
+        recovery::RecoveryToken,
+        reset_request::ResetRequest,
+        security_event::{SecurityEvent, SecurityEventKind},
+        service_account::ServiceAccount,
+        session::Session,
+        settings::Settings,
+        user::User,
+        username_history::UsernameHistory,
+    },
+    errors::{Error, Result},
+    opaque::{create_settings, PasswordSuite},
+    utilities::get_time_secs,
+};
+
+#[derive(Default)]
+struct Data {
+    users: HashMap<String, User>,
+    profiles: HashMap<String, UserProfile>,
+    sessions: HashMap<String, Session>,
+    passkeys: HashMap<String, Passkey>,
+    codes: Vec<Code>,
+    username_history: Vec<UsernameHistory>,
+    settings: Option<Settings>,
+    service_accounts: HashMap<String, ServiceAccount>,
+    personal_tokens: HashMap<String, PersonalToken>,
+    security_events: Vec<SecurityEvent>,
+    recovery_tokens: HashMap<String, RecoveryToken>,
+    reset_requests: HashMap<String, ResetRequest>,
+    invites: HashMap<String, Invite>,
+    outbox: HashMap<String, OutboxEmail>,
+    // always empty, there is no CDN to upload to
+    files: HashMap<String, File>,
+    // challenge id -> expiry
+    used_challenges: HashMap<String, u64>,
+}
+
+// Everything sits behind one lock, which makes multi-document writes atomic.
+// Uniqueness is checked the same way the MongoDB indexes enforce it.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: RwLock<Data>,
+}
+
+impl MemoryStore {
+    fn read(&self) -> RwLockReadGuard<'_, Data> {
+        self.data
+            .read()
+            .expect("Unexpected error: store lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Data> {
+        self.data
+            .write()
+            .expect("Unexpected error: store lock poisoned")
+    }
+}
+
+fn is_usable(invite: &Invite, now: u64) -> bool {
+    invite.max_uses.is_none_or(|max| invite.uses < max)
+        && invite.expires_at.is_none_or(|at| at > now)
+}
+
+impl Data {
+    // returns how many personal access tokens were revoked
+    fn revoke_sessions(&mut self, user_id: &str, except_token: Option<&str>) -> u64 {
+        self.sessions
+            .retain(|_, s| s.user_id != user_id || Some(s.token.as_str()) == except_token);
+        let before = self.personal_tokens.len();
+        self.personal_tokens.retain(|_, t| t.user_id != user_id);
+        (before - self.personal_tokens.len()) as u64
+    }
+
+    fn username_taken(&self, key: &str, except: Option<&str>) -> bool {
+        self.users
+            .values()
+            .any(|u| Some(u.id.as_str()) != except && u.username_key == key)
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn find(&self, id: &str) -> Result<Option<User>> {
+        Ok(self.read().users.get(id).cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let email = email.to_lowercase();
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|u| u.email.to_lowercase() == email)
+            .cloned())
+    }
+
+    async fn find_by_username(&self, username: &str, key: &str) -> Result<Option<User>> {
+        Ok(self
+            .read()
+            .users
+            .values()
+            .find(|u| u.username_key == key || u.username == username)
+            .cloned())
+    }
+
+    async fn is_username_taken(&self, key: &str, except: Option<&str>) -> Result<bool> {
+        Ok(self.read().username_taken(key, except))
+    }
+
+    async fn list_pending_approval(&self) -> Result<Vec<User>> {
+        let mut users = self
+            .read()
+            .users
+            .values()
+            .filter(|u| u.pending_approval)
+            .cloned()
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(users)
+    }
+
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>> {
+        let mut users = self
+            .read()
+            .users
+            .values()
+            .filter(|u| after.is_none_or(|after| u.id.as_str() > after))
+            .cloned()
+            .collect::<Vec<_>>();
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        users.truncate(limit);
+        Ok(users)
+    }
+
+    async fn create(
+        &self,
+        user: User,
+        profile: UserProfile,
+        invite: Option<&str>,
+        session: Option<Session>,
+    ) -> Result<()> {
+        let mut data = self.write();
+        let email = user.email.to_lowercase();
+        if data
+            .users
+            .values()
+            .any(|u| u.id == user.id || u.email.to_lowercase() == email)
+        {
+            return Err(Error::UserExists);
+        }
+        if data.username_taken(&user.username_key, None) {
+            return Err(Error::UsernameAlreadyTaken);
+        }
+        if let Some(code) = invite {
+            let now = get_time_secs();
+            match data.invites.get_mut(code) {
+                Some(invite) if is_usable(invite, now) => invite.uses += 1,
+                _ => return Err(Error::InvalidInvite),
+            }
+        }
+        if let Some(session) = session {
+            data.sessions.insert(session.id.clone(), session);
+        }
+        data.profiles.insert(profile.id.clone(), profile);
+        data.users.insert(user.id.clone(), user);
+        Ok(())
+    }
+
+    async fn update_settings(&self, id: &str, update: SettingsUpdate) -> Result<()> {
+        let mut data = self.write();
+        let Some(user) = data.users.get_mut(id) else {
+            return Ok(());
+        };
+        if let Some(security_notifications) = update.security_notifications {
+            user.security_notifications = security_notifications;
+        }
+        if let Some(locale) = update.locale {
+            user.locale = Some(locale);
+        }
+        Ok(())
+    }
+
+    async fn change_username(
+        &self,
+        id: &str,
+        username: &str,
+        key: &str,
+        history: UsernameHistory,
+    ) -> Result<()> {
+        let mut data = self.write();
+        if data.username_taken(key, Some(id)) {
+            return Err(Error::UsernameAlreadyTaken);
+        }
+        let Some(user) = data.users.get_mut(id) else {
+            return Err(Error::UserNotFound);
+        };
+        user.username = username.to_string();
+        user.username_key = key.to_string();
+        user.username_changed_at = Some(history.changed_at);
+        data.username_history.push(history);
+        Ok(())
+    }
+
+    async fn find_username_history(
+        &self,
+        key: &str,
+        held_after: Option<u64>,
+        except: Option<&str>,
+    ) -> Result<Option<UsernameHistory>> {
+        Ok(self
+            .read()
+            .username_history
+            .iter()
+            .filter(|h| h.username_key == key)
+            .filter(|h| held_after.map(|t| h.released_at > t).unwrap_or(true))
+            .filter(|h| Some(h.user_id.as_str()) != except)
+            .max_by_key(|h| h.changed_at)
+            .cloned())
+    }
+
+    async fn set_email_verified(&self, id: &str, verified_at: u64) -> Result<()> {
+        if let Some(user) = self.write().users.get_mut(id) {
+            user.email_verified = true;
+            user.email_verified_at = Some(verified_at);
+        }
+        Ok(())
+    }
+
+    async fn approve(&self, id: &str) -> Result<bool> {
+        match self.write().users.get_mut(id) {
+            Some(user) if user.pending_approval => {
+                user.pending_approval = false;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn set_administrator(&self, id: &str, administrator: bool) -> Result<bool> {
+        match self.write().users.get_mut(id) {
+            Some(user) => {
+                user.platform_administrator = administrator;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_password(
+        &self,
+        id: &str,
+        password_data: Vec<u8>,
+        suite: PasswordSuite,
+        revoke_sessions: bool,
+    ) -> Result<u64> {
+        let mut data = self.write();
+        if let Some(user) = data.users.get_mut(id) {
+            user.password_data = password_data;
+            user.password_suite = suite;
+        }
+        if !revoke_sessions {
+            return Ok(0);
+        }
+        Ok(data.revoke_sessions(id, None))
+    }
+
+    async fn revoke_sessions(&self, id: &str, except_token: Option<&str>) -> Result<u64> {
+        Ok(self.write().revoke_sessions(id, except_token))
+    }
+
+    async fn enable_mfa(&self, id: &str, secret: &str, codes: Vec<String>) -> Result<()> {
+        let mut data = self.write();
+        if let Some(user) = data.users.get_mut(id) {
+            user.mfa_enabled = true;
+            user.mfa_secret = Some(secret.to_string());
+        }
+        data.codes.retain(|c| c.user_id != id);
+        data.codes.extend(codes.into_iter().map(|code| Code {
+            code,
+            user_id: id.to_string(),
+        }));
+        Ok(())
+    }
+
+    async fn disable_mfa(&self, id: &str) -> Result<()> {
+        let mut data = self.write();
+        if let Some(user) = data.users.get_mut(id) {
+            user.mfa_enabled = false;
+            user.mfa_secret = None;
+        }
+        data.codes.retain(|c| c.user_id != id);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<Option<UserProfile>> {
+        let mut data = self.write();
+        data.users.remove(id);
+        data.sessions.retain(|_, s| s.user_id != id);
+        data.personal_tokens.retain(|_, t| t.user_id != id);
+        data.passkeys.retain(|_, p| p.user_id != id);
+        data.codes.retain(|c| c.user_id != id);
+        Ok(data.profiles.remove(id))
+    }
+
+    async fn delete_pending(&self, id: &str) -> Result<bool> {
+        let mut data = self.write();
+        if !data.users.get(id).is_some_and(|u| u.pending_approval) {
+            return Ok(false);
+        }
+        data.users.remove(id);
+        data.profiles.remove(id);
+        Ok(true)
+    }
+
+    async fn recover(&self, id: &str) -> Result<u64> {
+        let mut data = self.write();
+        data.reset_requests.retain(|_, r| r.user_id != id);
+        Ok(data.revoke_sessions(id, None))
+    }
+}
+
+#[async_trait]
+impl ProfileStore for MemoryStore {
+    async fn find(&self, id: &str) -> Result<Option<UserProfile>> {
+        Ok(self.read().profiles.get(id).cloned())
+    }
+
+    async fn update(&self, id: &str, update: ProfileUpdate) -> Result<()> {
+        let mut data = self.write();
+        let Some(profile) = data.profiles.get_mut(id) else {
+            return Ok(());
+        };
+        if let Some(display_name) = update.display_name {
+            profile.display_name = display_name;
+        }
+        if let Some(description) = update.description {
+            profile.description = description;
+        }
+        if let Some(website) = update.website {
+            profile.website = website;
+        }
+        if let Some(avatar) = update.avatar {
+            profile.avatar = Some(avatar);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn find(&self, id: &str) -> Result<Option<Session>> {
+        Ok(self.read().sessions.get(id).cloned())
+    }
+
+    async fn find_by_token(&self, token: &str) -> Result<Option<Session>> {
+        Ok(self
+            .read()
+            .sessions
+            .values()
+            .find(|s| s.token == token)
+            .cloned())
+    }
+
+    async fn list(&self, user_id: &str) -> Result<Vec<Session>> {
+        Ok(self
+            .read()
+            .sessions
+            .values()
+            .filter(|s| s.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, session: Session) -> Result<()> {
+        self.write().sessions.insert(session.id.clone(), session);
+        Ok(())
+    }
+
+    async fn delete_by_token(&self, token: &str) -> Result<()> {
+        self.write().sessions.retain(|_, s| s.token != token);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let mut data = self.write();
+        if data.sessions.get(id).is_none_or(|s| s.user_id != user_id) {
+            return Ok(false);
+        }
+        data.sessions.remove(id);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl PasskeyStore for MemoryStore {
+    async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        Ok(self
+            .read()
+            .passkeys
+            .values()
+            .find(|p| p.credential_id == credential_id)
+            .cloned())
+    }
+
+    async fn list(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        Ok(self
+            .read()
+            .passkeys
+            .values()
+            .filter(|p| p.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, passkey: Passkey) -> Result<()> {
+        self.write().passkeys.insert(passkey.id.clone(), passkey);
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let mut data = self.write();
+        if data.passkeys.get(id).is_none_or(|p| p.user_id != user_id) {
+            return Ok(false);
+        }
+        data.passkeys.remove(id);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl CodeStore for MemoryStore {
+    async fn consume(&self, user_id: &str, code: &str) -> Result<bool> {
+        let mut data = self.write();
+        let Some(index) = data
+            .codes
+            .iter()
+            .position(|c| c.user_id == user_id && c.code == code)
+        else {
+            return Ok(false);
+        };
+        data.codes.remove(index);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl SettingsStore for MemoryStore {
+    async fn get(&self) -> Result<Settings> {
+        let mut data = self.write();
+        if data.settings.is_none() && !data.users.is_empty() {
+            return Err(Error::ServerSetupUnavailable);
+        }
+        let settings = data.settings.get_or_insert_with(create_settings);
+        Ok(settings.clone())
+    }
+
+    async fn find(&self) -> Result<Option<Settings>> {
+        Ok(self.read().settings.clone())
+    }
+
+    async fn set(&self, settings: Settings) -> Result<()> {
+        self.write().settings = Some(settings);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ServiceAccountStore for MemoryStore {
+    async fn find_by_key_hash(&self, key_hash: &str) -> Result<Option<ServiceAccount>> {
+        Ok(self
+            .read()
+            .service_accounts
+            .values()
+            .find(|a| a.key_hash == key_hash)
+            .cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<ServiceAccount>> {
+        let mut accounts = self
+            .read()
+            .service_accounts
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        accounts.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(accounts)
+    }
+
+    async fn create(&self, account: ServiceAccount) -> Result<()> {
+        let mut data = self.write();
+        if data
+            .service_accounts
+            .values()
+            .any(|a| a.name == account.name)
+        {
+            return Err(Error::ServiceAccountExists);
+        }
+        data.service_accounts.insert(account.id.clone(), account);
+        Ok(())
+    }
+
+    async fn set_scopes(&self, id: &str, scopes: &[String]) -> Result<Option<ServiceAccount>> {
+        let mut data = self.write();
+        let Some(account) = data.service_accounts.get_mut(id) else {
+            return Ok(None);
+        };
+        account.scopes = scopes.to_vec();
+        Ok(Some(account.clone()))
+    }
+
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()> {
+        if let Some(account) = self.write().service_accounts.get_mut(id) {
+            account.last_used_at = Some(used_at);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool> {
+        Ok(self.write().service_accounts.remove(id).is_some())
+    }
+}
+
+#[async_trait]
+impl PersonalTokenStore for MemoryStore {
+    async fn find_by_token_hash(
+        &self,
+        token_hash: &str,
+        now: u64,
+    ) -> Result<Option<PersonalToken>> {
+        Ok(self
+            .read()
+            .personal_tokens
+            .values()
+            .find(|t| t.token_hash == token_hash && t.expires_at > now)
+            .cloned())
+    }
+
+    async fn list(&self, user_id: &str) -> Result<Vec<PersonalToken>> {
+        let mut tokens = self
+            .read()
+            .personal_tokens
+            .values()
+            .filter(|t| t.user_id == user_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        tokens.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(tokens)
+    }
+
+    async fn create(&self, token: PersonalToken) -> Result<()> {
+        self.write().personal_tokens.insert(token.id.clone(), token);
+        Ok(())
+    }
+
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()> {
+        if let Some(token) = self.write().personal_tokens.get_mut(id) {
+            token.last_used_at = Some(used_at);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let mut data = self.write();
+        if data
+            .personal_tokens
+            .get(id)
+            .is_none_or(|t| t.user_id != user_id)
+        {
+            return Ok(false);
+        }
+        data.personal_tokens.remove(id);
+        Ok(true)
+    }
+
+    async fn purge_expired(&self, now: u64) -> Result<u64> {
+        let mut data = self.write();
+        let before = data.personal_tokens.len();
+        data.personal_tokens.retain(|_, t| t.expires_at >= now);
+        Ok((before - data.personal_tokens.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl SecurityEventStore for MemoryStore {
+    async fn record(&self, event: SecurityEvent) -> Result<()> {
+        self.write().security_events.push(event);
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        user_id: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SecurityEvent>> {
+        // ids are ULIDs and events are appended, so newest first is the reverse order
+        Ok(self
+            .read()
+            .security_events
+            .iter()
+            .rev()
+            .filter(|e| e.user_id == user_id)
+            .filter(|e| before.is_none_or(|before| e.id.as_str() < before))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn is_new_device(&self, user_id: &str, user_agent: Option<&str>) -> Result<bool> {
+        let data = self.read();
+        let mut logins = data
+            .security_events
+            .iter()
+            .filter(|e| e.user_id == user_id && e.kind == SecurityEventKind::Login)
+            .peekable();
+        if logins.peek().is_none() {
+            return Ok(false);
+        }
+        Ok(!logins.any(|e| e.user_agent.as_deref() == user_agent))
+    }
+
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let mut data = self.write();
+        let before = data.security_events.len();
+        data.security_events.retain(|e| e.created_at >= cutoff);
+        Ok((before - data.security_events.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl RecoveryStore for MemoryStore {
+    async fn create(&self, token: RecoveryToken) -> Result<()> {
+        self.write()
+            .recovery_tokens
+            .insert(token.token.clone(), token);
+        Ok(())
+    }
+
+    async fn take(&self, token: &str) -> Result<Option<RecoveryToken>> {
+        Ok(self.write().recovery_tokens.remove(token))
+    }
+
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let mut data = self.write();
+        let before = data.recovery_tokens.len();
+        data.recovery_tokens.retain(|_, t| t.created_at >= cutoff);
+        Ok((before - data.recovery_tokens.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl ResetRequestStore for MemoryStore {
+    async fn create(&self, request: ResetRequest) -> Result<()> {
+        self.write()
+            .reset_requests
+            .insert(request.token.clone(), request);
+        Ok(())
+    }
+
+    async fn find(&self, token: &str) -> Result<Option<ResetRequest>> {
+        Ok(self.read().reset_requests.get(token).cloned())
+    }
+
+    async fn delete(&self, token: &str) -> Result<()> {
+        self.write().reset_requests.remove(token);
+        Ok(())
+    }
+
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let mut data = self.write();
+        let before = data.reset_requests.len();
+        data.reset_requests.retain(|_, r| r.available_at >= cutoff);
+        Ok((before - data.reset_requests.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl InviteStore for MemoryStore {
+    async fn create(&self, invite: Invite) -> Result<()> {
+        self.write().invites.insert(invite.code.clone(), invite);
+        Ok(())
+    }
+
+    async fn is_usable(&self, code: &str, now: u64) -> Result<bool> {
+        Ok(self
+            .read()
+            .invites
+            .get(code)
+            .is_some_and(|i| is_usable(i, now)))
+    }
+
+    async fn list(&self, created_by: &str) -> Result<Vec<Invite>> {
+        let mut invites = self
+            .read()
+            .invites
+            .values()
+            .filter(|i| i.created_by == created_by)
+            .cloned()
+            .collect::<Vec<_>>();
+        invites.sort_by_key(|i| std::cmp::Reverse(i.created_at));
+        Ok(invites)
+    }
+
+    async fn delete(&self, code: &str, created_by: &str) -> Result<()> {
+        self.write()
+            .invites
+            .retain(|_, i| i.code != code || i.created_by != created_by);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutboxStore for MemoryStore {
+    async fn enqueue(&self, email: OutboxEmail) -> Result<()> {
+        self.write().outbox.insert(email.id.clone(), email);
+        Ok(())
+    }
+
+    async fn claim(
+        &self,
+        now: u64,
+        stale_before: u64,
+        ignore_backoff: bool,
+    ) -> Result<Option<OutboxEmail>> {
+        let mut data = self.write();
+        let Some(email) = data
+            .outbox
+            .values_mut()
+            .filter(|e| match e.status {
+                OutboxStatus::Pending => ignore_backoff || e.next_attempt_at <= now,
+                OutboxStatus::Sending => e.claimed_at.is_some_and(|at| at < stale_before),
+                _ => false,
+            })
+            .min_by_key(|e| e.next_attempt_at)
+        else {
+            return Ok(None);
+        };
+        email.status = OutboxStatus::Sending;
+        email.claimed_at = Some(now);
+        Ok(Some(email.clone()))
+    }
+
+    async fn finish(&self, id: &str, delivery: Delivery, now: u64) -> Result<()> {
+        let mut data = self.write();
+        let Some(email) = data.outbox.get_mut(id) else {
+            return Ok(());
+        };
+        email.attempts += 1;
+        email.claimed_at = None;
+        match delivery {
+            Delivery::Sent => {
+                email.status = OutboxStatus::Sent;
+                email.sent_at = Some(now);
+            }
+            Delivery::Failed { error } => {
+                email.status = OutboxStatus::Failed;
+                email.last_error = Some(error);
+            }
+            Delivery::Retry {
+                error,
+                next_attempt_at,
+            } => {
+                email.status = OutboxStatus::Pending;
+                email.last_error = Some(error);
+                email.next_attempt_at = next_attempt_at;
+                return Ok(());
+            }
+        }
+        email.text.clear();
+        email.html.clear();
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        status: Option<OutboxStatus>,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>> {
+        let mut emails = self
+            .read()
+            .outbox
+            .values()
+            .filter(|e| status.is_none_or(|status| e.status == status))
+            .filter(|e| before.is_none_or(|before| e.id.as_str() < before))
+            .cloned()
+            .collect::<Vec<_>>();
+        emails.sort_by(|a, b| b.id.cmp(&a.id));
+        emails.truncate(limit);
+        Ok(emails)
+    }
+
+    async fn count(&self, status: OutboxStatus) -> Result<u64> {
+        Ok(self
+            .read()
+            .outbox
+            .values()
+            .filter(|e| e.status == status)
+            .count() as u64)
+    }
+
+    async fn purge_finished(&self, cutoff: u64) -> Result<u64> {
+        let mut data = self.write();
+        let before = data.outbox.len();
+        data.outbox.retain(|_, e| match e.status {
+            OutboxStatus::Sent => e.sent_at.is_none_or(|at| at >= cutoff),
+            OutboxStatus::Failed => e.created_at >= cutoff,
+            _ => true,
+        });
+        Ok((before - data.outbox.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl FileStore for MemoryStore {
+    async fn find(&self, id: &str) -> Result<Option<File>> {
+        Ok(self
+            .read()
+            .files
+            .get(id)
+            .filter(|f| !f.deleted && !f.flagged)
+            .cloned())
+    }
+
+    async fn set_attached(&self, id: &str, attached: bool) -> Result<()> {
+        if let Some(file) = self
+            .write()
+            .files
+            .get_mut(id)
+            .filter(|f| !f.deleted && !f.flagged)
+        {
+            file.attached = attached;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for MemoryStore {
+    async fn use_once(&self, id: &str, expires_at: u64) -> Result<bool> {
+        let now = get_time_secs();
+        let mut data = self.write();
+        // there is no TTL index, expired challenges are dropped whenever one is used
+        data.used_challenges.retain(|_, expiry| *expiry >= now);
+        Ok(data
+            .used_challenges
+            .insert(id.to_string(), expires_at)
+            .is_none())
+    }
+}
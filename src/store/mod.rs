@@ -0,0 +1,428 @@
+// Storage behind the handlers. Routes take the stores they need as `Data<dyn ...Store>`,
+// so the service can run on MongoDB or entirely in memory.
+
+pub mod memory;
+pub mod mongo;
+
+use std::sync::Arc;
+
+use actix_web::web::{Data, ServiceConfig};
+use async_trait::async_trait;
+use log::info;
+
+use crate::{
+    database::{
+        files::File,
+        invite::Invite,
+        outbox::{OutboxEmail, OutboxStatus},
+        passkey::Passkey,
+        personal_token::PersonalToken,
+        profile::UserProfile,
+        recovery::RecoveryToken,
+        reset_request::ResetRequest,
+        security_event::SecurityEvent,
+        service_account::ServiceAccount,
+        session::Session,
+        settings::Settings,
+        user::User,
+        username_history::UsernameHistory,
+    },
+    environment::STORAGE_BACKEND,
+    errors::Result,
+    opaque::PasswordSuite,
+};
+
+// Writes that touch several documents are single methods, so implementations can make them atomic
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn find(&self, id: &str) -> Result<Option<User>>;
+    // case-insensitive
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>>;
+    // by exact username or `username::normalize_key`
+    async fn find_by_username(&self, username: &str, key: &str) -> Result<Option<User>>;
+    // whether a user other than `except` has the username or a confusable one
+    async fn is_username_taken(&self, key: &str, except: Option<&str>) -> Result<bool>;
+    async fn list_pending_approval(&self) -> Result<Vec<User>>;
+    // ordered by id, starting after the user `after`
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>>;
+    // also uses up one redemption of `invite`, failing with `InvalidInvite` if it's no longer
+    // usable, and signs the user in with `session`
+    async fn create(
+        &self,
+        user: User,
+        profile: UserProfile,
+        invite: Option<&str>,
+        session: Option<Session>,
+    ) -> Result<()>;
+    async fn update_settings(&self, id: &str, update: SettingsUpdate) -> Result<()>;
+    // also records the old username in the history
+    async fn change_username(
+        &self,
+        id: &str,
+        username: &str,
+        key: &str,
+        history: UsernameHistory,
+    ) -> Result<()>;
+    // latest entry for the key; with `held_after`, only names still held at that time
+    async fn find_username_history(
+        &self,
+        key: &str,
+        held_after: Option<u64>,
+        except: Option<&str>,
+    ) -> Result<Option<UsernameHistory>>;
+    async fn set_email_verified(&self, id: &str, verified_at: u64) -> Result<()>;
+    // false if there was no pending account to approve
+    async fn approve(&self, id: &str) -> Result<bool>;
+    // false if there is no such user
+    async fn set_administrator(&self, id: &str, administrator: bool) -> Result<bool>;
+    // revoking sessions includes personal access tokens, returning how many of those were revoked
+    async fn set_password(
+        &self,
+        id: &str,
+        password_data: Vec<u8>,
+        suite: PasswordSuite,
+        revoke_sessions: bool,
+    ) -> Result<u64>;
+    // every session but `except_token` and every personal access token, returning how many of
+    // those were revoked
+    async fn revoke_sessions(&self, id: &str, except_token: Option<&str>) -> Result<u64>;
+    // replaces any existing recovery codes
+    async fn enable_mfa(&self, id: &str, secret: &str, codes: Vec<String>) -> Result<()>;
+    async fn disable_mfa(&self, id: &str) -> Result<()>;
+    // removes the user with their profile, sessions, personal tokens, passkeys and codes,
+    // returning the profile
+    async fn delete(&self, id: &str) -> Result<Option<UserProfile>>;
+    // false if there was no pending account to reject
+    async fn delete_pending(&self, id: &str) -> Result<bool>;
+    // revokes every session and personal access token and cancels any delayed reset, for
+    // account recovery, returning how many personal access tokens were revoked
+    async fn recover(&self, id: &str) -> Result<u64>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SettingsUpdate {
+    pub security_notifications: Option<bool>,
+    pub locale: Option<String>,
+}
+
+#[async_trait]
+pub trait ProfileStore: Send + Sync {
+    async fn find(&self, id: &str) -> Result<Option<UserProfile>>;
+    async fn update(&self, id: &str, update: ProfileUpdate) -> Result<()>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub website: Option<String>,
+    pub avatar: Option<String>,
+}
+
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn find(&self, id: &str) -> Result<Option<Session>>;
+    async fn find_by_token(&self, token: &str) -> Result<Option<Session>>;
+    async fn list(&self, user_id: &str) -> Result<Vec<Session>>;
+    async fn create(&self, session: Session) -> Result<()>;
+    async fn delete_by_token(&self, token: &str) -> Result<()>;
+    // false if the user has no such session
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait PasskeyStore: Send + Sync {
+    async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>>;
+    async fn list(&self, user_id: &str) -> Result<Vec<Passkey>>;
+    async fn create(&self, passkey: Passkey) -> Result<()>;
+    // false if the user has no such passkey
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool>;
+}
+
+// Recovery codes are written through `UserStore::enable_mfa`
+#[async_trait]
+pub trait CodeStore: Send + Sync {
+    // true if the code existed, it can't be used again
+    async fn consume(&self, user_id: &str, code: &str) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait SettingsStore: Send + Sync {
+    // created with a new OPAQUE server setup on first use, but never once users exist, since
+    // their passwords only work with the setup they were registered under
+    async fn get(&self) -> Result<Settings>;
+    // without creating anything
+    async fn find(&self) -> Result<Option<Settings>>;
+    // for imports and rotations
+    async fn set(&self, settings: Settings) -> Result<()>;
+}
+
+#[async_trait]
+pub trait ServiceAccountStore: Send + Sync {
+    async fn find_by_key_hash(&self, key_hash: &str) -> Result<Option<ServiceAccount>>;
+    // ordered by id
+    async fn list(&self) -> Result<Vec<ServiceAccount>>;
+    // fails with `ServiceAccountExists` if the name is taken
+    async fn create(&self, account: ServiceAccount) -> Result<()>;
+    // None if there is no such account
+    async fn set_scopes(&self, id: &str, scopes: &[String]) -> Result<Option<ServiceAccount>>;
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()>;
+    // false if there is no such account
+    async fn delete(&self, id: &str) -> Result<bool>;
+}
+
+#[async_trait]
+pub trait PersonalTokenStore: Send + Sync {
+    // only tokens that expire after `now`
+    async fn find_by_token_hash(&self, token_hash: &str, now: u64)
+        -> Result<Option<PersonalToken>>;
+    // newest first, including expired tokens until they're purged
+    async fn list(&self, user_id: &str) -> Result<Vec<PersonalToken>>;
+    async fn create(&self, token: PersonalToken) -> Result<()>;
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()>;
+    // false if the user has no such token
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool>;
+    // tokens that expired before `now`, returning how many
+    async fn purge_expired(&self, now: u64) -> Result<u64>;
+}
+
+// Append-only, see `security_event::record`
+#[async_trait]
+pub trait SecurityEventStore: Send + Sync {
+    async fn record(&self, event: SecurityEvent) -> Result<()>;
+    // newest first, starting before the event `before`
+    async fn list(
+        &self,
+        user_id: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SecurityEvent>>;
+    // whether the user has signed in before, but never with this user agent
+    async fn is_new_device(&self, user_id: &str, user_agent: Option<&str>) -> Result<bool>;
+    // events created before `cutoff`, returning how many
+    async fn purge_before(&self, cutoff: u64) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait RecoveryStore: Send + Sync {
+    async fn create(&self, token: RecoveryToken) -> Result<()>;
+    // removes the token, it can't be used again
+    async fn take(&self, token: &str) -> Result<Option<RecoveryToken>>;
+    // tokens created before `cutoff`, returning how many
+    async fn purge_before(&self, cutoff: u64) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait ResetRequestStore: Send + Sync {
+    async fn create(&self, request: ResetRequest) -> Result<()>;
+    async fn find(&self, token: &str) -> Result<Option<ResetRequest>>;
+    async fn delete(&self, token: &str) -> Result<()>;
+    // requests that became available before `cutoff`, returning how many
+    async fn purge_before(&self, cutoff: u64) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait InviteStore: Send + Sync {
+    async fn create(&self, invite: Invite) -> Result<()>;
+    // not used up and not expired at `now`, it's redeemed by `UserStore::create`
+    async fn is_usable(&self, code: &str, now: u64) -> Result<bool>;
+    // newest first
+    async fn list(&self, created_by: &str) -> Result<Vec<Invite>>;
+    async fn delete(&self, code: &str, created_by: &str) -> Result<()>;
+}
+
+// How a delivery attempt ended, every attempt releases the claim on the email
+#[derive(Clone, Debug)]
+pub enum Delivery {
+    Sent,
+    // given up on, like `Sent` the body is cleared
+    Failed { error: String },
+    Retry { error: String, next_attempt_at: u64 },
+}
+
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    async fn enqueue(&self, email: OutboxEmail) -> Result<()>;
+    // atomically claims the next email due at `now`, or any pending one with `ignore_backoff`,
+    // and emails claimed before `stale_before`, so several replicas can share the queue
+    async fn claim(
+        &self,
+        now: u64,
+        stale_before: u64,
+        ignore_backoff: bool,
+    ) -> Result<Option<OutboxEmail>>;
+    async fn finish(&self, id: &str, delivery: Delivery, now: u64) -> Result<()>;
+    // newest first, starting before the email `before`
+    async fn list(
+        &self,
+        status: Option<OutboxStatus>,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>>;
+    async fn count(&self, status: OutboxStatus) -> Result<u64>;
+    // sent or failed emails created or sent before `cutoff`, returning how many
+    async fn purge_finished(&self, cutoff: u64) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait ChallengeStore: Send + Sync {
+    // remembers a proof-of-work challenge until `expires_at`, false if it was already used
+    async fn use_once(&self, id: &str, expires_at: u64) -> Result<bool>;
+}
+
+// Files belong to the CDN, the store only marks them as used or unused
+#[async_trait]
+pub trait FileStore: Send + Sync {
+    // neither deleted nor flagged
+    async fn find(&self, id: &str) -> Result<Option<File>>;
+    async fn set_attached(&self, id: &str, attached: bool) -> Result<()>;
+}
+
+#[derive(Clone)]
+pub struct Stores {
+    pub users: Arc<dyn UserStore>,
+    pub profiles: Arc<dyn ProfileStore>,
+    pub sessions: Arc<dyn SessionStore>,
+    pub passkeys: Arc<dyn PasskeyStore>,
+    pub codes: Arc<dyn CodeStore>,
+    pub settings: Arc<dyn SettingsStore>,
+    pub service_accounts: Arc<dyn ServiceAccountStore>,
+    pub personal_tokens: Arc<dyn PersonalTokenStore>,
+    pub security_events: Arc<dyn SecurityEventStore>,
+    pub recovery_tokens: Arc<dyn RecoveryStore>,
+    pub reset_requests: Arc<dyn ResetRequestStore>,
+    pub invites: Arc<dyn InviteStore>,
+    pub outbox: Arc<dyn OutboxStore>,
+    pub files: Arc<dyn FileStore>,
+    pub challenges: Arc<dyn ChallengeStore>,
+}
+
+impl Stores {
+    fn shared<T>(store: Arc<T>) -> Stores
+    where
+        T: UserStore + ProfileStore + SessionStore + PasskeyStore + CodeStore + SettingsStore,
+        T: ServiceAccountStore + PersonalTokenStore + SecurityEventStore + RecoveryStore,
+        T: ResetRequestStore + InviteStore + OutboxStore + FileStore + ChallengeStore + 'static,
+    {
+        Stores {
+            users: store.clone(),
+            profiles: store.clone(),
+            sessions: store.clone(),
+            passkeys: store.clone(),
+            codes: store.clone(),
+            settings: store.clone(),
+            service_accounts: store.clone(),
+            personal_tokens: store.clone(),
+            security_events: store.clone(),
+            recovery_tokens: store.clone(),
+            reset_requests: store.clone(),
+            invites: store.clone(),
+            outbox: store.clone(),
+            files: store.clone(),
+            challenges: store,
+        }
+    }
+
+    pub fn mongo() -> Stores {
+        Stores::shared(Arc::new(mongo::MongoStore))
+    }
+
+    pub fn memory() -> Stores {
+        Stores::shared(Arc::new(memory::MemoryStore::default()))
+    }
+
+    pub fn users(&self) -> Data<dyn UserStore> {
+        Data::from(self.users.clone())
+    }
+
+    pub fn profiles(&self) -> Data<dyn ProfileStore> {
+        Data::from(self.profiles.clone())
+    }
+
+    pub fn sessions(&self) -> Data<dyn SessionStore> {
+        Data::from(self.sessions.clone())
+    }
+
+    pub fn passkeys(&self) -> Data<dyn PasskeyStore> {
+        Data::from(self.passkeys.clone())
+    }
+
+    pub fn codes(&self) -> Data<dyn CodeStore> {
+        Data::from(self.codes.clone())
+    }
+
+    pub fn settings(&self) -> Data<dyn SettingsStore> {
+        Data::from(self.settings.clone())
+    }
+
+    pub fn service_accounts(&self) -> Data<dyn ServiceAccountStore> {
+        Data::from(self.service_accounts.clone())
+    }
+
+    pub fn personal_tokens(&self) -> Data<dyn PersonalTokenStore> {
+        Data::from(self.personal_tokens.clone())
+    }
+
+    pub fn security_events(&self) -> Data<dyn SecurityEventStore> {
+        Data::from(self.security_events.clone())
+    }
+
+    pub fn recovery_tokens(&self) -> Data<dyn RecoveryStore> {
+        Data::from(self.recovery_tokens.clone())
+    }
+
+    pub fn reset_requests(&self) -> Data<dyn ResetRequestStore> {
+        Data::from(self.reset_requests.clone())
+    }
+
+    pub fn invites(&self) -> Data<dyn InviteStore> {
+        Data::from(self.invites.clone())
+    }
+
+    pub fn outbox(&self) -> Data<dyn OutboxStore> {
+        Data::from(self.outbox.clone())
+    }
+
+    pub fn files(&self) -> Data<dyn FileStore> {
+        Data::from(self.files.clone())
+    }
+
+    pub fn challenges(&self) -> Data<dyn ChallengeStore> {
+        Data::from(self.challenges.clone())
+    }
+
+    // registers every store as app data for the handlers to extract
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        cfg.app_data(self.users())
+            .app_data(self.profiles())
+            .app_data(self.sessions())
+            .app_data(self.passkeys())
+            .app_data(self.codes())
+            .app_data(self.settings())
+            .app_data(self.service_accounts())
+            .app_data(self.personal_tokens())
+            .app_data(self.security_events())
+            .app_data(self.recovery_tokens())
+            .app_data(self.reset_requests())
+            .app_data(self.invites())
+            .app_data(self.outbox())
+            .app_data(self.files())
+            .app_data(self.challenges());
+    }
+}
+
+// Whether the stores live in MongoDB, otherwise the server never connects to it
+pub fn uses_mongo() -> bool {
+    STORAGE_BACKEND.as_str() == "mongodb"
+}
+
+pub fn create() -> Stores {
+    match STORAGE_BACKEND.as_str() {
+        "mongodb" => Stores::mongo(),
+        "memory" => {
+            info!("Using in-memory storage, accounts are lost on restart");
+            Stores::memory()
+        }
+        other => panic!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
@@ -0,0 +1,1108 @@
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use log::error;
+use mongodb::{
+    bson::{self, doc, Binary, Bson, DateTime, Document},
+    options::ReturnDocument,
+};
+use tracing::instrument;
+
+use super::{
+    ChallengeStore, CodeStore, Delivery, FileStore, InviteStore, OutboxStore, PasskeyStore,
+    PersonalTokenStore, ProfileStore, ProfileUpdate, RecoveryStore, ResetRequestStore,
+    SecurityEventStore, ServiceAccountStore, SessionStore, SettingsStore, SettingsUpdate,
+    UserStore,
+};
+use crate::{
+    database::{
+        code::{self, Code},
+        files::{self, File},
+        indexes::email_collation,
+        invite::{self, Invite},
+        outbox::{self, OutboxEmail, OutboxStatus},
+        passkey::{self, Passkey},
+        personal_token::{self, PersonalToken},
+        profile::{self, UserProfile},
+        recovery::{self, RecoveryToken},
+        reset_request::{self, ResetRequest},
+        security_event::{self, SecurityEvent},
+        service_account::{self, ServiceAccount},
+        session::{self, Session},
+        settings::{self, Settings},
+        start_transaction,
+        used_challenge::{self, UsedChallenge},
+        user::{self, User},
+        username_history::{self, UsernameHistory},
+    },
+    errors::{is_duplicate_key, Error, Result},
+    opaque::{create_settings, PasswordSuite},
+    utilities::get_time_secs,
+};
+
+pub struct MongoStore;
+
+async fn collect<T>(cursor: mongodb::Cursor<T>) -> Result<Vec<T>>
+where
+    T: serde::de::DeserializeOwned + Unpin + Send + Sync,
+{
+    Ok(cursor
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<std::result::Result<Vec<_>, mongodb::error::Error>>()?)
+}
+
+fn usable_invite(code: &str, now: u64) -> Document {
+    doc! {
+        "code": code,
+        "$and": [
+            { "$or": [
+                { "max_uses": null },
+                { "$expr": { "$lt": ["$uses", "$max_uses"] } },
+            ] },
+            { "$or": [
+                { "expires_at": null },
+                { "expires_at": { "$gt": now as i64 } },
+            ] },
+        ],
+    }
+}
+
+#[async_trait]
+impl UserStore for MongoStore {
+    #[instrument(name = "db.users.find", skip_all)]
+    async fn find(&self, id: &str) -> Result<Option<User>> {
+        Ok(user::get_collection().find_one(doc! { "id": id }).await?)
+    }
+
+    #[instrument(name = "db.users.find_by_email", skip_all)]
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        Ok(user::get_collection()
+            .find_one(doc! { "email": email })
+            .collation(email_collation())
+            .await?)
+    }
+
+    #[instrument(name = "db.users.find_by_username", skip_all)]
+    async fn find_by_username(&self, username: &str, key: &str) -> Result<Option<User>> {
+        Ok(user::get_collection()
+            .find_one(doc! {
+                "$or": [
+                    { "username_key": key },
+                    { "username": username },
+                ]
+            })
+            .await?)
+    }
+
+    #[instrument(name = "db.users.is_username_taken", skip_all)]
+    async fn is_username_taken(&self, key: &str, except: Option<&str>) -> Result<bool> {
+        // m002 gave every user a key, so this stays on the username_key index
+        let mut filter = doc! { "username_key": key };
+        if let Some(except) = except {
+            filter.insert("id", doc! { "$ne": except });
+        }
+        Ok(user::get_collection().find_one(filter).await?.is_some())
+    }
+
+    #[instrument(name = "db.users.list_pending_approval", skip_all)]
+    async fn list_pending_approval(&self) -> Result<Vec<User>> {
+        collect(
+            user::get_collection()
+                .find(doc! { "pending_approval": true })
+                .sort(doc! { "id": 1 })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.users.list", skip_all)]
+    async fn list(&self, after: Option<&str>, limit: usize) -> Result<Vec<User>> {
+        let filter = match after {
+            Some(after) => doc! { "id": { "$gt": after } },
+            None => doc! {},
+        };
+        collect(
+            user::get_collection()
+                .find(filter)
+                .sort(doc! { "id": 1 })
+                .limit(limit as i64)
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.users.create", skip_all)]
+    async fn create(
+        &self,
+        user: User,
+        profile: UserProfile,
+        invite: Option<&str>,
+        session: Option<Session>,
+    ) -> Result<()> {
+        let mut transaction = start_transaction().await?;
+        if let Some(code) = invite {
+            let result = invite::get_collection()
+                .update_one(
+                    usable_invite(code, get_time_secs()),
+                    doc! { "$inc": { "uses": 1 } },
+                )
+                .session(&mut transaction)
+                .await?;
+            if result.modified_count == 0 {
+                return Err(Error::InvalidInvite);
+            }
+        }
+        user::get_collection()
+            .insert_one(user)
+            .session(&mut transaction)
+            .await?;
+        profile::get_collection()
+            .insert_one(profile)
+            .session(&mut transaction)
+            .await?;
+        if let Some(session) = session {
+            session::get_collection()
+                .insert_one(session)
+                .session(&mut transaction)
+                .await?;
+        }
+        transaction.commit_transaction().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.update_settings", skip_all)]
+    async fn update_settings(&self, id: &str, update: SettingsUpdate) -> Result<()> {
+        let mut update_query = doc! {};
+        if let Some(security_notifications) = update.security_notifications {
+            update_query.insert("security_notifications", security_notifications);
+        }
+        if let Some(locale) = update.locale {
+            update_query.insert("locale", locale);
+        }
+        if update_query.is_empty() {
+            return Ok(());
+        }
+        user::get_collection()
+            .update_one(doc! { "id": id }, doc! { "$set": update_query })
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.change_username", skip_all)]
+    async fn change_username(
+        &self,
+        id: &str,
+        username: &str,
+        key: &str,
+        history: UsernameHistory,
+    ) -> Result<()> {
+        let changed_at = history.changed_at;
+        let mut transaction = start_transaction().await?;
+        username_history::get_collection()
+            .insert_one(history)
+            .session(&mut transaction)
+            .await?;
+        user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$set": {
+                        "username": username,
+                        "username_key": key,
+                        "username_changed_at": changed_at as i64
+                    }
+                },
+            )
+            .session(&mut transaction)
+            .await?;
+        transaction.commit_transaction().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.find_username_history", skip_all)]
+    async fn find_username_history(
+        &self,
+        key: &str,
+        held_after: Option<u64>,
+        except: Option<&str>,
+    ) -> Result<Option<UsernameHistory>> {
+        let mut filter = doc! { "username_key": key };
+        if let Some(held_after) = held_after {
+            filter.insert("released_at", doc! { "$gt": held_after as i64 });
+        }
+        if let Some(except) = except {
+            filter.insert("user_id", doc! { "$ne": except });
+        }
+        Ok(username_history::get_collection()
+            .find_one(filter)
+            .sort(doc! { "changed_at": -1 })
+            .await?)
+    }
+
+    #[instrument(name = "db.users.set_email_verified", skip_all)]
+    async fn set_email_verified(&self, id: &str, verified_at: u64) -> Result<()> {
+        user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$set": {
+                        "email_verified": true,
+                        "email_verified_at": verified_at as i64
+                    }
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.set_administrator", skip_all)]
+    async fn set_administrator(&self, id: &str, administrator: bool) -> Result<bool> {
+        let result = user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! { "$set": { "platform_administrator": administrator } },
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    #[instrument(name = "db.users.approve", skip_all)]
+    async fn approve(&self, id: &str) -> Result<bool> {
+        let result = user::get_collection()
+            .update_one(
+                doc! {
+                    "id": id,
+                    "pending_approval": true
+                },
+                doc! {
+                    "$set": {
+                        "pending_approval": false
+                    }
+                },
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    #[instrument(name = "db.users.set_password", skip_all)]
+    async fn set_password(
+        &self,
+        id: &str,
+        password_data: Vec<u8>,
+        suite: PasswordSuite,
+        revoke_sessions: bool,
+    ) -> Result<u64> {
+        let bin = Binary {
+            bytes: password_data,
+            subtype: bson::spec::BinarySubtype::Generic,
+        };
+        let suite =
+            bson::to_bson(&suite).expect("Unexpected error: failed to serialize password suite");
+        let mut transaction = start_transaction().await?;
+        user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$set": {
+                        "password_data": bin,
+                        "password_suite": suite
+                    }
+                },
+            )
+            .session(&mut transaction)
+            .await?;
+        let mut revoked = 0;
+        if revoke_sessions {
+            session::get_collection()
+                .delete_many(doc! { "user_id": id })
+                .session(&mut transaction)
+                .await?;
+            revoked = personal_token::get_collection()
+                .delete_many(doc! { "user_id": id })
+                .session(&mut transaction)
+                .await?
+                .deleted_count;
+        }
+        transaction.commit_transaction().await?;
+        Ok(revoked)
+    }
+
+    #[instrument(name = "db.users.revoke_sessions", skip_all)]
+    async fn revoke_sessions(&self, id: &str, except_token: Option<&str>) -> Result<u64> {
+        let mut filter = doc! { "user_id": id };
+        if let Some(except_token) = except_token {
+            filter.insert("token", doc! { "$ne": except_token });
+        }
+        let mut transaction = start_transaction().await?;
+        session::get_collection()
+            .delete_many(filter)
+            .session(&mut transaction)
+            .await?;
+        let revoked = personal_token::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?
+            .deleted_count;
+        transaction.commit_transaction().await?;
+        Ok(revoked)
+    }
+
+    #[instrument(name = "db.users.enable_mfa", skip_all)]
+    async fn enable_mfa(&self, id: &str, secret: &str, codes: Vec<String>) -> Result<()> {
+        let codes = codes
+            .into_iter()
+            .map(|code| Code {
+                code,
+                user_id: id.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let mut transaction = start_transaction().await?;
+        user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$set": {
+                        "mfa_enabled": true,
+                        "mfa_secret": secret
+                    }
+                },
+            )
+            .session(&mut transaction)
+            .await?;
+        code::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        if !codes.is_empty() {
+            code::get_collection()
+                .insert_many(codes)
+                .session(&mut transaction)
+                .await?;
+        }
+        transaction.commit_transaction().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.disable_mfa", skip_all)]
+    async fn disable_mfa(&self, id: &str) -> Result<()> {
+        let mut transaction = start_transaction().await?;
+        user::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! {
+                    "$set": {
+                        "mfa_enabled": false,
+                        "mfa_secret": Bson::Null
+                    }
+                },
+            )
+            .session(&mut transaction)
+            .await?;
+        code::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        transaction.commit_transaction().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.users.delete", skip_all)]
+    async fn delete(&self, id: &str) -> Result<Option<UserProfile>> {
+        let mut transaction = start_transaction().await?;
+        session::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        user::get_collection()
+            .delete_one(doc! { "id": id })
+            .session(&mut transaction)
+            .await?;
+        // accounts from before profiles existed may not have one
+        let profile = profile::get_collection()
+            .find_one_and_delete(doc! { "id": id })
+            .session(&mut transaction)
+            .await?;
+        personal_token::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        passkey::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        code::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        transaction.commit_transaction().await?;
+        Ok(profile)
+    }
+
+    #[instrument(name = "db.users.delete_pending", skip_all)]
+    async fn delete_pending(&self, id: &str) -> Result<bool> {
+        let mut transaction = start_transaction().await?;
+        let result = user::get_collection()
+            .delete_one(doc! {
+                "id": id,
+                "pending_approval": true
+            })
+            .session(&mut transaction)
+            .await?;
+        if result.deleted_count == 0 {
+            return Ok(false);
+        }
+        profile::get_collection()
+            .delete_one(doc! { "id": id })
+            .session(&mut transaction)
+            .await?;
+        transaction.commit_transaction().await?;
+        Ok(true)
+    }
+
+    #[instrument(name = "db.users.recover", skip_all)]
+    async fn recover(&self, id: &str) -> Result<u64> {
+        let mut transaction = start_transaction().await?;
+        session::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        let revoked = personal_token::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?
+            .deleted_count;
+        reset_request::get_collection()
+            .delete_many(doc! { "user_id": id })
+            .session(&mut transaction)
+            .await?;
+        transaction.commit_transaction().await?;
+        Ok(revoked)
+    }
+}
+
+#[async_trait]
+impl ProfileStore for MongoStore {
+    #[instrument(name = "db.profiles.find", skip_all)]
+    async fn find(&self, id: &str) -> Result<Option<UserProfile>> {
+        Ok(profile::get_collection()
+            .find_one(doc! { "id": id })
+            .await?)
+    }
+
+    #[instrument(name = "db.profiles.update", skip_all)]
+    async fn update(&self, id: &str, update: ProfileUpdate) -> Result<()> {
+        let mut update_query = Document::new();
+        if let Some(display_name) = update.display_name {
+            update_query.insert("display_name", display_name);
+        }
+        if let Some(description) = update.description {
+            update_query.insert("description", description);
+        }
+        if let Some(website) = update.website {
+            update_query.insert("website", website);
+        }
+        if let Some(avatar) = update.avatar {
+            update_query.insert("avatar", avatar);
+        }
+        if update_query.is_empty() {
+            return Ok(());
+        }
+        profile::get_collection()
+            .update_one(doc! { "id": id }, doc! { "$set": update_query })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MongoStore {
+    #[instrument(name = "db.sessions.find", skip_all)]
+    async fn find(&self, id: &str) -> Result<Option<Session>> {
+        Ok(session::get_collection()
+            .find_one(doc! { "id": id })
+            .await?)
+    }
+
+    #[instrument(name = "db.sessions.find_by_token", skip_all)]
+    async fn find_by_token(&self, token: &str) -> Result<Option<Session>> {
+        Ok(session::get_collection()
+            .find_one(doc! { "token": token })
+            .await?)
+    }
+
+    #[instrument(name = "db.sessions.list", skip_all)]
+    async fn list(&self, user_id: &str) -> Result<Vec<Session>> {
+        collect(
+            session::get_collection()
+                .find(doc! { "user_id": user_id })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.sessions.create", skip_all)]
+    async fn create(&self, session: Session) -> Result<()> {
+        session::get_collection().insert_one(session).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.sessions.delete_by_token", skip_all)]
+    async fn delete_by_token(&self, token: &str) -> Result<()> {
+        session::get_collection()
+            .delete_one(doc! { "token": token })
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.sessions.delete", skip_all)]
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let result = session::get_collection()
+            .delete_one(doc! {
+                "id": id,
+                "user_id": user_id
+            })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl PasskeyStore for MongoStore {
+    #[instrument(name = "db.passkeys.find_by_credential_id", skip_all)]
+    async fn find_by_credential_id(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        Ok(passkey::get_collection()
+            .find_one(doc! { "credential_id": credential_id })
+            .await?)
+    }
+
+    #[instrument(name = "db.passkeys.list", skip_all)]
+    async fn list(&self, user_id: &str) -> Result<Vec<Passkey>> {
+        collect(
+            passkey::get_collection()
+                .find(doc! { "user_id": user_id })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.passkeys.create", skip_all)]
+    async fn create(&self, passkey: Passkey) -> Result<()> {
+        passkey::get_collection().insert_one(passkey).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.passkeys.delete", skip_all)]
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let result = passkey::get_collection()
+            .delete_one(doc! {
+                "id": id,
+                "user_id": user_id
+            })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl CodeStore for MongoStore {
+    #[instrument(name = "db.codes.consume", skip_all)]
+    async fn consume(&self, user_id: &str, code: &str) -> Result<bool> {
+        Ok(code::get_collection()
+            .find_one_and_delete(doc! {
+                "code": code,
+                "user_id": user_id
+            })
+            .await?
+            .is_some())
+    }
+}
+
+#[async_trait]
+impl SettingsStore for MongoStore {
+    #[instrument(name = "db.settings.get", skip_all)]
+    async fn get(&self) -> Result<Settings> {
+        if let Some(settings) = SettingsStore::find(self).await? {
+            return Ok(settings);
+        }
+        if user::get_collection().find_one(doc! {}).await?.is_some() {
+            error!("The OPAQUE server setup is missing but users exist, import it from a backup");
+            return Err(Error::ServerSetupUnavailable);
+        }
+        // replicas starting together all end up with the same setup
+        let settings = bson::to_document(&create_settings())
+            .expect("Unexpected error: failed to serialize settings");
+        settings::get_collection()
+            .update_one(
+                doc! { "_id": settings::SETTINGS_ID },
+                doc! { "$setOnInsert": settings },
+            )
+            .upsert(true)
+            .await?;
+        SettingsStore::find(self)
+            .await?
+            .ok_or(Error::ServerSetupUnavailable)
+    }
+
+    #[instrument(name = "db.settings.find", skip_all)]
+    async fn find(&self) -> Result<Option<Settings>> {
+        Ok(settings::get_collection()
+            .find_one(doc! { "current_server_setup": { "$exists": true } })
+            .await?)
+    }
+
+    #[instrument(name = "db.settings.set", skip_all)]
+    async fn set(&self, settings: Settings) -> Result<()> {
+        settings::get_collection()
+            .replace_one(
+                doc! { "current_server_setup": { "$exists": true } },
+                settings,
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ServiceAccountStore for MongoStore {
+    #[instrument(name = "db.service_accounts.find_by_key_hash", skip_all)]
+    async fn find_by_key_hash(&self, key_hash: &str) -> Result<Option<ServiceAccount>> {
+        Ok(service_account::get_collection()
+            .find_one(doc! { "key_hash": key_hash })
+            .await?)
+    }
+
+    #[instrument(name = "db.service_accounts.list", skip_all)]
+    async fn list(&self) -> Result<Vec<ServiceAccount>> {
+        collect(
+            service_account::get_collection()
+                .find(doc! {})
+                .sort(doc! { "id": 1 })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.service_accounts.create", skip_all)]
+    async fn create(&self, account: ServiceAccount) -> Result<()> {
+        service_account::get_collection()
+            .insert_one(account)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.service_accounts.set_scopes", skip_all)]
+    async fn set_scopes(&self, id: &str, scopes: &[String]) -> Result<Option<ServiceAccount>> {
+        Ok(service_account::get_collection()
+            .find_one_and_update(
+                doc! { "id": id },
+                doc! { "$set": { "scopes": scopes.to_vec() } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    #[instrument(name = "db.service_accounts.set_last_used", skip_all)]
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()> {
+        service_account::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! { "$set": { "last_used_at": used_at as i64 } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.service_accounts.delete", skip_all)]
+    async fn delete(&self, id: &str) -> Result<bool> {
+        let result = service_account::get_collection()
+            .delete_one(doc! { "id": id })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[async_trait]
+impl PersonalTokenStore for MongoStore {
+    #[instrument(name = "db.personal_tokens.find_by_token_hash", skip_all)]
+    async fn find_by_token_hash(
+        &self,
+        token_hash: &str,
+        now: u64,
+    ) -> Result<Option<PersonalToken>> {
+        Ok(personal_token::get_collection()
+            .find_one(doc! {
+                "token_hash": token_hash,
+                "expires_at": { "$gt": now as i64 },
+            })
+            .await?)
+    }
+
+    #[instrument(name = "db.personal_tokens.list", skip_all)]
+    async fn list(&self, user_id: &str) -> Result<Vec<PersonalToken>> {
+        collect(
+            personal_token::get_collection()
+                .find(doc! { "user_id": user_id })
+                .sort(doc! { "id": -1 })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.personal_tokens.create", skip_all)]
+    async fn create(&self, token: PersonalToken) -> Result<()> {
+        personal_token::get_collection().insert_one(token).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.personal_tokens.set_last_used", skip_all)]
+    async fn set_last_used(&self, id: &str, used_at: u64) -> Result<()> {
+        personal_token::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! { "$set": { "last_used_at": used_at as i64 } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.personal_tokens.delete", skip_all)]
+    async fn delete(&self, id: &str, user_id: &str) -> Result<bool> {
+        let result = personal_token::get_collection()
+            .delete_one(doc! {
+                "id": id,
+                "user_id": user_id
+            })
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    #[instrument(name = "db.personal_tokens.purge_expired", skip_all)]
+    async fn purge_expired(&self, now: u64) -> Result<u64> {
+        let result = personal_token::get_collection()
+            .delete_many(doc! { "expires_at": { "$lt": now as i64 } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl SecurityEventStore for MongoStore {
+    #[instrument(name = "db.security_events.record", skip_all)]
+    async fn record(&self, event: SecurityEvent) -> Result<()> {
+        security_event::get_collection().insert_one(event).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.security_events.list", skip_all)]
+    async fn list(
+        &self,
+        user_id: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SecurityEvent>> {
+        let mut filter = doc! { "user_id": user_id };
+        if let Some(before) = before {
+            filter.insert("id", doc! { "$lt": before });
+        }
+        collect(
+            security_event::get_collection()
+                .find(filter)
+                .sort(doc! { "id": -1 })
+                .limit(limit as i64)
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.security_events.is_new_device", skip_all)]
+    async fn is_new_device(&self, user_id: &str, user_agent: Option<&str>) -> Result<bool> {
+        let collection = security_event::get_collection();
+        let previous = collection
+            .find_one(doc! {
+                "user_id": user_id,
+                "kind": "LOGIN",
+            })
+            .await?;
+        if previous.is_none() {
+            return Ok(false);
+        }
+        let known = collection
+            .find_one(doc! {
+                "user_id": user_id,
+                "kind": "LOGIN",
+                "user_agent": user_agent,
+            })
+            .await?;
+        Ok(known.is_none())
+    }
+
+    #[instrument(name = "db.security_events.purge_before", skip_all)]
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let result = security_event::get_collection()
+            .delete_many(doc! { "created_at": { "$lt": cutoff as i64 } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl RecoveryStore for MongoStore {
+    #[instrument(name = "db.recovery.create", skip_all)]
+    async fn create(&self, token: RecoveryToken) -> Result<()> {
+        recovery::get_collection().insert_one(token).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.recovery.take", skip_all)]
+    async fn take(&self, token: &str) -> Result<Option<RecoveryToken>> {
+        Ok(recovery::get_collection()
+            .find_one_and_delete(doc! { "token": token })
+            .await?)
+    }
+
+    #[instrument(name = "db.recovery.purge_before", skip_all)]
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let result = recovery::get_collection()
+            .delete_many(doc! { "created_at": { "$lt": cutoff as i64 } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl ResetRequestStore for MongoStore {
+    #[instrument(name = "db.reset_requests.create", skip_all)]
+    async fn create(&self, request: ResetRequest) -> Result<()> {
+        reset_request::get_collection().insert_one(request).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.reset_requests.find", skip_all)]
+    async fn find(&self, token: &str) -> Result<Option<ResetRequest>> {
+        Ok(reset_request::get_collection()
+            .find_one(doc! { "token": token })
+            .await?)
+    }
+
+    #[instrument(name = "db.reset_requests.delete", skip_all)]
+    async fn delete(&self, token: &str) -> Result<()> {
+        reset_request::get_collection()
+            .delete_one(doc! { "token": token })
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.reset_requests.purge_before", skip_all)]
+    async fn purge_before(&self, cutoff: u64) -> Result<u64> {
+        let result = reset_request::get_collection()
+            .delete_many(doc! { "available_at": { "$lt": cutoff as i64 } })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl InviteStore for MongoStore {
+    #[instrument(name = "db.invites.create", skip_all)]
+    async fn create(&self, invite: Invite) -> Result<()> {
+        invite::get_collection().insert_one(invite).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.invites.is_usable", skip_all)]
+    async fn is_usable(&self, code: &str, now: u64) -> Result<bool> {
+        Ok(invite::get_collection()
+            .find_one(usable_invite(code, now))
+            .await?
+            .is_some())
+    }
+
+    #[instrument(name = "db.invites.list", skip_all)]
+    async fn list(&self, created_by: &str) -> Result<Vec<Invite>> {
+        collect(
+            invite::get_collection()
+                .find(doc! { "created_by": created_by })
+                .sort(doc! { "created_at": -1 })
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.invites.delete", skip_all)]
+    async fn delete(&self, code: &str, created_by: &str) -> Result<()> {
+        invite::get_collection()
+            .delete_one(doc! {
+                "code": code,
+                "created_by": created_by,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutboxStore for MongoStore {
+    #[instrument(name = "db.outbox.enqueue", skip_all)]
+    async fn enqueue(&self, email: OutboxEmail) -> Result<()> {
+        outbox::get_collection().insert_one(email).await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.outbox.claim", skip_all)]
+    async fn claim(
+        &self,
+        now: u64,
+        stale_before: u64,
+        ignore_backoff: bool,
+    ) -> Result<Option<OutboxEmail>> {
+        let pending = if ignore_backoff {
+            doc! { "status": "PENDING" }
+        } else {
+            doc! { "status": "PENDING", "next_attempt_at": { "$lte": now as i64 } }
+        };
+        let stale = doc! {
+            "status": "SENDING",
+            "claimed_at": { "$lt": stale_before as i64 }
+        };
+        Ok(outbox::get_collection()
+            .find_one_and_update(
+                doc! { "$or": [pending, stale] },
+                doc! {
+                    "$set": {
+                        "status": "SENDING",
+                        "claimed_at": now as i64,
+                    }
+                },
+            )
+            .sort(doc! { "next_attempt_at": 1 })
+            .return_document(ReturnDocument::After)
+            .await?)
+    }
+
+    #[instrument(name = "db.outbox.finish", skip_all)]
+    async fn finish(&self, id: &str, delivery: Delivery, now: u64) -> Result<()> {
+        let set = match delivery {
+            Delivery::Sent => doc! {
+                "status": "SENT",
+                "sent_at": now as i64,
+                "claimed_at": None::<i64>,
+                "text": "",
+                "html": "",
+            },
+            Delivery::Failed { error } => doc! {
+                "status": "FAILED",
+                "last_error": error,
+                "claimed_at": None::<i64>,
+                "text": "",
+                "html": "",
+            },
+            Delivery::Retry {
+                error,
+                next_attempt_at,
+            } => doc! {
+                "status": "PENDING",
+                "last_error": error,
+                "next_attempt_at": next_attempt_at as i64,
+                "claimed_at": None::<i64>,
+            },
+        };
+        outbox::get_collection()
+            .update_one(
+                doc! { "id": id },
+                doc! { "$set": set, "$inc": { "attempts": 1 } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "db.outbox.list", skip_all)]
+    async fn list(
+        &self,
+        status: Option<OutboxStatus>,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<OutboxEmail>> {
+        let mut filter = doc! {};
+        if let Some(status) = status {
+            filter.insert("status", status.as_str());
+        }
+        if let Some(before) = before {
+            filter.insert("id", doc! { "$lt": before });
+        }
+        collect(
+            outbox::get_collection()
+                .find(filter)
+                .sort(doc! { "id": -1 })
+                .limit(limit as i64)
+                .await?,
+        )
+        .await
+    }
+
+    #[instrument(name = "db.outbox.count", skip_all)]
+    async fn count(&self, status: OutboxStatus) -> Result<u64> {
+        Ok(outbox::get_collection()
+            .count_documents(doc! { "status": status.as_str() })
+            .await?)
+    }
+
+    #[instrument(name = "db.outbox.purge_finished", skip_all)]
+    async fn purge_finished(&self, cutoff: u64) -> Result<u64> {
+        let result = outbox::get_collection()
+            .delete_many(doc! {
+                "$or": [
+                    { "status": "SENT", "sent_at": { "$lt": cutoff as i64 } },
+                    { "status": "FAILED", "created_at": { "$lt": cutoff as i64 } },
+                ]
+            })
+            .await?;
+        Ok(result.deleted_count)
+    }
+}
+
+#[async_trait]
+impl FileStore for MongoStore {
+    #[instrument(name = "db.files.find", skip_all)]
+    async fn find(&self, id: &str) -> Result<Option<File>> {
+        Ok(files::get_collection()
+            .find_one(doc! {
+                "id": id,
+                "deleted": false,
+                "flagged": false
+            })
+            .await?)
+    }
+
+    #[instrument(name = "db.files.set_attached", skip_all)]
+    async fn set_attached(&self, id: &str, attached: bool) -> Result<()> {
+        files::get_collection()
+            .update_one(
+                doc! {
+                    "id": id,
+                    "deleted": false,
+                    "flagged": false,
+                },
+                doc! { "$set": { "attached": attached } },
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChallengeStore for MongoStore {
+    #[instrument(name = "db.used_challenges.use_once", skip_all)]
+    async fn use_once(&self, id: &str, expires_at: u64) -> Result<bool> {
+        let challenge = UsedChallenge {
+            id: id.to_string(),
+            expires_at: DateTime::from_millis(expires_at as i64 * 1000),
+        };
+        match used_challenge::get_collection().insert_one(challenge).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
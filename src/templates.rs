@@ -0,0 +1,227 @@
+// Email templates
+//
+// Built-in English templates are compiled into the binary. Operators can
+// override or add locales by pointing EMAIL_TEMPLATES_DIR at a directory
+// laid out as `<locale>/<template>.{subject,txt,html}`, plus an optional
+// `<locale>/layout.html` which wraps every HTML body.
+
+use std::{collections::HashMap, fs};
+
+use actix_web::HttpRequest;
+use lazy_static::lazy_static;
+use log::info;
+
+use crate::environment::{EMAIL_TEMPLATES_DIR, PUBLIC_ROOT, SERVICE_NAME};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+macro_rules! builtin {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_str!(concat!("../templates/en/", $name)))),*]
+    };
+}
+
+const BUILTIN_TEMPLATES: &[(&str, &str)] = builtin![
+    "layout.html",
+    "reset_password.subject",
+    "reset_password.txt",
+    "reset_password.html",
+    "verify_email.subject",
+    "verify_email.txt",
+    "verify_email.html",
+    "email_in_use.subject",
+    "email_in_use.txt",
+    "email_in_use.html",
+    "password_changed.subject",
+    "password_changed.txt",
+    "password_changed.html",
+    "password_reset.subject",
+    "password_reset.txt",
+    "password_reset.html",
+    "password_reset_requested.subject",
+    "password_reset_requested.txt",
+    "password_reset_requested.html",
+    "mfa_disabled.subject",
+    "mfa_disabled.txt",
+    "mfa_disabled.html",
+    "passkey_added.subject",
+    "passkey_added.txt",
+    "passkey_added.html",
+    "username_changed.subject",
+    "username_changed.txt",
+    "username_changed.html",
+    "new_device.subject",
+    "new_device.txt",
+    "new_device.html",
+];
+
+lazy_static! {
+    // locale -> file name -> contents
+    static ref TEMPLATES: HashMap<String, HashMap<String, String>> = load_templates();
+}
+
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+pub fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+fn load_templates() -> HashMap<String, HashMap<String, String>> {
+    let mut templates: HashMap<String, HashMap<String, String>> = HashMap::new();
+    templates.insert(
+        DEFAULT_LOCALE.to_string(),
+        BUILTIN_TEMPLATES
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect(),
+    );
+    let Some(dir) = &*EMAIL_TEMPLATES_DIR else {
+        return templates;
+    };
+    let locales = fs::read_dir(dir).expect("Failed to read EMAIL_TEMPLATES_DIR");
+    for locale in locales.flatten() {
+        if !locale.path().is_dir() {
+            continue;
+        }
+        let files = templates
+            .entry(normalize_locale(&locale.file_name().to_string_lossy()))
+            .or_default();
+        let entries = fs::read_dir(locale.path()).expect("Failed to read template directory");
+        for file in entries.flatten() {
+            if !file.path().is_file() {
+                continue;
+            }
+            let content = fs::read_to_string(file.path()).expect("Failed to read template");
+            files.insert(file.file_name().to_string_lossy().to_string(), content);
+        }
+    }
+    templates
+}
+
+pub fn load() {
+    info!("Loaded email templates for {} locale(s)", TEMPLATES.len());
+}
+
+pub fn is_supported(locale: &str) -> bool {
+    TEMPLATES.contains_key(&normalize_locale(locale))
+}
+
+// Picks the user's saved locale if there is one, otherwise the best match from Accept-Language
+pub fn negotiate_locale(req: &HttpRequest, preferred: Option<&str>) -> String {
+    if let Some(preferred) = preferred {
+        if is_supported(preferred) {
+            return normalize_locale(preferred);
+        }
+    }
+    let Some(header) = req
+        .headers()
+        .get("Accept-Language")
+        .and_then(|h| h.to_str().ok())
+    else {
+        return DEFAULT_LOCALE.to_string();
+    };
+    let mut candidates = header
+        .split(',')
+        .filter_map(|part| {
+            let mut parts = part.split(';');
+            let tag = normalize_locale(parts.next()?);
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((tag, quality))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (tag, _) in candidates {
+        if TEMPLATES.contains_key(&tag) {
+            return tag;
+        }
+        if let Some((primary, _)) = tag.split_once('-') {
+            if TEMPLATES.contains_key(primary) {
+                return primary.to_string();
+            }
+        }
+    }
+    DEFAULT_LOCALE.to_string()
+}
+
+fn get_template(locale: &str, file: &str) -> &'static str {
+    TEMPLATES
+        .get(locale)
+        .and_then(|files| files.get(file))
+        .or_else(|| {
+            TEMPLATES
+                .get(DEFAULT_LOCALE)
+                .and_then(|files| files.get(file))
+        })
+        .map(|content| content.as_str())
+        .unwrap_or_default()
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Replaces `{{name}}` placeholders, leaving unknown ones untouched
+fn substitute(template: &str, variables: &[(&str, String)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        match variables.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+pub fn render(name: &str, locale: &str, variables: &[(&str, String)]) -> Email {
+    let locale = normalize_locale(locale);
+    let mut variables = variables.to_vec();
+    variables.push(("service_name", SERVICE_NAME.to_string()));
+    variables.push(("public_root", PUBLIC_ROOT.to_string()));
+    let subject = substitute(
+        get_template(&locale, &format!("{}.subject", name)),
+        &variables,
+    )
+    .trim()
+    .to_string();
+    let text = substitute(get_template(&locale, &format!("{}.txt", name)), &variables);
+    variables.push(("subject", subject.clone()));
+    let mut escaped = variables
+        .into_iter()
+        .map(|(key, value)| (key, escape_html(&value)))
+        .collect::<Vec<_>>();
+    let content = substitute(get_template(&locale, &format!("{}.html", name)), &escaped);
+    escaped.push(("content", content));
+    let html = substitute(get_template(&locale, "layout.html"), &escaped);
+    Email {
+        subject,
+        text,
+        html,
+    }
+}
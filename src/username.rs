@@ -0,0 +1,92 @@
+// Username policy: reserved names, confusable-aware uniqueness and change history
+
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+
+use crate::{
+    environment::RESERVED_USERNAMES,
+    errors::{Error, Result},
+    store::UserStore,
+    utilities::{get_time_secs, USERNAME_RE},
+};
+
+const BUILTIN_RESERVED: &[&str] = &[
+    "admin",
+    "administrator",
+    "root",
+    "system",
+    "support",
+    "help",
+    "security",
+    "abuse",
+    "postmaster",
+    "webmaster",
+    "hostmaster",
+    "noreply",
+    "moderator",
+    "staff",
+    "official",
+    "api",
+    "www",
+    "null",
+    "undefined",
+];
+
+lazy_static! {
+    static ref RESERVED_KEYS: HashSet<String> = BUILTIN_RESERVED
+        .iter()
+        .map(|name| name.to_string())
+        .chain(RESERVED_USERNAMES.iter().cloned())
+        .map(|name| normalize_key(&name))
+        .collect();
+}
+
+// Folds case and look-alike characters, so that names which render the same
+// (`admin`, `Admin`, `adrnin`, `adm1n`) share a key. `USERNAME_RE` only lets ASCII through
+pub fn normalize_key(username: &str) -> String {
+    let mut key = String::with_capacity(username.len());
+    for c in username.trim().chars().map(|c| c.to_ascii_lowercase()) {
+        match c {
+            '0' => key.push('o'),
+            '1' | 'i' => key.push('l'),
+            'm' => key.push_str("rn"),
+            'w' => key.push_str("vv"),
+            '.' | '-' | '_' => key.push('_'),
+            c => key.push(c),
+        }
+    }
+    key
+}
+
+// Returns the trimmed username and its key
+pub fn validate(username: &str) -> Result<(String, String)> {
+    let username = username.trim();
+    if !USERNAME_RE.is_match(username) {
+        return Err(Error::InvalidUsername);
+    }
+    let key = normalize_key(username);
+    if RESERVED_KEYS.contains(&key) {
+        return Err(Error::UsernameReserved);
+    }
+    Ok((username.to_string(), key))
+}
+
+// `user_id` is the user taking the name, who may reclaim their own old names
+pub async fn ensure_available(
+    users: &dyn UserStore,
+    key: &str,
+    user_id: Option<&str>,
+) -> Result<()> {
+    if users.is_username_taken(key, user_id).await? {
+        return Err(Error::UsernameAlreadyTaken);
+    }
+    if users
+        .find_username_history(key, Some(get_time_secs()), user_id)
+        .await?
+        .is_some()
+    {
+        return Err(Error::UsernameAlreadyTaken);
+    }
+    Ok(())
+}
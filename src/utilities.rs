@@ -6,25 +6,24 @@ use actix_extensible_rate_limit::{
     },
     HeaderCompatibleOutput, RateLimiter,
 };
-use actix_web::{dev::ServiceRequest, HttpResponse};
+use actix_web::{dev::ServiceRequest, web::Data, HttpRequest, HttpResponse};
 use aes_gcm::{aead::Aead, Aes256Gcm, Nonce};
 use lazy_static::lazy_static;
-use lettre::{
-    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
-    AsyncStd1Executor, AsyncTransport, Message,
-};
-use mongodb::bson::doc;
 use rand::{distributions::Alphanumeric, rngs::StdRng, thread_rng, Rng, SeedableRng};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
 
 use crate::{
-    database::session,
-    environment::{
-        HCAPTCHA_SECRET, PUBLIC_ROOT, SMTP_FROM, SMTP_PASSWORD, SMTP_SERVER, SMTP_USERNAME,
-    },
+    config::RateLimit,
+    constants::{CONTINUE_TIMEOUT, VERIFY_TIMEOUT},
+    database::user,
+    environment::{ESCALATION_TIMEOUT, PUBLIC_ROOT, SERVICE_NAME},
     errors::Error,
-    routes::login,
+    mail, metrics,
+    routes::{forgot, login, update_password},
+    store::{OutboxStore, PasskeyStore, SessionStore, UserStore},
+    templates::{self, Email},
 };
 
 lazy_static! {
@@ -32,9 +31,12 @@ lazy_static! {
     pub static ref EMAIL_RE: Regex = Regex::new(r#"^(?:[a-z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*|"(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21\x23-\x5b\x5d-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])*")@(?:(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z0-9](?:[a-z0-9-]*[a-z0-9])?|\[(?:(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9]))\.){3}(?:(2(5[0-5]|[0-4][0-9])|1[0-9][0-9]|[1-9]?[0-9])|[a-z0-9-]*[a-z0-9]:(?:[\x01-\x08\x0b\x0c\x0e-\x1f\x21-\x5a\x53-\x7f]|\\[\x01-\x09\x0b\x0c\x0e-\x7f])+)\])$"#).expect("Unexpected error: failed to process regex");
 }
 
+// AES-GCM takes a 96-bit nonce, stored in front of the ciphertext
+const NONCE_SIZE: usize = 12;
+
 pub fn encrypt(buffer: Vec<u8>, encrypt: Aes256Gcm) -> Vec<u8> {
     let mut rng = StdRng::from_entropy();
-    let mut nonce_bytes: Vec<u8> = vec![0; 96];
+    let mut nonce_bytes: Vec<u8> = vec![0; NONCE_SIZE];
     rng.fill(&mut nonce_bytes[..]);
     let nonce = Nonce::from_slice(&nonce_bytes);
     let mut encrypted = encrypt.encrypt(nonce, buffer.as_slice()).unwrap();
@@ -44,14 +46,17 @@ pub fn encrypt(buffer: Vec<u8>, encrypt: Aes256Gcm) -> Vec<u8> {
     result
 }
 
-pub fn decrypt(mut buffer: Vec<u8>, encrypt: Option<Aes256Gcm>) -> Vec<u8> {
+// None if the buffer was tampered with or sealed under another key
+pub fn decrypt(mut buffer: Vec<u8>, encrypt: Option<Aes256Gcm>) -> Option<Vec<u8>> {
     if let Some(e) = encrypt {
-        let data = buffer.split_off(96);
+        if buffer.len() < NONCE_SIZE {
+            return None;
+        }
+        let data = buffer.split_off(NONCE_SIZE);
         let nonce = Nonce::from_slice(&buffer);
-        let decrypted = e.decrypt(nonce, data.as_slice()).unwrap();
-        decrypted
+        e.decrypt(nonce, data.as_slice()).ok()
     } else {
-        buffer
+        Some(buffer)
     }
 }
 
@@ -74,19 +79,20 @@ pub fn generate_codes() -> Vec<String> {
 }
 
 pub fn create_rate_limiter(
-    interval: Duration,
-    max_requests: u64,
+    name: &'static str,
+    limit: &RateLimit,
+    backend: InMemoryBackend,
 ) -> RateLimiter<
     InMemoryBackend,
     SimpleOutput,
     impl Fn(&ServiceRequest) -> SimpleInputFuture + 'static,
 > {
-    let backend = InMemoryBackend::builder().build();
-    let input = SimpleInputFunctionBuilder::new(interval, max_requests)
+    let input = SimpleInputFunctionBuilder::new(limit.interval(), limit.requests)
         .real_ip_key()
         .build();
     RateLimiter::builder(backend, input)
-        .request_denied_response(|o| {
+        .request_denied_response(move |o| {
+            metrics::rate_limited(name);
             HttpResponse::from_error(Error::RateLimited {
                 remaining: o.remaining,
                 reset: o.seconds_until_reset(),
@@ -98,20 +104,21 @@ pub fn create_rate_limiter(
 }
 
 pub fn create_success_rate_limiter(
-    interval: Duration,
-    max_requests: u64,
+    name: &'static str,
+    limit: &RateLimit,
+    backend: InMemoryBackend,
 ) -> RateLimiter<
     InMemoryBackend,
     SimpleOutput,
     impl Fn(&ServiceRequest) -> SimpleInputFuture + 'static,
 > {
-    let backend = InMemoryBackend::builder().build();
-    let input = SimpleInputFunctionBuilder::new(interval, max_requests)
+    let input = SimpleInputFunctionBuilder::new(limit.interval(), limit.requests)
         .real_ip_key()
         .build();
     RateLimiter::builder(backend, input)
         .fail_open(true)
-        .request_denied_response(|o| {
+        .request_denied_response(move |o| {
+            metrics::rate_limited(name);
             HttpResponse::from_error(Error::RateLimited {
                 remaining: o.remaining,
                 reset: o.seconds_until_reset(),
@@ -122,6 +129,11 @@ pub fn create_success_rate_limiter(
         .build()
 }
 
+// Hashing first keeps the comparison from leaking how much of the secret matched
+pub fn secret_matches(given: &str, expected: &str) -> bool {
+    Sha256::digest(given.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
 pub fn generate_continue_token_long() -> String {
     thread_rng()
         .sample_iter(&Alphanumeric)
@@ -130,86 +142,87 @@ pub fn generate_continue_token_long() -> String {
         .collect()
 }
 
-pub async fn send_email(to: String, subject: String, body: String) -> crate::errors::Result<()> {
-    let Some(from) = &*SMTP_FROM else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(username) = &*SMTP_USERNAME else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(password) = &*SMTP_PASSWORD else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let Some(server) = &*SMTP_SERVER else {
-        return Err(Error::EmailMisconfigured);
-    };
-    let email = Message::builder()
-        .from(from.parse().map_err(|_| Error::EmailMisconfigured)?)
-        .to(to.parse().map_err(|_| Error::InternalEmailError)?)
-        .subject(subject)
-        .header(ContentType::TEXT_PLAIN)
-        .body(body)
-        .map_err(|_| Error::EmailMisconfigured)?;
-    let creds = Credentials::new(username.to_string(), password.to_string());
-    let mailer = AsyncSmtpTransport::<AsyncStd1Executor>::relay(server)
-        .expect("failed to set server")
-        .credentials(creds)
-        .build();
-    mailer
-        .send(email)
-        .await
-        .map_err(|_| Error::InternalEmailError)?;
-    Ok(())
+// short enough to be typed in by hand
+pub fn generate_invite_code() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
 }
 
-pub async fn send_reset_email(to: String, token: String) -> crate::errors::Result<()> {
-    let continue_url = format!("{}/forgot?token={}", &*PUBLIC_ROOT, token);
-    send_email(to, "Reset password".to_string(), format!("Hi there! We received a request to reset your password. If this was you, please click the following link to continue.\n\n{}", continue_url)).await
+// An API key with `prefix`, 40 random characters after it
+pub fn generate_api_key(prefix: &str) -> String {
+    let key: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    format!("{}{}", prefix, key)
 }
 
-pub async fn send_verify_email(to: String, token: String) -> crate::errors::Result<()> {
-    send_email(to, "Verify email".to_string(), format!("Hi there! We received a request to create an account. If this was you, please enter the following token to continue.\n\n{}", token)).await
+// Keys are random enough that a fast hash is as good as a password hash
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
 }
 
-pub async fn send_in_use_email(to: String) -> crate::errors::Result<()> {
-    send_email(to, "Verify email".to_string(), "Hi there! We received a request to create an account. However, this email is already in use. If this was you, please reset your password instead.".to_string()).await
+// Queues the email in the outbox, delivery and retries happen in the background
+pub async fn send_email(req: &HttpRequest, to: String, email: Email) -> crate::errors::Result<()> {
+    if !mail::is_enabled() {
+        return Err(Error::EmailMisconfigured);
+    }
+    let outbox = req
+        .app_data::<Data<dyn OutboxStore>>()
+        .expect("Unexpected error: outbox store not configured");
+    mail::outbox::enqueue(outbox.get_ref(), to, email).await
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct HCaptchaResponse {
-    success: bool,
-    challenge_ts: Option<String>,
-    hostname: Option<String>,
-    credit: Option<bool>,
-    error_codes: Option<Vec<String>>,
+pub async fn send_reset_email(
+    req: &HttpRequest,
+    to: String,
+    token: String,
+    locale: String,
+) -> crate::errors::Result<()> {
+    let continue_url = format!("{}/forgot?token={}", &*PUBLIC_ROOT, token);
+    let email = templates::render(
+        "reset_password",
+        &locale,
+        &[
+            ("url", continue_url),
+            ("expiry_minutes", (CONTINUE_TIMEOUT / 60).to_string()),
+        ],
+    );
+    send_email(req, to, email).await
 }
 
-pub async fn validate_captcha(token: String) -> crate::errors::Result<()> {
-    let client = reqwest::Client::new();
-    let result = client
-        .post("https://hcaptcha.com/siteverify")
-        .query(&[("response", token), ("secret", HCAPTCHA_SECRET.to_string())])
-        .send()
-        .await;
-    let Ok(result) = result else {
-        return Err(Error::InternalCaptchaError);
-    };
-    if result.status() != reqwest::StatusCode::OK {
-        return Err(Error::InternalCaptchaError);
-    }
-    let text = result
-        .text()
-        .await
-        .expect("Unexpected error: failed to read response");
-    let response: HCaptchaResponse = serde_json::from_str(&text)
-        .expect("Unexpected error: failed to convert response into JSON");
-    if !response.success {
-        return Err(Error::InvalidCaptcha);
-    }
-    Ok(())
+pub async fn send_verify_email(
+    req: &HttpRequest,
+    to: String,
+    token: String,
+    locale: String,
+) -> crate::errors::Result<()> {
+    let email = templates::render(
+        "verify_email",
+        &locale,
+        &[
+            ("token", token),
+            ("expiry_minutes", (VERIFY_TIMEOUT / 60).to_string()),
+        ],
+    );
+    send_email(req, to, email).await
+}
+
+pub async fn send_in_use_email(
+    req: &HttpRequest,
+    to: String,
+    locale: String,
+) -> crate::errors::Result<()> {
+    let email = templates::render("email_in_use", &locale, &[]);
+    send_email(req, to, email).await
 }
 
 pub async fn validate_escalation(
+    sessions: &dyn SessionStore,
     escalation_token: String,
     token: String,
 ) -> crate::errors::Result<String> {
@@ -217,29 +230,87 @@ pub async fn validate_escalation(
     let Some(escalate) = escalate else {
         return Err(Error::SessionExpired);
     };
-    if get_time_secs() - escalate.time > 3600 {
+    if get_time_secs() - escalate.time > *ESCALATION_TIMEOUT {
         drop(escalate);
         login::ACTIVE_ESCALATIONS.remove(&escalation_token);
         return Err(Error::SessionExpired);
     }
 
-    let sessions = session::get_collection();
-    let session = sessions
-        .find_one(doc! {"id": escalate.session_id.clone()})
-        .await?;
+    let session_id = escalate.session_id.clone();
+    let user_id = escalate.user_id.clone();
+    drop(escalate);
+    let session = sessions.find(&session_id).await?;
     if session.is_none() {
         return Err(Error::SessionExpired);
     }
 
-    let user_session = sessions.find_one(doc! { "token": token }).await?;
+    let user_session = sessions.find_by_token(&token).await?;
     if user_session.is_none() {
         return Err(Error::SessionExpired);
     }
-    if user_session.unwrap().id != escalate.session_id {
+    if user_session.unwrap().id != session_id {
         return Err(Error::SessionExpired);
     }
 
-    Ok(escalate.user_id.clone())
+    Ok(user_id)
+}
+
+pub async fn validate_administrator(
+    users: &dyn UserStore,
+    user_id: &str,
+) -> crate::errors::Result<user::User> {
+    let user = users.find(user_id).await?.ok_or(Error::UserNotFound)?;
+    if !user.platform_administrator {
+        return Err(Error::MissingPermission);
+    }
+    Ok(user)
+}
+
+pub fn verify_totp(secret: &str, email: &str, code: &str) -> bool {
+    let Ok(secret) = Secret::Encoded(secret.to_string()).to_bytes() else {
+        return false;
+    };
+    let Ok(totp) = TOTP::new(
+        Algorithm::SHA256,
+        8,
+        1,
+        30,
+        secret,
+        Some(SERVICE_NAME.to_string()),
+        email.to_string(),
+    ) else {
+        return false;
+    };
+    totp.generate_current()
+        .map(|current| current == code)
+        .unwrap_or(false)
+}
+
+// A TOTP secret or any passkey counts as a second factor
+pub async fn has_second_factor(
+    passkeys: &dyn PasskeyStore,
+    user: &user::User,
+) -> crate::errors::Result<bool> {
+    if user.mfa_enabled {
+        return Ok(true);
+    }
+    Ok(!passkeys.list(&user.id).await?.is_empty())
+}
+
+// The in-memory half of revoking sessions, for stores that already deleted them
+pub fn clear_pending_state(user_id: &str) {
+    login::ACTIVE_ESCALATIONS.retain(|_, e| e.user_id != user_id);
+    login::PENDING_LOGINS.retain(|_, p| p.user.id != user_id);
+    login::PENDING_MFAS.retain(|_, p| p.user.id != user_id);
+    clear_password_flows(user_id);
+}
+
+// Every flow that ends in choosing a password, once the password has changed
+pub fn clear_password_flows(user_id: &str) {
+    login::PENDING_UPGRADES.retain(|_, u| u.user_id != user_id);
+    update_password::PENDING_UPDATES.retain(|_, p| p.user_id != user_id);
+    forgot::PENDING_FORGOTS1.retain(|_, p| p.user_id != user_id);
+    forgot::PENDING_FORGOTS2.retain(|_, p| p.user_id != user_id);
 }
 
 pub fn get_time() -> Duration {
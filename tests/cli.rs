@@ -0,0 +1,121 @@
+mod common;
+
+use account_services::{
+    cli::{self, USAGE},
+    errors::Error,
+    store::Stores,
+};
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[actix_web::test]
+async fn unknown_commands_print_the_usage() {
+    let stores = Stores::memory();
+    assert_eq!(cli::run(&stores, &args("nope")).await.unwrap_err(), USAGE);
+    assert_eq!(cli::run(&stores, &args("users")).await.unwrap_err(), USAGE);
+    assert_eq!(
+        cli::run(&stores, &args("users list --limit many"))
+            .await
+            .unwrap_err(),
+        "--limit expects a number, got many"
+    );
+    assert_eq!(cli::failure("oops".to_string())["error"], "oops");
+}
+
+#[actix_web::test]
+async fn rotated_keys_keep_the_current_secret_as_a_previous_one() {
+    common::init();
+    let output = cli::run(&Stores::memory(), &args("rotate-keys"))
+        .await
+        .unwrap();
+    assert_eq!(output["jwtSecret"].as_str().unwrap().len(), 96);
+    assert_eq!(
+        output["jwtPreviousSecrets"],
+        format!("{},{}", common::JWT_SECRET, common::PREVIOUS_JWT_SECRET)
+    );
+    assert_eq!(output["keyId"].as_str().unwrap().len(), 16);
+}
+
+#[actix_web::test]
+async fn promoted_users_reach_the_admin_routes() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app
+        .get("/api/admin/registrations", Some(&account.token))
+        .await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+
+    let output = cli::run(
+        &app.stores,
+        &args(&format!("users promote {}", account.email)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output["changed"], true);
+    assert_eq!(output["user"]["platformAdministrator"], true);
+    app.get("/api/admin/registrations", Some(&account.token))
+        .await
+        .ok();
+
+    let output = cli::run(
+        &app.stores,
+        &args(&format!("users demote {}", account.username)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output["user"]["id"], account.id.as_str());
+    assert_eq!(output["user"]["platformAdministrator"], false);
+}
+
+#[actix_web::test]
+async fn revoked_sessions_sign_the_user_out() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let output = cli::run(
+        &app.stores,
+        &args(&format!("users revoke-sessions {}", account.id)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(output["changed"], true);
+    let res = app.get("/api/user", Some(&account.token)).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+
+    let token = app.login(&account).await;
+    let events = app
+        .get("/api/user/security-events", Some(&token))
+        .await
+        .ok();
+    assert!(events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|event| event["kind"] == "ADMIN_SESSIONS_REVOKED"));
+}
+
+#[actix_web::test]
+async fn users_are_listed_in_pages() {
+    let app = common::app().await;
+    let first = app.register().await;
+    let second = app.register().await;
+    let page = cli::run(&app.stores, &args("users list --limit 1"))
+        .await
+        .unwrap();
+    assert_eq!(page.as_array().unwrap().len(), 1);
+    let after = page[0]["id"].as_str().unwrap();
+    let rest = cli::run(&app.stores, &args(&format!("users list --after {}", after)))
+        .await
+        .unwrap();
+    let ids: Vec<&str> = rest
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["id"].as_str().unwrap())
+        .collect();
+    assert!(!ids.contains(&after));
+    assert!([first.id.as_str(), second.id.as_str()]
+        .iter()
+        .any(|id| ids.contains(id)));
+}
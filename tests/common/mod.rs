@@ -0,0 +1,587 @@
+// Shared harness for the end-to-end tests. The app is built exactly like `main` builds it,
+// on top of the in-memory stores, so no database is needed.
+#![allow(dead_code)]
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Once,
+};
+
+use account_services::{
+    errors::Error,
+    opaque::{Argon2id, PasswordSuite},
+    routes::{self, forgot::PENDING_FORGOTS1, register::PENDING_REGISTERS1},
+    store::Stores,
+};
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::{header::HeaderMap, Method, StatusCode},
+    test, App,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use opaque_ke::{
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialResponse, Identifiers, RegistrationResponse,
+};
+use rand::rngs::OsRng;
+use serde_json::{json, Value};
+use totp_rs::{Algorithm, Secret, TOTP};
+use ulid::Ulid;
+use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+use webauthn_rs::prelude::Url;
+
+pub const SERVICE_NAME: &str = "Nextflow Test";
+pub const PUBLIC_ROOT: &str = "https://localhost";
+pub const INTROSPECTION_CLIENT: (&str, &str) =
+    ("resource-server", "a-resource-server-secret-for-tests");
+pub const JWT_SECRET: &str = "an-insecure-secret-for-tests-only";
+// still accepted, as if `rotate-keys` had replaced it
+pub const PREVIOUS_JWT_SECRET: &str = "a-previous-secret-for-tests-only-32";
+pub const METRICS_TOKEN: &str = "a-metrics-token-for-tests-only-32";
+
+static INIT: Once = Once::new();
+
+// Configures the environment once per test binary
+pub fn init() {
+    INIT.call_once(|| {
+        std::env::set_var("STORAGE_BACKEND", "memory");
+        std::env::set_var("HOST", "127.0.0.1:0");
+        std::env::set_var("CORS_ORIGINS", PUBLIC_ROOT);
+        std::env::set_var("JWT_SECRET", JWT_SECRET);
+        std::env::set_var("JWT_PREVIOUS_SECRETS", PREVIOUS_JWT_SECRET);
+        std::env::set_var("METRICS_TOKEN", METRICS_TOKEN);
+        std::env::set_var("CAPTCHA_PROVIDER", "test");
+        std::env::set_var("MAIL_TRANSPORT", "log");
+        std::env::set_var("PUBLIC_ROOT", PUBLIC_ROOT);
+        std::env::set_var("RP_ID", "localhost");
+        std::env::set_var("SERVICE_NAME", SERVICE_NAME);
+        std::env::set_var(
+            "INTROSPECTION_CLIENTS",
+            format!("{}:{}", INTROSPECTION_CLIENT.0, INTROSPECTION_CLIENT.1),
+        );
+        // unoptimized Argon2 with the production parameters would dominate the runtime
+        std::env::set_var("OPAQUE_MASTER_KEY", "00".repeat(32));
+        std::env::set_var("OPAQUE_ARGON2_MEMORY", "1024");
+        std::env::set_var("OPAQUE_ARGON2_ITERATIONS", "1");
+    })
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl Response {
+    pub fn ok(self) -> Value {
+        assert!(
+            self.status.is_success(),
+            "expected success, got {}: {}",
+            self.status,
+            self.body
+        );
+        self.body
+    }
+
+    pub fn error(self) -> Error {
+        assert!(
+            !self.status.is_success(),
+            "expected an error, got {}: {}",
+            self.status,
+            self.body
+        );
+        serde_json::from_value(self.body.clone())
+            .unwrap_or_else(|_| panic!("not an error response: {}", self.body))
+    }
+}
+
+pub struct Account {
+    pub id: String,
+    pub email: String,
+    pub username: String,
+    pub password: String,
+    pub token: String,
+}
+
+pub struct TestApp<S> {
+    service: S,
+    pub stores: Stores,
+}
+
+pub async fn app() -> TestApp<
+    impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
+> {
+    init();
+    let stores = Stores::memory();
+    let service = test::init_service(
+        App::new()
+            .configure(|cfg| stores.configure(cfg))
+            .configure(|cfg| routes::configure(cfg, &routes::RouteState::default())),
+    )
+    .await;
+    TestApp { service, stores }
+}
+
+// Every request comes from its own address so that rate limits stay out of the way
+fn next_address() -> String {
+    static NEXT: AtomicU32 = AtomicU32::new(1);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "10.{}.{}.{}:4000",
+        (n >> 16) & 0xff,
+        (n >> 8) & 0xff,
+        n & 0xff
+    )
+}
+
+impl<S, B> TestApp<S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> Response {
+        let mut req = test::TestRequest::default().method(method).uri(path);
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        if let Some(body) = body {
+            req = req.set_json(body);
+        }
+        self.send(req).await
+    }
+
+    // For requests that aren't JSON or authenticate differently
+    pub async fn send(&self, req: test::TestRequest) -> Response {
+        let req = req.peer_addr(next_address().parse().unwrap());
+        let res = test::call_service(&self.service, req.to_request()).await;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let bytes = test::read_body(res).await;
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or(Value::String(String::from_utf8_lossy(&bytes).to_string()))
+        };
+        Response {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn get(&self, path: &str, token: Option<&str>) -> Response {
+        self.request(Method::GET, path, token, None).await
+    }
+
+    pub async fn post(&self, path: &str, token: Option<&str>, body: Value) -> Response {
+        self.request(Method::POST, path, token, Some(body)).await
+    }
+
+    pub async fn patch(&self, path: &str, token: Option<&str>, body: Value) -> Response {
+        self.request(Method::PATCH, path, token, Some(body)).await
+    }
+
+    pub async fn delete(&self, path: &str, token: Option<&str>, body: Option<Value>) -> Response {
+        self.request(Method::DELETE, path, token, body).await
+    }
+
+    // Registers a fresh account through all three stages
+    pub async fn register(&self) -> Account {
+        let id = Ulid::new().to_string().to_lowercase();
+        let email = format!("{}@example.com", id);
+        let username = format!("user_{}", id);
+        let password = format!("correct horse {}", id);
+        let res = self.register_with(&email, &username, &password).await.ok();
+        let token = res["token"].as_str().unwrap().to_string();
+        let user = self.get("/api/user", Some(&token)).await.ok();
+        Account {
+            id: user["id"].as_str().unwrap().to_string(),
+            email,
+            username,
+            password,
+            token,
+        }
+    }
+
+    pub async fn register_with(&self, email: &str, username: &str, password: &str) -> Response {
+        self.post(
+            "/api/user",
+            None,
+            json!({
+                "stage": "VERIFY_EMAIL",
+                "email": email,
+                "captchaToken": "pass",
+            }),
+        )
+        .await
+        .ok();
+        let email_token = email_token(email).expect("no verification email was sent");
+        let (message, state) = start_registration(password);
+        let res = self
+            .post(
+                "/api/user",
+                None,
+                json!({
+                    "stage": "BEGIN_REGISTRATION",
+                    "emailToken": email_token,
+                    "message": message,
+                }),
+            )
+            .await
+            .ok();
+        let message = finish_registration(state, password, res["message"].as_str().unwrap());
+        self.post(
+            "/api/user",
+            None,
+            json!({
+                "stage": "REGISTER",
+                "continueToken": res["continueToken"],
+                "message": message,
+                "username": username,
+                "displayName": "Test User",
+                "friendlyName": "Tests",
+            }),
+        )
+        .await
+    }
+
+    // Runs BEGIN_LOGIN and FINISH_LOGIN, returning the response to the second stage.
+    // A wrong password fails on the client, which can't open its envelope.
+    pub async fn login_with(
+        &self,
+        email: &str,
+        password: &str,
+        escalate: Option<&str>,
+    ) -> Option<Response> {
+        let (message, state) = start_login(password);
+        let res = self
+            .post(
+                "/api/session",
+                None,
+                json!({
+                    "stage": "BEGIN_LOGIN",
+                    "email": email,
+                    "message": message,
+                    "escalate": escalate.is_some(),
+                    "token": escalate,
+                }),
+            )
+            .await
+            .ok();
+        let message = finish_login(state, password, res["message"].as_str().unwrap())?;
+        Some(
+            self.post(
+                "/api/session",
+                None,
+                json!({
+                    "stage": "FINISH_LOGIN",
+                    "message": message,
+                    "continueToken": res["continueToken"],
+                    "friendlyName": "Tests",
+                }),
+            )
+            .await,
+        )
+    }
+
+    pub async fn login(&self, account: &Account) -> String {
+        let res = self
+            .login_with(&account.email, &account.password, None)
+            .await
+            .expect("wrong password")
+            .ok();
+        res["token"].as_str().unwrap().to_string()
+    }
+
+    // Registers the account's password again with the current suite, returning that suite
+    pub async fn upgrade(&self, account: &Account, upgrade_token: &str) -> Value {
+        let (message, state) = start_registration(&account.password);
+        let res = self
+            .post(
+                "/api/session",
+                None,
+                json!({
+                    "stage": "BEGIN_UPGRADE",
+                    "continueToken": upgrade_token,
+                    "message": message,
+                }),
+            )
+            .await
+            .ok();
+        let message =
+            finish_registration(state, &account.password, res["message"].as_str().unwrap());
+        self.post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "FINISH_UPGRADE",
+                "continueToken": upgrade_token,
+                "message": message,
+            }),
+        )
+        .await
+        .ok();
+        res["suite"].clone()
+    }
+
+    // Runs the forgot password flow for an account without a second factor
+    pub async fn reset_password(&self, email: &str, password: &str) {
+        self.post(
+            "/api/forgot",
+            None,
+            json!({ "stage": "VERIFY_EMAIL", "email": email }),
+        )
+        .await
+        .ok();
+        let continue_token = PENDING_FORGOTS1
+            .iter()
+            .find(|pending| pending.email.eq_ignore_ascii_case(email))
+            .map(|pending| pending.key().clone())
+            .expect("no reset email was sent");
+        let (message, state) = start_registration(password);
+        let res = self
+            .post(
+                "/api/forgot",
+                None,
+                json!({
+                    "stage": "RESET_PASSWORD",
+                    "continueToken": continue_token,
+                    "message": message,
+                }),
+            )
+            .await
+            .ok();
+        let message = finish_registration(state, password, res["message"].as_str().unwrap());
+        self.post(
+            "/api/forgot",
+            None,
+            json!({
+                "stage": "FINISH_RESET",
+                "continueToken": res["continueToken"],
+                "message": message,
+            }),
+        )
+        .await
+        .ok();
+    }
+
+    // Logs in again on top of the account's session, for routes that need a recent login
+    pub async fn escalate(&self, account: &Account) -> String {
+        let res = self
+            .login_with(&account.email, &account.password, Some(&account.token))
+            .await
+            .expect("wrong password")
+            .ok();
+        res["token"].as_str().unwrap().to_string()
+    }
+
+    // Starts enabling MFA, returning the secret and recovery codes
+    pub async fn begin_mfa_setup(&self, account: &Account) -> Value {
+        let escalation = self.escalate(account).await;
+        self.patch(
+            "/api/user/mfa",
+            Some(&account.token),
+            json!({ "stage": "TOGGLE", "escalationToken": escalation }),
+        )
+        .await
+        .ok()
+    }
+
+    pub async fn enable_mfa(&self, account: &Account) -> Value {
+        let setup = self.begin_mfa_setup(account).await;
+        let code = totp_code(setup["secret"].as_str().unwrap(), &account.username).await;
+        self.patch(
+            "/api/user/mfa",
+            Some(&account.token),
+            json!({
+                "stage": "ENABLE_VERIFY",
+                "code": code,
+                "continueToken": setup["continueToken"],
+            }),
+        )
+        .await
+        .ok();
+        setup
+    }
+
+    // Logs in with the password, then answers the MFA stage with the code
+    pub async fn login_mfa(&self, account: &Account, code: &str) -> Response {
+        let res = self
+            .login_with(&account.email, &account.password, None)
+            .await
+            .expect("wrong password")
+            .ok();
+        assert_eq!(res["mfaEnabled"], true);
+        assert!(res["token"].is_null());
+        self.post(
+            "/api/session",
+            None,
+            json!({ "stage": "MFA", "code": code, "continueToken": res["continueToken"] }),
+        )
+        .await
+    }
+
+    // Registers a passkey with the authenticator, returning its credential id
+    pub async fn add_passkey(
+        &self,
+        account: &Account,
+        authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+    ) -> String {
+        let escalation = self.escalate(account).await;
+        let res = self
+            .post(
+                "/api/user/passkeys",
+                Some(&account.token),
+                json!({ "stage": "BEGIN_REGISTER", "escalationToken": escalation }),
+            )
+            .await
+            .ok();
+        let credential = authenticator
+            .do_registration(
+                Url::parse(PUBLIC_ROOT).unwrap(),
+                serde_json::from_value(res["message"].clone()).unwrap(),
+            )
+            .expect("Failed to create a passkey");
+        let credential = serde_json::to_value(credential).unwrap();
+        self.post(
+            "/api/user/passkeys",
+            Some(&account.token),
+            json!({
+                "stage": "FINISH_REGISTER",
+                "message": credential,
+                "continueToken": res["continueToken"],
+                "friendlyName": "Test key",
+            }),
+        )
+        .await
+        .ok();
+        credential["id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn login_passkey(
+        &self,
+        authenticator: &mut WebauthnAuthenticator<SoftPasskey>,
+        credential_id: &str,
+    ) -> Response {
+        let res = self
+            .post(
+                "/api/session/passkeys",
+                None,
+                json!({ "stage": "BEGIN_LOGIN", "escalate": false }),
+            )
+            .await
+            .ok();
+        // the challenge is discoverable, so pick the credential like a browser would
+        let mut challenge = res["message"].clone();
+        challenge["publicKey"]["allowCredentials"] =
+            json!([{ "type": "public-key", "id": credential_id }]);
+        let credential = authenticator
+            .do_authentication(
+                Url::parse(PUBLIC_ROOT).unwrap(),
+                serde_json::from_value(challenge).unwrap(),
+            )
+            .expect("Failed to sign in with the passkey");
+        self.post(
+            "/api/session/passkeys",
+            None,
+            json!({
+                "stage": "FINISH_LOGIN",
+                "message": credential,
+                "continueToken": res["continueToken"],
+            }),
+        )
+        .await
+    }
+}
+
+pub fn email_token(email: &str) -> Option<String> {
+    PENDING_REGISTERS1
+        .iter()
+        .find(|pending| pending.email == email)
+        .map(|pending| pending.key().clone())
+}
+
+pub fn reset_token(email: &str) -> Option<String> {
+    PENDING_FORGOTS1
+        .iter()
+        .find(|pending| pending.email == email)
+        .map(|pending| pending.key().clone())
+}
+
+// The client side of the current suite
+fn ksf() -> argon2::Argon2<'static> {
+    PasswordSuite::current(0).ksf.unwrap().argon2()
+}
+
+pub fn start_registration(password: &str) -> (String, ClientRegistration<Argon2id>) {
+    let result = ClientRegistration::<Argon2id>::start(&mut OsRng, password.as_bytes())
+        .expect("Failed to start OPAQUE registration");
+    (BASE64.encode(result.message.serialize()), result.state)
+}
+
+pub fn finish_registration(
+    state: ClientRegistration<Argon2id>,
+    password: &str,
+    message: &str,
+) -> String {
+    let response = RegistrationResponse::deserialize(&BASE64.decode(message).unwrap())
+        .expect("Invalid registration response");
+    let ksf = ksf();
+    let result = state
+        .finish(
+            &mut OsRng,
+            password.as_bytes(),
+            response,
+            ClientRegistrationFinishParameters::new(Identifiers::default(), Some(&ksf)),
+        )
+        .expect("Failed to finish OPAQUE registration");
+    BASE64.encode(result.message.serialize())
+}
+
+pub fn start_login(password: &str) -> (String, ClientLogin<Argon2id>) {
+    let result = ClientLogin::<Argon2id>::start(&mut OsRng, password.as_bytes())
+        .expect("Failed to start OPAQUE login");
+    (BASE64.encode(result.message.serialize()), result.state)
+}
+
+pub fn finish_login(state: ClientLogin<Argon2id>, password: &str, message: &str) -> Option<String> {
+    let response = CredentialResponse::deserialize(&BASE64.decode(message).unwrap())
+        .expect("Invalid credential response");
+    let ksf = ksf();
+    let result = state
+        .finish(
+            password.as_bytes(),
+            response,
+            ClientLoginFinishParameters::new(None, Identifiers::default(), Some(&ksf)),
+        )
+        .ok()?;
+    Some(BASE64.encode(result.message.serialize()))
+}
+
+// The server only accepts the current code, so don't start right before the step rolls over
+pub async fn totp_code(secret: &str, account_name: &str) -> String {
+    let totp = TOTP::new(
+        Algorithm::SHA256,
+        8,
+        1,
+        30,
+        Secret::Encoded(secret.to_string()).to_bytes().unwrap(),
+        Some(SERVICE_NAME.to_string()),
+        account_name.to_string(),
+    )
+    .unwrap();
+    let into_step = account_services::utilities::get_time_secs() % 30;
+    if into_step > 27 {
+        actix_web::rt::time::sleep(std::time::Duration::from_secs(30 - into_step)).await;
+    }
+    totp.generate_current().unwrap()
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use account_services::config::Config;
+
+const FILE: &str = r#"
+[server]
+host = "0.0.0.0:8000"
+cors_origins = ["https://account.example.com"]
+public_root = "https://account.example.com"
+service_name = "Nextflow"
+rp_id = "example.com"
+
+[database]
+uri = "mongodb://localhost:27017"
+name = "account"
+cdn_name = "cdn"
+
+[security]
+jwt_secret = "a-secret-that-is-long-enough-for-jwt"
+opaque_master_key = "0000000000000000000000000000000000000000000000000000000000000000"
+
+[captcha]
+provider = "none"
+
+[rate_limits.login]
+requests = 3
+"#;
+
+fn parse(file: &str, vars: &[(&str, &str)]) -> Result<Config, Vec<String>> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Config::parse(file, &|name| vars.get(name).cloned())
+}
+
+#[test]
+fn file_values_and_defaults_are_used() {
+    let config = parse(FILE, &[]).unwrap();
+    assert_eq!(config.server.rp_id, "example.com");
+    assert_eq!(config.rate_limits.login.requests, 3);
+    assert_eq!(config.rate_limits.login.window, 20);
+    assert_eq!(config.sessions.lifetime, 604800);
+    assert_eq!(config.database.storage_backend, "mongodb");
+    assert!(config.smtp.is_none());
+}
+
+#[test]
+fn environment_overrides_the_file() {
+    let config = parse(
+        FILE,
+        &[
+            ("SERVICE_NAME", "Override"),
+            ("RATE_LIMIT_LOGIN_WINDOW", "60"),
+            (
+                "CORS_ORIGINS",
+                "https://a.example.com, https://b.example.com",
+            ),
+        ],
+    )
+    .unwrap();
+    assert_eq!(config.server.service_name, "Override");
+    assert_eq!(config.rate_limits.login.window, 60);
+    assert_eq!(config.server.cors_origins.len(), 2);
+}
+
+#[test]
+fn secrets_are_read_from_files() {
+    let path = std::env::temp_dir().join("account_services_jwt_secret");
+    std::fs::write(&path, "a-secret-read-from-a-docker-secret-file\n").unwrap();
+    let config = parse(FILE, &[("JWT_SECRET_FILE", path.to_str().unwrap())]).unwrap();
+    assert_eq!(
+        config.security.jwt_secret,
+        "a-secret-read-from-a-docker-secret-file"
+    );
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let errors = parse(
+        "",
+        &[
+            ("PUBLIC_ROOT", "https://account.example.com"),
+            ("RP_ID", "other.com"),
+            ("CAPTCHA_PROVIDER", "turnstile"),
+            ("SMTP_SERVER", "smtp.example.com"),
+            ("SESSION_LIFETIME", "forever"),
+            ("METRICS_TOKEN", "too-short"),
+        ],
+    )
+    .err()
+    .unwrap();
+    let has = |text: &str| errors.iter().any(|e| e.contains(text));
+    assert!(has("MONGODB_URI"));
+    assert!(has("JWT_SECRET"));
+    assert!(has("OPAQUE_MASTER_KEY"));
+    assert!(has("RP_ID (other.com)"));
+    assert!(has("CAPTCHA_SECRET"));
+    assert!(has("SMTP_FROM"));
+    assert!(has("SESSION_LIFETIME"));
+    assert!(has("METRICS_TOKEN"));
+}
+
+#[test]
+fn the_memory_backend_needs_no_database() {
+    let file = FILE.replace("[database]", "[unused]");
+    assert!(parse(&file, &[]).is_err());
+    let config = parse(&file, &[("STORAGE_BACKEND", "memory")]).unwrap();
+    assert_eq!(config.database.uri, "");
+}
+
+#[test]
+fn the_test_captcha_needs_the_memory_backend() {
+    let errors = parse(FILE, &[("CAPTCHA_PROVIDER", "test")]).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("STORAGE_BACKEND=memory"));
+    assert!(parse(
+        FILE,
+        &[("CAPTCHA_PROVIDER", "test"), ("STORAGE_BACKEND", "memory")]
+    )
+    .is_ok());
+}
+
+#[test]
+fn rp_id_may_be_a_parent_domain() {
+    assert!(parse(FILE, &[("RP_ID", "account.example.com")]).is_ok());
+    assert!(parse(FILE, &[("RP_ID", "ample.com")]).is_err());
+}
+
+#[test]
+fn invalid_choices_are_rejected() {
+    let errors = parse(FILE, &[("REGISTRATION_MODE", "sometimes")])
+        .err()
+        .unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("open, invite, closed"));
+}
+
+#[test]
+fn introspection_clients_need_long_unique_secrets() {
+    let config = parse(
+        FILE,
+        &[(
+            "INTROSPECTION_CLIENTS",
+            "chat:a-secret-that-is-long-enough-for-chat",
+        )],
+    )
+    .unwrap();
+    assert_eq!(config.introspection.clients[0].id, "chat");
+    assert_eq!(config.introspection.cache_seconds, 30);
+    let errors = parse(
+        FILE,
+        &[("INTROSPECTION_CLIENTS", "chat:short,chat:short,files")],
+    )
+    .err()
+    .unwrap();
+    assert_eq!(errors.len(), 4);
+    assert!(errors.iter().any(|e| e.contains("id:secret, got files")));
+    assert!(errors.iter().any(|e| e.contains("listed twice")));
+}
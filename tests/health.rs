@@ -0,0 +1,41 @@
+mod common;
+
+use account_services::errors::Error;
+use actix_web::http::StatusCode;
+
+#[actix_web::test]
+async fn liveness_does_not_need_the_server_setup() {
+    let app = common::app().await;
+    let res = app.get("/healthz", None).await.ok();
+    assert_eq!(res["status"], "ok");
+    let res = app.get("/readyz", None).await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.body["database"], true);
+    assert_eq!(res.body["settings"], false);
+}
+
+#[actix_web::test]
+async fn ready_once_the_server_setup_exists() {
+    let app = common::app().await;
+    app.register().await;
+    let res = app.get("/readyz", None).await.ok();
+    assert_eq!(res["status"], "ready");
+}
+
+#[actix_web::test]
+async fn metrics_count_logins_and_routes() {
+    let app = common::app().await;
+    let account = app.register().await;
+    app.login(&account).await;
+    let res = app.get("/metrics", None).await;
+    assert!(matches!(res.error(), Error::MissingToken));
+    let res = app.get("/metrics", Some("not-the-metrics-token")).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+
+    let metrics = app.get("/metrics", Some(common::METRICS_TOKEN)).await.ok();
+    let metrics = metrics.as_str().unwrap();
+    assert!(metrics.contains(r#"logins_total{method="password",outcome="success"}"#));
+    assert!(metrics.contains(r#"registrations_total{outcome="success"}"#));
+    assert!(metrics.contains(r#"route="/api/session""#));
+    assert!(metrics.contains(r#"pending_flows{flow="login"}"#));
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use account_services::errors::Error;
+use actix_web::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    test::TestRequest,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use common::{Response, TestApp, INTROSPECTION_CLIENT};
+
+fn introspection(token: &str, client: Option<(&str, &str)>) -> TestRequest {
+    let mut req = TestRequest::post()
+        .uri("/api/introspect")
+        .set_form([("token", token), ("token_type_hint", "access_token")]);
+    if let Some((id, secret)) = client {
+        let credentials = BASE64.encode(format!("{}:{}", id, secret));
+        req = req.insert_header(("Authorization", format!("Basic {}", credentials)));
+    }
+    req
+}
+
+async fn introspect<S, B>(app: &TestApp<S>, token: &str) -> Response
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+    B: actix_web::body::MessageBody,
+{
+    app.send(introspection(token, Some(INTROSPECTION_CLIENT)))
+        .await
+}
+
+#[actix_web::test]
+async fn resource_servers_must_authenticate() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app.send(introspection(&account.token, None)).await;
+    assert_eq!(res.headers.get(WWW_AUTHENTICATE).unwrap(), "Basic");
+    assert!(matches!(res.error(), Error::InvalidClient));
+    let res = app
+        .send(introspection(
+            &account.token,
+            Some((INTROSPECTION_CLIENT.0, "wrong")),
+        ))
+        .await;
+    assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn active_tokens_describe_their_session() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = introspect(&app, &account.token).await;
+    let cache = res.headers.get("cache-control").unwrap().to_str().unwrap();
+    assert!(cache.contains("private") && cache.contains("max-age=30"));
+    let body = res.ok();
+    assert_eq!(body["active"], true);
+    assert_eq!(body["client_id"], INTROSPECTION_CLIENT.0);
+    assert_eq!(body["sub"], account.id.as_str());
+    assert_eq!(body["username"], account.username.as_str());
+    assert_eq!(body["amr"][0], "pwd");
+    assert_eq!(body["escalated"], false);
+    assert!(body["sid"].is_string());
+    assert!(body["exp"].as_u64().unwrap() > body["iat"].as_u64().unwrap());
+
+    app.escalate(&account).await;
+    let body = introspect(&app, &account.token).await.ok();
+    assert_eq!(body["escalated"], true);
+    assert!(body["escalation_exp"].is_u64());
+}
+
+#[actix_web::test]
+async fn unusable_tokens_are_inactive() {
+    let app = common::app().await;
+    let res = introspect(&app, "not-a-token").await;
+    assert_eq!(res.headers.get("cache-control").unwrap(), "no-store");
+    assert_eq!(res.ok(), serde_json::json!({ "active": false }));
+
+    let account = app.register().await;
+    app.delete("/api/session", Some(&account.token), None)
+        .await
+        .ok();
+    let body = introspect(&app, &account.token).await.ok();
+    assert_eq!(body["active"], false);
+}
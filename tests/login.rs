@@ -0,0 +1,392 @@
+mod common;
+
+use account_services::{
+    constants::CONTINUE_TIMEOUT,
+    errors::Error,
+    opaque::{open_server_setup, rotate_server_setup, Legacy, PasswordSuite, Suite},
+    routes::login::PENDING_LOGINS,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64, Engine};
+use opaque_ke::{
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration,
+    ClientRegistrationFinishParameters, CredentialResponse, ServerRegistration, ServerSetup,
+};
+use rand::rngs::OsRng;
+use serde_json::json;
+
+#[actix_web::test]
+async fn login_creates_a_new_session() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let token = app.login(&account).await;
+    assert_ne!(token, account.token);
+    let user = app.get("/api/user", Some(&token)).await.ok();
+    assert_eq!(user["id"], account.id);
+    let sessions = app.get("/api/session", Some(&token)).await.ok();
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn login_ignores_the_case_of_the_email() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app
+        .login_with(&account.email.to_uppercase(), &account.password, None)
+        .await
+        .expect("wrong password")
+        .ok();
+    assert!(res["token"].is_string());
+}
+
+#[actix_web::test]
+async fn failed_logins_count_every_spelling_of_the_email() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let begin = |email: String| {
+        let (message, _) = common::start_login(&account.password);
+        json!({ "stage": "BEGIN_LOGIN", "email": email, "message": message, "escalate": false })
+    };
+    // CAPTCHA_LOGIN_THRESHOLD defaults to 5
+    for attempt in 0..5 {
+        let email = if attempt % 2 == 0 {
+            account.email.to_uppercase()
+        } else {
+            account.email.clone()
+        };
+        let res = app.post("/api/session", None, begin(email)).await.ok();
+        // a finalization the server can't verify, as a wrong password would send
+        app.post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "FINISH_LOGIN",
+                "message": BASE64.encode([0u8; 64]),
+                "continueToken": res["continueToken"],
+            }),
+        )
+        .await
+        .error();
+    }
+    let error = app
+        .post("/api/session", None, begin(account.email.clone()))
+        .await
+        .error();
+    assert!(matches!(error, Error::CaptchaRequired));
+}
+
+#[actix_web::test]
+async fn login_with_a_wrong_password_fails() {
+    let app = common::app().await;
+    let account = app.register().await;
+    assert!(app
+        .login_with(&account.email, "not the password", None)
+        .await
+        .is_none());
+}
+
+#[actix_web::test]
+async fn login_does_not_reveal_unknown_emails() {
+    let app = common::app().await;
+    // the server answers with a fake record, so the client fails exactly like a wrong password
+    assert!(app
+        .login_with("nobody@example.com", "password", None)
+        .await
+        .is_none());
+}
+
+#[actix_web::test]
+async fn login_rejects_a_finalization_for_another_attempt() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let begin = |message: String| {
+        json!({
+            "stage": "BEGIN_LOGIN",
+            "email": account.email,
+            "message": message,
+            "escalate": false,
+        })
+    };
+    let (message, first) = common::start_login(&account.password);
+    let first_res = app.post("/api/session", None, begin(message)).await.ok();
+    let (message, _) = common::start_login(&account.password);
+    let second_res = app.post("/api/session", None, begin(message)).await.ok();
+    let finalization = common::finish_login(
+        first,
+        &account.password,
+        first_res["message"].as_str().unwrap(),
+    )
+    .unwrap();
+    let error = app
+        .post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "FINISH_LOGIN",
+                "message": finalization,
+                "continueToken": second_res["continueToken"],
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::CredentialError));
+}
+
+#[actix_web::test]
+async fn login_rejects_an_expired_continue_token() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let (message, state) = common::start_login(&account.password);
+    let res = app
+        .post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "BEGIN_LOGIN",
+                "email": account.email,
+                "message": message,
+                "escalate": false,
+            }),
+        )
+        .await
+        .ok();
+    let continue_token = res["continueToken"].as_str().unwrap();
+    PENDING_LOGINS.get_mut(continue_token).unwrap().time -= CONTINUE_TIMEOUT + 1;
+    let finalization =
+        common::finish_login(state, &account.password, res["message"].as_str().unwrap()).unwrap();
+    let error = app
+        .post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "FINISH_LOGIN",
+                "message": finalization,
+                "continueToken": continue_token,
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn logout_invalidates_the_token() {
+    let app = common::app().await;
+    let account = app.register().await;
+    app.delete("/api/session", Some(&account.token), None)
+        .await
+        .ok();
+    let error = app.get("/api/user", Some(&account.token)).await.error();
+    assert!(matches!(error, Error::InvalidToken));
+}
+
+#[actix_web::test]
+async fn authenticated_routes_require_a_token() {
+    let app = common::app().await;
+    let error = app.get("/api/user", None).await.error();
+    assert!(matches!(error, Error::MissingToken));
+    let error = app.get("/api/user", Some("not-a-jwt")).await.error();
+    assert!(matches!(error, Error::InvalidToken));
+}
+
+#[actix_web::test]
+async fn validate_checks_tokens_and_escalations() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app
+        .post("/api/validate", None, json!({ "token": account.token }))
+        .await
+        .ok();
+    assert_eq!(res["escalated"], false);
+    let escalation = app.escalate(&account).await;
+    let res = app
+        .post(
+            "/api/validate",
+            None,
+            json!({ "token": account.token, "escalationToken": escalation }),
+        )
+        .await
+        .ok();
+    assert_eq!(res["escalated"], true);
+    app.delete("/api/session", Some(&account.token), None)
+        .await
+        .ok();
+    let error = app
+        .post("/api/validate", None, json!({ "token": account.token }))
+        .await
+        .error();
+    assert!(matches!(error, Error::InvalidToken));
+}
+
+#[actix_web::test]
+async fn legacy_passwords_are_upgraded_after_login() {
+    let app = common::app().await;
+    let account = app.register().await;
+    // store a password file from before suites were versioned
+    let settings = app.stores.settings.get().await.unwrap();
+    let server_setup = open_server_setup(&settings, settings.current_server_setup).unwrap();
+    let server_setup = ServerSetup::<Legacy>::deserialize(&server_setup).unwrap();
+    let start =
+        ClientRegistration::<Legacy>::start(&mut OsRng, account.password.as_bytes()).unwrap();
+    let response =
+        ServerRegistration::<Legacy>::start(&server_setup, start.message, account.email.as_bytes())
+            .unwrap();
+    let upload = start
+        .state
+        .finish(
+            &mut OsRng,
+            account.password.as_bytes(),
+            response.message,
+            ClientRegistrationFinishParameters::default(),
+        )
+        .unwrap();
+    let password_file = ServerRegistration::<Legacy>::finish(upload.message);
+    app.stores
+        .users
+        .set_password(
+            &account.id,
+            password_file.serialize().to_vec(),
+            PasswordSuite::legacy(),
+            false,
+        )
+        .await
+        .unwrap();
+
+    let login = ClientLogin::<Legacy>::start(&mut OsRng, account.password.as_bytes()).unwrap();
+    let res = app
+        .post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "BEGIN_LOGIN",
+                "email": account.email,
+                "message": BASE64.encode(login.message.serialize()),
+                "escalate": false,
+            }),
+        )
+        .await
+        .ok();
+    assert_eq!(res["suite"]["version"], 1);
+    let response =
+        CredentialResponse::deserialize(&BASE64.decode(res["message"].as_str().unwrap()).unwrap())
+            .unwrap();
+    let finalization = login
+        .state
+        .finish(
+            account.password.as_bytes(),
+            response,
+            ClientLoginFinishParameters::default(),
+        )
+        .unwrap();
+    let res = app
+        .post(
+            "/api/session",
+            None,
+            json!({
+                "stage": "FINISH_LOGIN",
+                "message": BASE64.encode(finalization.message.serialize()),
+                "continueToken": res["continueToken"],
+            }),
+        )
+        .await
+        .ok();
+    let suite = app
+        .upgrade(&account, res["upgradeToken"].as_str().unwrap())
+        .await;
+    assert_eq!(suite["version"], 2);
+    let user = app.stores.users.find(&account.id).await.unwrap().unwrap();
+    assert_eq!(user.password_suite.version, Suite::Argon2id);
+    // the same password keeps working, now with key stretching
+    app.login(&account).await;
+}
+
+#[actix_web::test]
+async fn current_passwords_are_not_upgraded() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .unwrap()
+        .ok();
+    assert!(res["upgradeToken"].is_null());
+}
+
+#[actix_web::test]
+async fn rotated_server_setups_keep_existing_passwords() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let mut settings = app.stores.settings.get().await.unwrap();
+    let rotated = rotate_server_setup(&mut settings);
+    app.stores.settings.set(settings).await.unwrap();
+
+    // the old setup still signs users in, and moves them to the new one
+    let res = app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .unwrap()
+        .ok();
+    let suite = app
+        .upgrade(&account, res["upgradeToken"].as_str().unwrap())
+        .await;
+    assert_eq!(suite["setup"], rotated);
+    let res = app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .unwrap()
+        .ok();
+    assert!(res["upgradeToken"].is_null());
+}
+
+#[actix_web::test]
+async fn upgrade_tokens_are_single_use() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let mut settings = app.stores.settings.get().await.unwrap();
+    rotate_server_setup(&mut settings);
+    app.stores.settings.set(settings).await.unwrap();
+    let res = app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .unwrap()
+        .ok();
+    let upgrade_token = res["upgradeToken"].as_str().unwrap();
+    app.upgrade(&account, upgrade_token).await;
+    let (message, _) = common::start_registration(&account.password);
+    let error = app
+        .post(
+            "/api/session",
+            None,
+            json!({ "stage": "BEGIN_UPGRADE", "continueToken": upgrade_token, "message": message }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn signing_out_ends_the_upgrade() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let mut settings = app.stores.settings.get().await.unwrap();
+    rotate_server_setup(&mut settings);
+    app.stores.settings.set(settings).await.unwrap();
+    let res = app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .unwrap()
+        .ok();
+    let upgrade_token = res["upgradeToken"].as_str().unwrap();
+    app.delete("/api/session", res["token"].as_str(), None)
+        .await
+        .ok();
+    let (message, _) = common::start_registration(&account.password);
+    let error = app
+        .post(
+            "/api/session",
+            None,
+            json!({ "stage": "BEGIN_UPGRADE", "continueToken": upgrade_token, "message": message }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
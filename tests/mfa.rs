@@ -0,0 +1,178 @@
+mod common;
+
+use account_services::errors::Error;
+use serde_json::json;
+
+#[actix_web::test]
+async fn login_requires_the_second_factor() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let setup = app.enable_mfa(&account).await;
+    let user = app.get("/api/user", Some(&account.token)).await.ok();
+    assert_eq!(user["mfaEnabled"], true);
+
+    let error = app.login_mfa(&account, "00000000").await.error();
+    assert!(matches!(error, Error::IncorrectCode));
+    let code = common::totp_code(setup["secret"].as_str().unwrap(), &account.email).await;
+    let res = app.login_mfa(&account, &code).await.ok();
+    app.get("/api/user", Some(res["token"].as_str().unwrap()))
+        .await
+        .ok();
+}
+
+#[actix_web::test]
+async fn recovery_codes_work_once() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let setup = app.enable_mfa(&account).await;
+    let code = setup["codes"][0].as_str().unwrap();
+    app.login_mfa(&account, code).await.ok();
+    let error = app.login_mfa(&account, code).await.error();
+    assert!(matches!(error, Error::IncorrectCode));
+    app.login_mfa(&account, setup["codes"][1].as_str().unwrap())
+        .await
+        .ok();
+}
+
+#[actix_web::test]
+async fn setup_requires_a_correct_code() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let setup = app.begin_mfa_setup(&account).await;
+    let error = app
+        .patch(
+            "/api/user/mfa",
+            Some(&account.token),
+            json!({
+                "stage": "ENABLE_VERIFY",
+                "code": "00000000",
+                "continueToken": setup["continueToken"],
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::IncorrectCode));
+    let user = app.get("/api/user", Some(&account.token)).await.ok();
+    assert_eq!(user["mfaEnabled"], false);
+}
+
+#[actix_web::test]
+async fn setup_cannot_be_finished_by_another_user() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    let setup = app.begin_mfa_setup(&account).await;
+    let code = common::totp_code(setup["secret"].as_str().unwrap(), &account.username).await;
+    let error = app
+        .patch(
+            "/api/user/mfa",
+            Some(&other.token),
+            json!({
+                "stage": "ENABLE_VERIFY",
+                "code": code,
+                "continueToken": setup["continueToken"],
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::UserMismatch));
+    let user = app.get("/api/user", Some(&account.token)).await.ok();
+    assert_eq!(user["mfaEnabled"], false);
+}
+
+#[actix_web::test]
+async fn disabling_mfa_restores_password_logins() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let setup = app.enable_mfa(&account).await;
+    // escalating an MFA account takes the second factor as well
+    let res = app
+        .login_with(&account.email, &account.password, Some(&account.token))
+        .await
+        .unwrap()
+        .ok();
+    let code = common::totp_code(setup["secret"].as_str().unwrap(), &account.email).await;
+    let res = app
+        .post(
+            "/api/session",
+            None,
+            json!({ "stage": "MFA", "code": code, "continueToken": res["continueToken"] }),
+        )
+        .await
+        .ok();
+    app.patch(
+        "/api/user/mfa",
+        Some(&account.token),
+        json!({ "stage": "TOGGLE", "escalationToken": res["token"] }),
+    )
+    .await
+    .ok();
+    // logins go straight through again
+    app.login(&account).await;
+    let user = app.get("/api/user", Some(&account.token)).await.ok();
+    assert_eq!(user["mfaEnabled"], false);
+}
+
+#[actix_web::test]
+async fn forgot_password_requires_the_second_factor() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let setup = app.enable_mfa(&account).await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": account.email }),
+    )
+    .await
+    .ok();
+    let continue_token = common::reset_token(&account.email).unwrap();
+    let res = app
+        .post(
+            "/api/forgot",
+            None,
+            json!({ "stage": "BEGIN_VERIFICATION", "continueToken": continue_token }),
+        )
+        .await
+        .ok();
+    assert_eq!(res["secondFactorRequired"], true);
+    assert_eq!(res["mfaEnabled"], true);
+
+    let reset = |message: String| {
+        json!({
+            "stage": "RESET_PASSWORD",
+            "continueToken": continue_token,
+            "message": message,
+        })
+    };
+    let (message, _) = common::start_registration("a brand new password");
+    let error = app.post("/api/forgot", None, reset(message)).await.error();
+    assert!(matches!(error, Error::SecondFactorRequired));
+
+    let error = app
+        .post(
+            "/api/forgot",
+            None,
+            json!({
+                "stage": "VERIFY_SECOND_FACTOR",
+                "continueToken": continue_token,
+                "code": "00000000",
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::IncorrectCode));
+    let code = common::totp_code(setup["secret"].as_str().unwrap(), &account.email).await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({
+            "stage": "VERIFY_SECOND_FACTOR",
+            "continueToken": continue_token,
+            "code": code,
+        }),
+    )
+    .await
+    .ok();
+    let (message, _) = common::start_registration("a brand new password");
+    app.post("/api/forgot", None, reset(message)).await.ok();
+}
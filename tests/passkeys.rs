@@ -0,0 +1,142 @@
+mod common;
+
+use account_services::errors::Error;
+use serde_json::json;
+use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+
+fn authenticator() -> WebauthnAuthenticator<SoftPasskey> {
+    WebauthnAuthenticator::new(SoftPasskey::new(true))
+}
+
+#[actix_web::test]
+async fn passkeys_can_sign_in() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let mut authenticator = authenticator();
+    let credential_id = app.add_passkey(&account, &mut authenticator).await;
+    let passkeys = app
+        .get("/api/user/passkeys", Some(&account.token))
+        .await
+        .ok();
+    assert_eq!(passkeys.as_array().unwrap().len(), 1);
+    assert_eq!(passkeys[0]["friendlyName"], "Test key");
+
+    let res = app
+        .login_passkey(&mut authenticator, &credential_id)
+        .await
+        .ok();
+    let user = app
+        .get("/api/user", Some(res["token"].as_str().unwrap()))
+        .await
+        .ok();
+    assert_eq!(user["id"], account.id);
+}
+
+#[actix_web::test]
+async fn passkey_registration_requires_an_escalation() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let error = app
+        .post(
+            "/api/user/passkeys",
+            Some(&account.token),
+            json!({ "stage": "BEGIN_REGISTER", "escalationToken": "not-an-escalation" }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn passkey_registration_cannot_be_finished_by_another_user() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    let escalation = app.escalate(&account).await;
+    let res = app
+        .post(
+            "/api/user/passkeys",
+            Some(&account.token),
+            json!({ "stage": "BEGIN_REGISTER", "escalationToken": escalation }),
+        )
+        .await
+        .ok();
+    let credential = authenticator()
+        .do_registration(
+            common::PUBLIC_ROOT.parse().unwrap(),
+            serde_json::from_value(res["message"].clone()).unwrap(),
+        )
+        .unwrap();
+    let error = app
+        .post(
+            "/api/user/passkeys",
+            Some(&other.token),
+            json!({
+                "stage": "FINISH_REGISTER",
+                "message": credential,
+                "continueToken": res["continueToken"],
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::UserMismatch));
+    let passkeys = app
+        .get("/api/user/passkeys", Some(&account.token))
+        .await
+        .ok();
+    assert!(passkeys.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn passkeys_cannot_be_deleted_by_another_user() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    app.add_passkey(&account, &mut authenticator()).await;
+    let passkeys = app
+        .get("/api/user/passkeys", Some(&account.token))
+        .await
+        .ok();
+    let passkey_id = passkeys[0]["id"].as_str().unwrap();
+
+    let escalation = app.escalate(&other).await;
+    app.delete(
+        &format!("/api/user/passkeys/{}", passkey_id),
+        Some(&other.token),
+        Some(json!({ "escalationToken": escalation })),
+    )
+    .await
+    .ok();
+    let passkeys = app
+        .get("/api/user/passkeys", Some(&account.token))
+        .await
+        .ok();
+    assert_eq!(passkeys.as_array().unwrap().len(), 1);
+    let passkeys = app.get("/api/user/passkeys", Some(&other.token)).await.ok();
+    assert!(passkeys.as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn deleted_passkeys_cannot_sign_in() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let mut authenticator = authenticator();
+    let credential_id = app.add_passkey(&account, &mut authenticator).await;
+    let passkeys = app
+        .get("/api/user/passkeys", Some(&account.token))
+        .await
+        .ok();
+    let escalation = app.escalate(&account).await;
+    app.delete(
+        &format!("/api/user/passkeys/{}", passkeys[0]["id"].as_str().unwrap()),
+        Some(&account.token),
+        Some(json!({ "escalationToken": escalation })),
+    )
+    .await
+    .ok();
+    let error = app
+        .login_passkey(&mut authenticator, &credential_id)
+        .await
+        .error();
+    assert!(matches!(error, Error::CredentialError));
+}
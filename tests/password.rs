@@ -0,0 +1,318 @@
+mod common;
+
+use account_services::{
+    constants::CONTINUE_TIMEOUT,
+    database::{recovery::RecoveryToken, reset_request::ResetRequest},
+    errors::Error,
+    routes::{forgot::PENDING_FORGOTS1, login::ACTIVE_ESCALATIONS},
+    utilities::get_time_secs,
+};
+use serde_json::json;
+
+#[actix_web::test]
+async fn update_password_replaces_the_password() {
+    let app = common::app().await;
+    let mut account = app.register().await;
+    let escalation = app.escalate(&account).await;
+    let password = "a brand new password";
+    let (message, state) = common::start_registration(password);
+    let res = app
+        .patch(
+            "/api/user/password",
+            Some(&account.token),
+            json!({
+                "stage": "BEGIN_UPDATE",
+                "escalationToken": escalation,
+                "message": message,
+            }),
+        )
+        .await
+        .ok();
+    let message = common::finish_registration(state, password, res["message"].as_str().unwrap());
+    app.patch(
+        "/api/user/password",
+        Some(&account.token),
+        json!({
+            "stage": "FINISH_UPDATE",
+            "continueToken": res["continueToken"],
+            "message": message,
+        }),
+    )
+    .await
+    .ok();
+    assert!(app
+        .login_with(&account.email, &account.password, None)
+        .await
+        .is_none());
+    account.password = password.to_string();
+    app.login(&account).await;
+    // the session that changed the password stays valid
+    app.get("/api/user", Some(&account.token)).await.ok();
+}
+
+#[actix_web::test]
+async fn update_password_requires_an_escalation() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let (message, _) = common::start_registration("a brand new password");
+    let error = app
+        .patch(
+            "/api/user/password",
+            Some(&account.token),
+            json!({
+                "stage": "BEGIN_UPDATE",
+                "escalationToken": "not-an-escalation",
+                "message": message,
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn escalations_expire() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let escalation = app.escalate(&account).await;
+    ACTIVE_ESCALATIONS.get_mut(&escalation).unwrap().time -= 3601;
+    let (message, _) = common::start_registration("a brand new password");
+    let error = app
+        .patch(
+            "/api/user/password",
+            Some(&account.token),
+            json!({
+                "stage": "BEGIN_UPDATE",
+                "escalationToken": escalation,
+                "message": message,
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn escalations_are_bound_to_their_session() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    let escalation = app.escalate(&other).await;
+    let (message, _) = common::start_registration("a brand new password");
+    let error = app
+        .patch(
+            "/api/user/password",
+            Some(&account.token),
+            json!({
+                "stage": "BEGIN_UPDATE",
+                "escalationToken": escalation,
+                "message": message,
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+    // escalating needs the password of the account behind the session
+    let error = app
+        .login_with(&other.email, &other.password, Some(&account.token))
+        .await
+        .unwrap()
+        .error();
+    assert!(matches!(error, Error::UserMismatch));
+}
+
+#[actix_web::test]
+async fn forgot_password_resets_and_revokes_sessions() {
+    let app = common::app().await;
+    let mut account = app.register().await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": account.email }),
+    )
+    .await
+    .ok();
+    let continue_token = common::reset_token(&account.email).unwrap();
+    let res = app
+        .post(
+            "/api/forgot",
+            None,
+            json!({ "stage": "BEGIN_VERIFICATION", "continueToken": continue_token }),
+        )
+        .await
+        .ok();
+    assert_eq!(res["secondFactorRequired"], false);
+    let password = "a brand new password";
+    let (message, state) = common::start_registration(password);
+    let res = app
+        .post(
+            "/api/forgot",
+            None,
+            json!({
+                "stage": "RESET_PASSWORD",
+                "continueToken": continue_token,
+                "message": message,
+            }),
+        )
+        .await
+        .ok();
+    let message = common::finish_registration(state, password, res["message"].as_str().unwrap());
+    app.post(
+        "/api/forgot",
+        None,
+        json!({
+            "stage": "FINISH_RESET",
+            "continueToken": res["continueToken"],
+            "message": message,
+        }),
+    )
+    .await
+    .ok();
+    let error = app.get("/api/user", Some(&account.token)).await.error();
+    assert!(matches!(error, Error::InvalidToken));
+    account.password = password.to_string();
+    app.login(&account).await;
+}
+
+#[actix_web::test]
+async fn forgot_password_uses_the_stored_email() {
+    let app = common::app().await;
+    let mut account = app.register().await;
+    let password = "a brand new password";
+    app.reset_password(&account.email.to_uppercase(), password)
+        .await;
+    account.password = password.to_string();
+    app.login(&account).await;
+    app.login_with(&account.email.to_uppercase(), password, None)
+        .await
+        .expect("wrong password")
+        .ok();
+}
+
+#[actix_web::test]
+async fn forgot_password_tokens_expire() {
+    let app = common::app().await;
+    let account = app.register().await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": account.email }),
+    )
+    .await
+    .ok();
+    let continue_token = common::reset_token(&account.email).unwrap();
+    PENDING_FORGOTS1.get_mut(&continue_token).unwrap().time -= CONTINUE_TIMEOUT + 1;
+    let (message, _) = common::start_registration("a brand new password");
+    let error = app
+        .post(
+            "/api/forgot",
+            None,
+            json!({
+                "stage": "RESET_PASSWORD",
+                "continueToken": continue_token,
+                "message": message,
+            }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn changing_the_password_cancels_pending_resets() {
+    let app = common::app().await;
+    let account = app.register().await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": account.email }),
+    )
+    .await
+    .ok();
+    let continue_token = common::reset_token(&account.email).unwrap();
+    let escalation = app.escalate(&account).await;
+    let password = "a brand new password";
+    let (message, state) = common::start_registration(password);
+    let res = app
+        .patch(
+            "/api/user/password",
+            Some(&account.token),
+            json!({
+                "stage": "BEGIN_UPDATE",
+                "escalationToken": escalation,
+                "message": message,
+            }),
+        )
+        .await
+        .ok();
+    let message = common::finish_registration(state, password, res["message"].as_str().unwrap());
+    app.patch(
+        "/api/user/password",
+        Some(&account.token),
+        json!({
+            "stage": "FINISH_UPDATE",
+            "continueToken": res["continueToken"],
+            "message": message,
+        }),
+    )
+    .await
+    .ok();
+    assert!(PENDING_FORGOTS1.get(&continue_token).is_none());
+}
+
+#[actix_web::test]
+async fn forgot_password_does_not_reveal_unknown_emails() {
+    let app = common::app().await;
+    app.post(
+        "/api/forgot",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": "nobody@example.com" }),
+    )
+    .await
+    .ok();
+    assert!(common::reset_token("nobody@example.com").is_none());
+}
+
+#[actix_web::test]
+async fn recovery_signs_out_and_cancels_delayed_resets() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let now = get_time_secs();
+    app.stores
+        .recovery_tokens
+        .create(RecoveryToken {
+            token: "a-recovery-token".to_string(),
+            user_id: account.id.clone(),
+            created_at: now,
+        })
+        .await
+        .unwrap();
+    app.stores
+        .reset_requests
+        .create(ResetRequest {
+            token: "an-attackers-reset".to_string(),
+            user_id: account.id.clone(),
+            email: account.email.clone(),
+            created_at: now,
+            available_at: now,
+        })
+        .await
+        .unwrap();
+    app.post("/api/recover", None, json!({ "token": "a-recovery-token" }))
+        .await
+        .ok();
+    app.get("/api/user", Some(&account.token)).await.error();
+    assert!(app
+        .stores
+        .reset_requests
+        .find("an-attackers-reset")
+        .await
+        .unwrap()
+        .is_none());
+    // the token only works once
+    let error = app
+        .post("/api/recover", None, json!({ "token": "a-recovery-token" }))
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
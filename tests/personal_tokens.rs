@@ -0,0 +1,170 @@
+mod common;
+
+use account_services::errors::Error;
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn creating_a_token_needs_a_recent_login() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let body = |escalation: &str, scopes: Value, days: u64| {
+        json!({
+            "name": "CI",
+            "scopes": scopes,
+            "expiresInDays": days,
+            "escalationToken": escalation,
+        })
+    };
+    let res = app
+        .post(
+            "/api/user/tokens",
+            Some(&account.token),
+            body("expired", json!(["profile:read"]), 30),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::SessionExpired));
+
+    let escalation = app.escalate(&account).await;
+    let res = app
+        .post(
+            "/api/user/tokens",
+            Some(&account.token),
+            body(&escalation, json!(["admin"]), 30),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::InvalidScope));
+    let res = app
+        .post(
+            "/api/user/tokens",
+            Some(&account.token),
+            body(&escalation, json!(["profile:read"]), 1000),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::InvalidExpiry));
+}
+
+#[actix_web::test]
+async fn tokens_only_reach_routes_in_their_scopes() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let escalation = app.escalate(&account).await;
+    let created = app
+        .post(
+            "/api/user/tokens",
+            Some(&account.token),
+            json!({
+                "name": "CI",
+                "scopes": ["profile:read"],
+                "expiresInDays": 30,
+                "escalationToken": escalation,
+            }),
+        )
+        .await
+        .ok();
+    let token = created["token"].as_str().unwrap();
+    assert!(token.starts_with("nxp_"));
+
+    let user = app.get("/api/user", Some(token)).await.ok();
+    assert_eq!(user["id"], account.id.as_str());
+    let res = app
+        .patch(
+            "/api/user/profile",
+            Some(token),
+            json!({ "displayName": "Scripted" }),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+    // tokens can't manage tokens
+    let res = app.get("/api/user/tokens", Some(token)).await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+
+    let tokens = app.get("/api/user/tokens", Some(&account.token)).await.ok();
+    assert_eq!(tokens[0]["id"], created["id"]);
+    assert!(tokens[0]["lastUsedAt"].is_u64());
+    assert!(tokens[0].get("tokenHash").is_none());
+    let events = app
+        .get("/api/user/security-events", Some(&account.token))
+        .await
+        .ok();
+    assert_eq!(events["events"][0]["kind"], "PERSONAL_TOKEN_CREATED");
+
+    app.delete(
+        &format!("/api/user/tokens/{}", created["id"].as_str().unwrap()),
+        Some(&account.token),
+        None,
+    )
+    .await
+    .ok();
+    let res = app.get("/api/user", Some(token)).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+}
+
+#[actix_web::test]
+async fn denied_requests_do_not_count_as_use() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let escalation = app.escalate(&account).await;
+    let created = app
+        .post(
+            "/api/user/tokens",
+            Some(&account.token),
+            json!({
+                "name": "CI",
+                "scopes": ["profile:read"],
+                "expiresInDays": 30,
+                "escalationToken": escalation,
+            }),
+        )
+        .await
+        .ok();
+    let res = app.get("/api/user/tokens", created["token"].as_str()).await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+    let tokens = app.get("/api/user/tokens", Some(&account.token)).await.ok();
+    assert!(tokens[0]["lastUsedAt"].is_null());
+}
+
+#[actix_web::test]
+async fn signing_out_everywhere_revokes_tokens() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let create = |escalation: String| {
+        json!({
+            "name": "CI",
+            "scopes": ["profile:read"],
+            "expiresInDays": 30,
+            "escalationToken": escalation,
+        })
+    };
+    let escalation = app.escalate(&account).await;
+    let created = app
+        .post("/api/user/tokens", Some(&account.token), create(escalation))
+        .await
+        .ok();
+    let token = created["token"].as_str().unwrap().to_string();
+
+    app.delete("/api/session/all", Some(&account.token), None)
+        .await
+        .ok();
+    let res = app.get("/api/user", Some(&token)).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+    let events = app
+        .get("/api/user/security-events", Some(&account.token))
+        .await
+        .ok();
+    assert!(events["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|event| event["kind"] == "PERSONAL_TOKENS_REVOKED"));
+
+    let escalation = app.escalate(&account).await;
+    let created = app
+        .post("/api/user/tokens", Some(&account.token), create(escalation))
+        .await
+        .ok();
+    let token = created["token"].as_str().unwrap().to_string();
+    app.reset_password(&account.email, "a new correct horse battery")
+        .await;
+    let res = app.get("/api/user", Some(&token)).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+}
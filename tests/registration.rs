@@ -0,0 +1,151 @@
+mod common;
+
+use account_services::{
+    constants::VERIFY_TIMEOUT, errors::Error, routes::register::PENDING_REGISTERS1,
+};
+use serde_json::json;
+use ulid::Ulid;
+
+fn new_email() -> String {
+    format!("{}@example.com", Ulid::new().to_string().to_lowercase())
+}
+
+#[actix_web::test]
+async fn register_creates_a_session() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let user = app.get("/api/user", Some(&account.token)).await.ok();
+    assert_eq!(user["email"], account.email);
+    assert_eq!(user["username"], account.username);
+    assert_eq!(user["displayName"], "Test User");
+    assert_eq!(user["emailVerified"], true);
+    assert_eq!(user["mfaEnabled"], false);
+}
+
+#[actix_web::test]
+async fn register_requires_a_captcha() {
+    let app = common::app().await;
+    let email = new_email();
+    let error = app
+        .post(
+            "/api/user",
+            None,
+            json!({ "stage": "VERIFY_EMAIL", "email": email }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::CaptchaRequired));
+    let error = app
+        .post(
+            "/api/user",
+            None,
+            json!({ "stage": "VERIFY_EMAIL", "email": email, "captchaToken": "fail" }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::InvalidCaptcha));
+    assert!(common::email_token(&email).is_none());
+}
+
+#[actix_web::test]
+async fn register_rejects_an_expired_email_token() {
+    let app = common::app().await;
+    let email = new_email();
+    app.post(
+        "/api/user",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": email, "captchaToken": "pass" }),
+    )
+    .await
+    .ok();
+    let token = common::email_token(&email).unwrap();
+    PENDING_REGISTERS1.get_mut(&token).unwrap().time -= VERIFY_TIMEOUT + 1;
+    let (message, _) = common::start_registration("password");
+    let error = app
+        .post(
+            "/api/user",
+            None,
+            json!({ "stage": "BEGIN_REGISTRATION", "emailToken": token, "message": message }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+    assert!(!PENDING_REGISTERS1.contains_key(&token));
+}
+
+#[actix_web::test]
+async fn register_rejects_an_unknown_email_token() {
+    let app = common::app().await;
+    let (message, _) = common::start_registration("password");
+    let error = app
+        .post(
+            "/api/user",
+            None,
+            json!({ "stage": "BEGIN_REGISTRATION", "emailToken": "12345678", "message": message }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::SessionExpired));
+}
+
+#[actix_web::test]
+async fn register_does_not_reveal_existing_emails() {
+    let app = common::app().await;
+    let account = app.register().await;
+    app.post(
+        "/api/user",
+        None,
+        json!({ "stage": "VERIFY_EMAIL", "email": account.email, "captchaToken": "pass" }),
+    )
+    .await
+    .ok();
+    // the owner gets an email saying the address is in use, and nobody gets a token
+    assert!(common::email_token(&account.email).is_none());
+}
+
+#[actix_web::test]
+async fn register_rejects_a_taken_username() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let error = app
+        .register_with(&new_email(), &account.username.to_uppercase(), "password")
+        .await
+        .error();
+    assert!(matches!(error, Error::UsernameAlreadyTaken));
+}
+
+#[actix_web::test]
+async fn register_rejects_a_look_alike_username() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let look_alike = account
+        .username
+        .replacen("user_", "USER.", 1)
+        .replace('0', "O")
+        .replace('1', "I");
+    let error = app
+        .register_with(&new_email(), &look_alike, "password")
+        .await
+        .error();
+    assert!(matches!(error, Error::UsernameAlreadyTaken));
+}
+
+#[actix_web::test]
+async fn register_rejects_a_look_alike_of_a_reserved_username() {
+    let app = common::app().await;
+    let error = app
+        .register_with(&new_email(), "Adrn1n", "password")
+        .await
+        .error();
+    assert!(matches!(error, Error::UsernameReserved));
+}
+
+#[actix_web::test]
+async fn register_rejects_an_invalid_username() {
+    let app = common::app().await;
+    let error = app
+        .register_with(&new_email(), "no spaces allowed", "password")
+        .await
+        .error();
+    assert!(matches!(error, Error::InvalidUsername));
+}
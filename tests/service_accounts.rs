@@ -0,0 +1,139 @@
+mod common;
+
+use account_services::errors::Error;
+use actix_web::test::TestRequest;
+use serde_json::{json, Value};
+use ulid::Ulid;
+
+fn name() -> String {
+    format!("service-{}", Ulid::new().to_string().to_lowercase())
+}
+
+#[actix_web::test]
+async fn only_administrators_manage_service_accounts() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let res = app
+        .post(
+            "/api/admin/service-accounts",
+            Some(&account.token),
+            json!({ "name": name(), "scopes": [] }),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+
+    app.stores
+        .users
+        .set_administrator(&account.id, true)
+        .await
+        .unwrap();
+    let res = app
+        .post(
+            "/api/admin/service-accounts",
+            Some(&account.token),
+            json!({ "name": name(), "scopes": ["everything"] }),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::InvalidScope));
+
+    let name = name();
+    let body = json!({ "name": name, "scopes": [] });
+    app.post(
+        "/api/admin/service-accounts",
+        Some(&account.token),
+        body.clone(),
+    )
+    .await
+    .ok();
+    let res = app
+        .post("/api/admin/service-accounts", Some(&account.token), body)
+        .await;
+    assert!(matches!(res.error(), Error::ServiceAccountExists));
+}
+
+#[actix_web::test]
+async fn api_keys_are_limited_to_their_scopes() {
+    let app = common::app().await;
+    let admin = app.register().await;
+    app.stores
+        .users
+        .set_administrator(&admin.id, true)
+        .await
+        .unwrap();
+    let created = app
+        .post(
+            "/api/admin/service-accounts",
+            Some(&admin.token),
+            json!({ "name": name(), "scopes": ["users:read"] }),
+        )
+        .await
+        .ok();
+    let key = created["key"].as_str().unwrap();
+    let id = created["serviceAccount"]["id"].as_str().unwrap();
+    assert!(key.starts_with("nxs_"));
+
+    let user = app
+        .get(&format!("/api/user/{}", admin.id), Some(key))
+        .await
+        .ok();
+    assert_eq!(user["username"], admin.username.as_str());
+    // user routes never accept a service account
+    let res = app.get("/api/user", Some(key)).await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+    let res = app
+        .send(
+            TestRequest::post()
+                .uri("/api/introspect")
+                .insert_header(("Authorization", format!("Bearer {}", key)))
+                .set_form([("token", admin.token.as_str())]),
+        )
+        .await;
+    assert!(matches!(res.error(), Error::MissingPermission));
+
+    let listed = app
+        .get("/api/admin/service-accounts", Some(&admin.token))
+        .await
+        .ok();
+    let listed: Vec<&Value> = listed
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|account| account["id"] == id)
+        .collect();
+    assert!(listed[0]["lastUsedAt"].is_u64());
+    assert!(listed[0].get("keyHash").is_none());
+
+    let updated = app
+        .patch(
+            &format!("/api/admin/service-accounts/{}", id),
+            Some(&admin.token),
+            json!({ "scopes": ["introspect"] }),
+        )
+        .await
+        .ok();
+    assert_eq!(updated["scopes"], json!(["introspect"]));
+    let introspection = app
+        .send(
+            TestRequest::post()
+                .uri("/api/introspect")
+                .insert_header(("Authorization", format!("Bearer {}", key)))
+                .set_form([("token", admin.token.as_str())]),
+        )
+        .await
+        .ok();
+    assert_eq!(introspection["active"], true);
+    assert_eq!(
+        introspection["client_id"],
+        created["serviceAccount"]["name"]
+    );
+
+    app.delete(
+        &format!("/api/admin/service-accounts/{}", id),
+        Some(&admin.token),
+        None,
+    )
+    .await
+    .ok();
+    let res = app.get(&format!("/api/user/{}", admin.id), Some(key)).await;
+    assert!(matches!(res.error(), Error::InvalidToken));
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use account_services::{
+    authenticate::key_id, database::session::Session, errors::Error, utilities::get_time_millis,
+};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde_json::json;
+
+#[actix_web::test]
+async fn sessions_cannot_be_revoked_by_another_user() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    let sessions = app.get("/api/session", Some(&account.token)).await.ok();
+    let session_id = sessions[0]["id"].as_str().unwrap();
+    app.delete(
+        &format!("/api/session/{}", session_id),
+        Some(&other.token),
+        None,
+    )
+    .await
+    .ok();
+    app.get("/api/user", Some(&account.token)).await.ok();
+    let sessions = app.get("/api/session", Some(&account.token)).await.ok();
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn other_sessions_can_be_revoked() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let second = app.login(&account).await;
+    let sessions = app.get("/api/session", Some(&account.token)).await.ok();
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+    let first = app
+        .stores
+        .sessions
+        .find_by_token(&account.token)
+        .await
+        .unwrap()
+        .unwrap();
+    app.delete(&format!("/api/session/{}", first.id), Some(&second), None)
+        .await
+        .ok();
+    let error = app.get("/api/user", Some(&account.token)).await.error();
+    assert!(matches!(error, Error::InvalidToken));
+    app.get("/api/user", Some(&second)).await.ok();
+}
+
+#[actix_web::test]
+async fn logout_all_keeps_the_current_session() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let second = app.login(&account).await;
+    let third = app.login(&account).await;
+    app.delete("/api/session/all", Some(&second), None)
+        .await
+        .ok();
+    app.get("/api/user", Some(&second)).await.ok();
+    for token in [&account.token, &third] {
+        let error = app.get("/api/user", Some(token)).await.error();
+        assert!(matches!(error, Error::InvalidToken));
+    }
+}
+
+#[actix_web::test]
+async fn profiles_are_visible_to_other_users() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    app.patch(
+        "/api/user/profile",
+        Some(&account.token),
+        json!({ "displayName": "  Renamed  ", "description": "Hello" }),
+    )
+    .await
+    .ok();
+    let user = app
+        .get(&format!("/api/user/{}", account.id), Some(&other.token))
+        .await
+        .ok();
+    assert_eq!(user["displayName"], "Renamed");
+    assert_eq!(user["description"], "Hello");
+    // profiles only expose public fields
+    assert!(user.get("email").is_none());
+    let user = app
+        .get(
+            &format!("/api/user/by-username/{}", account.username),
+            Some(&other.token),
+        )
+        .await
+        .ok();
+    assert_eq!(user["id"], account.id);
+}
+
+#[actix_web::test]
+async fn administration_requires_an_administrator() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let other = app.register().await;
+    let error = app
+        .get("/api/admin/registrations", Some(&account.token))
+        .await
+        .error();
+    assert!(matches!(error, Error::MissingPermission));
+    let error = app
+        .get(
+            &format!("/api/admin/users/{}/security-events", other.id),
+            Some(&account.token),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::MissingPermission));
+    let error = app
+        .post(
+            &format!("/api/admin/registrations/{}", other.id),
+            Some(&account.token),
+            json!({ "approve": false }),
+        )
+        .await
+        .error();
+    assert!(matches!(error, Error::MissingPermission));
+    app.get("/api/user", Some(&other.token)).await.ok();
+}
+
+#[actix_web::test]
+async fn tokens_signed_with_a_previous_secret_stay_valid() {
+    let app = common::app().await;
+    let account = app.register().await;
+    let sign = |secret: &str| {
+        let header = Header {
+            kid: Some(key_id(secret)),
+            ..Header::default()
+        };
+        let now = get_time_millis() as u64;
+        let claims = json!({ "id": account.id, "issued_at": now, "expires_at": now + 60_000 });
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    };
+    for (secret, valid) in [
+        (common::PREVIOUS_JWT_SECRET, true),
+        ("a-secret-this-server-never-used-32", false),
+    ] {
+        let token = sign(secret);
+        app.stores
+            .sessions
+            .create(Session {
+                id: ulid::Ulid::new().to_string(),
+                token: token.clone(),
+                friendly_name: "Rotated".to_string(),
+                user_id: account.id.clone(),
+                auth_methods: Vec::new(),
+            })
+            .await
+            .unwrap();
+        let res = app.get("/api/user", Some(&token)).await;
+        assert_eq!(res.status.is_success(), valid);
+    }
+}